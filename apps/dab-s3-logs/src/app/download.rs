@@ -1,73 +1,124 @@
-use aws_sdk_s3::{types::Object, Client};
+use aws_sdk_s3::types::Object;
 use indicatif::{ProgressBar, ProgressStyle};
 use tokio::sync::mpsc;
 use anyhow::Result;
-use std::fs;
-use aws::s3::{download_file, Query};
+use log::error as log_error;
+use std::cmp::Reverse;
+use aws::s3::{download::download_object, store::ObjectStore, Query};
 
-use super::App;
+use crate::storage::{index::{DownloadIndex, IndexEntry}, workspace::Workspace};
+
+use super::{scheduler::{self, ConcurrencyHandle, RunningFetch}, App};
 
 const PROGRESS_BAR_TEMPLATE: &str = "[{elapsed_precise}] {bar:40.cyan/blue} {bytes}/{total_bytes} {msg}";
 
-fn create_batches_from_query(query: &Query, app: &App) -> Vec<Vec<Object>> {
-  let num_downloads = query.objects.len();
-  let download_thread_concurrency = {
-    let cfg_clone = app.config.lock().unwrap().clone().unwrap();
-    cfg_clone.download_thread_concurrency
-  };
+/// Objects that were downloaded and objects that failed after every retry
+#[derive(Debug, Default)]
+pub struct DownloadReport {
+  pub files: Vec<String>,
+  pub failures: Vec<DownloadFailure>,
+}
 
-  let downloads_per_thread = num_downloads / download_thread_concurrency;
-  let objects: Vec<Object> = query.objects.clone().into_values().collect();
-  objects.chunks(downloads_per_thread).map(|x| x.to_vec()).collect::<Vec<Vec<Object>>>()
+/// An object that couldn't be downloaded
+#[derive(Debug, Clone)]
+pub struct DownloadFailure {
+  pub key: String,
+  pub reason: String,
+  pub attempts: u32,
 }
 
-pub async fn download_query_results(query: &Query, bucket: String,  app: &App, client: &Client) -> Result<Vec<String>> {
+/// Download every object in the query into the workspace, recording each completed download in the index.
+///
+/// Objects are downloaded largest first, `concurrency` at a time, with large objects split into
+/// concurrent byte ranges. The limit follows the concurrency file, so the `concurrency` command can
+/// change it mid fetch. Each object is verified before it's renamed into place, and its MD5 is kept
+/// in the index for `verify`. Retryable errors are retried with jittered backoff. Objects that
+/// still fail are reported rather than stopping the rest of the download.
+pub async fn download_query_results<C: ObjectStore>(query: &Query, bucket: String, app: &App, client: &C, index: &mut DownloadIndex, workspace: &Workspace, concurrency: usize) -> Result<DownloadReport> {
   let progress_bar = ProgressBar::new(query.size);
   progress_bar.set_style(ProgressStyle::with_template(PROGRESS_BAR_TEMPLATE).unwrap());
 
-  let download_dir = {
+  let (download_options, data_directory) = {
     let cfg_binding = app.config.lock().unwrap();
-    cfg_binding.clone().unwrap().download_directory
+    let cfg = cfg_binding.as_ref().unwrap();
+    (cfg.download_options(), cfg.data_directory.clone())
   };
 
-  let (tx, mut rx) = mpsc::channel::<DownloadResult>(128);
-
-  let chunks = create_batches_from_query(query, app);
-
-   for chunk in chunks {
-      let tx_clone = tx.clone();
-      let client_clone = client.clone();
-      let download_dir_path_buf = download_dir.to_path_buf();
-      let bucket = bucket.clone();
-       
-      tokio::spawn(async move {
-          for object in chunk {
-              let o = object.clone();
-              let key_temp = o.key.unwrap().clone();
-
-              download_file(&client_clone, &bucket, key_temp.as_str(), &download_dir_path_buf).await.unwrap();
-  
-              let file_path = download_dir_path_buf.join(key_temp).to_str().unwrap().to_string();
-              let metadata = fs::metadata(file_path.clone()).unwrap();
-              tx_clone.send(DownloadResult { bytes: metadata.len(), file: file_path }).await.unwrap();
-          }
-      });
+  let concurrency = ConcurrencyHandle::new(concurrency);
+  progress_bar.set_message(concurrency_message(concurrency.limit()));
+  // shows the `concurrency` command the fetch is running until it's dropped, however this returns
+  let running = RunningFetch::start(&data_directory, &workspace.name, concurrency.limit())?;
+  let progress_bar_clone = progress_bar.clone();
+  let watcher = scheduler::watch_concurrency_file(running.concurrency_file().to_path_buf(), concurrency.clone(), move |limit| {
+    progress_bar_clone.set_message(concurrency_message(limit));
+  });
+
+  let mut objects: Vec<Object> = query.objects.values().cloned().collect();
+  objects.sort_by_key(|object| Reverse(object.size.unwrap_or(0)));
+
+  let (tx, mut rx) = mpsc::channel::<Result<DownloadResult, DownloadFailure>>(128);
+
+  let client = client.clone();
+  let download_dir = workspace.path.clone();
+  let workspace_name = workspace.name.clone();
+  let scheduled = scheduler::spawn_scheduled(objects, concurrency, move |object| {
+    let tx = tx.clone();
+    let client = client.clone();
+    let download_dir = download_dir.clone();
+    let bucket = bucket.clone();
+    let entry = IndexEntry::from_object(&workspace_name, &bucket, &object);
+
+    async move {
+      let key = entry.key.clone();
+      let result = download_object(&client, &bucket, &object, &download_dir, &download_options).await;
+
+      let outcome = match result {
+        Ok(md5) => {
+          let file = download_dir.join(&key).to_string_lossy().to_string();
+          Ok(DownloadResult { bytes: entry.size, file, entry: IndexEntry { md5: Some(md5), ..entry } })
+        }
+        Err((e, attempts)) => Err(DownloadFailure { key, reason: e.to_string(), attempts }),
+      };
+      let _ = tx.send(outcome).await;
+    }
+  });
+
+  // the channel closes once the scheduler has finished every object
+  let mut report = DownloadReport::default();
+  while let Some(outcome) = rx.recv().await {
+    match outcome {
+      Ok(result) => {
+        index.record(result.entry)?;
+        report.files.push(result.file);
+        progress_bar.inc(result.bytes);
+      }
+      Err(failure) => {
+        log_error!("Failed to download {} after {} attempts: {}", failure.key, failure.attempts, failure.reason);
+        report.failures.push(failure);
+      }
+    }
   }
 
-  // wait for all downloads to complete
-  drop(tx);
-  let mut files = Vec::new();
-  while let Some(result) = rx.recv().await {
-      files.push(result.file);
-      progress_bar.inc(result.bytes);
+  scheduled.await?;
+  watcher.abort();
+  drop(running);
+  index.compact()?;
+
+  if report.failures.is_empty() {
+    progress_bar.finish_with_message("Downloaded");
+  } else {
+    progress_bar.abandon_with_message(format!("{} failed", report.failures.len()));
   }
 
-  progress_bar.finish_with_message("Downloaded");
+  Ok(report)
+}
 
-  Ok(files)
+fn concurrency_message(limit: usize) -> String {
+  format!("({} concurrent)", limit)
 }
 
 struct DownloadResult {
   bytes: u64,
   file: String,
-}
\ No newline at end of file
+  entry: IndexEntry,
+}
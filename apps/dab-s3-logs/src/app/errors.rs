@@ -6,4 +6,28 @@ use thiserror::Error;
 pub enum ApplicationError {
   #[error("Failed to create directory: `{0}`")]
   DirectoryCreationError(PathBuf),
-}
\ No newline at end of file
+}
+
+#[derive(Error, Debug)]
+pub enum TimeRangeError {
+  #[error("Unable to parse time: `{0}`")]
+  InvalidTime(String),
+  #[error("`--until` can't be combined with a `start..end` range")]
+  RangeWithUntil,
+  #[error("`--until` requires `--since`")]
+  MissingSince,
+  #[error("Time range is empty: {0} is not before {1}")]
+  EmptyRange(String, String),
+}
+
+#[derive(Error, Debug)]
+pub enum QueryBuilderError {
+  #[error("No bucket selected")]
+  MissingBucket,
+  #[error("Selection cancelled")]
+  SelectionCancelled,
+  #[error("Failed to list bucket: {0}")]
+  ListFailed(anyhow::Error),
+  #[error(transparent)]
+  TimeRange(#[from] TimeRangeError),
+}
@@ -4,10 +4,15 @@ use fs_extra::dir::get_size;
 use log::{debug, info, error as log_error};
 use std::thread::available_parallelism;
 use anyhow::Result;
-use crate::config;
+use aws::client::ClientOptions;
+use crate::{config, storage::catalog::BucketCatalog};
 
 pub mod errors;
 pub mod download;
+pub mod query_builder;
+pub mod scheduler;
+pub mod stream;
+pub mod time_range;
 use errors::ApplicationError::{self, DirectoryCreationError};
 
 #[derive(Debug)]
@@ -37,28 +42,41 @@ impl App {
   pub fn set_used_storage(&mut self, used_storage: u64) {
     self.used_storage = used_storage;
   }
-}
 
-pub fn setup() -> Result<App> {
-  env_logger::init();
-  info!("Setting up");
+  /// Catalog of the buckets visible to a profile at an endpoint, filling [`App::buckets`] once it's read
+  pub fn bucket_catalog(&self, options: &ClientOptions) -> BucketCatalog {
+    let cfg = self.config.lock().unwrap().clone().unwrap();
 
-  let cfg_from_file = config::get_config().unwrap();
-  let mut app = App::new();
+    BucketCatalog::new(&cfg.data_directory, options, self.buckets.clone())
+  }
+
+  /// An app using the given config rather than the one on disk, creating its directories
+  pub fn with_config(cfg: config::ApplicationConfig) -> Result<Self> {
+    let mut app = App::new();
 
-  {
-      let cloned_cfg = cfg_from_file.clone();
+    {
+      let cloned_cfg = cfg.clone();
       let mut app_config_binding = app.config.lock().unwrap();
       app_config_binding.replace(cloned_cfg);
+    }
+
+    setup_directories(&app)?;
+
+    let size = get_size(cfg.download_directory).unwrap();
+
+    app.set_used_storage(size);
+
+    Ok(app)
   }
+}
 
-  setup_directories(&app)?;
+pub fn setup() -> Result<App> {
+  env_logger::init();
+  info!("Setting up");
 
-  let size = get_size(cfg_from_file.download_directory).unwrap();
-  
-  app.set_used_storage(size); 
+  let cfg_from_file = config::get_config()?;
 
-  Ok(app)
+  App::with_config(cfg_from_file)
 }
 
 fn setup_directories(app: &App) -> Result<(), ApplicationError> {
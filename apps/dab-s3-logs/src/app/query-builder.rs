@@ -1,5 +0,0 @@
-fn query_builder () {
-  // bucket
-  // service
-  // date
-}
@@ -0,0 +1,353 @@
+use std::collections::HashSet;
+
+use aws::s3::{list_keys, prefixes::list_prefix, store::ObjectStore, Query, DELIMITER};
+use chrono::{Duration, Utc};
+use dialoguer::{theme::ColorfulTheme, FuzzySelect, Input, MultiSelect};
+use log::debug;
+
+use crate::{config::DEFAULT_DATE_PREFIX_FORMAT, storage::{catalog::BucketCatalog, listings::ListingCache}};
+
+use super::{errors::QueryBuilderError, time_range::{date_prefix, TimeRange}};
+
+const DEFAULT_SINCE: &str = "1d";
+const REFRESH_BUCKETS_ITEM: &str = "[refresh bucket list]";
+/// How long after a day ends logs may still land in its date folder
+const LATE_LOG_HOURS: i64 = 1;
+
+/// Composes a bucket, environment, services and a time range into S3 listings.
+///
+/// Keys are expected to be laid out as `<prefix>/<environment>/<service>/<date>/...`, any of
+/// which may be omitted. One listing is produced per service.
+#[derive(Debug, Clone)]
+pub struct QueryBuilder {
+  bucket: Option<String>,
+  prefix: Option<String>,
+  environment: Option<String>,
+  services: Vec<String>,
+  time_range: Option<TimeRange>,
+  date_prefix_format: String,
+  listing_cache: Option<ListingCache>,
+}
+
+impl Default for QueryBuilder {
+  fn default() -> Self {
+    Self {
+      bucket: None,
+      prefix: None,
+      environment: None,
+      services: Vec::new(),
+      time_range: None,
+      date_prefix_format: DEFAULT_DATE_PREFIX_FORMAT.to_string(),
+      listing_cache: None,
+    }
+  }
+}
+
+impl QueryBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn bucket(mut self, bucket: impl Into<String>) -> Self {
+    self.bucket = Some(bucket.into());
+    self
+  }
+
+  /// Prefix that everything else is nested under
+  pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+    self.prefix = Some(prefix.into());
+    self
+  }
+
+  pub fn environment(mut self, environment: impl Into<String>) -> Self {
+    self.environment = Some(environment.into());
+    self
+  }
+
+  pub fn service(mut self, service: impl Into<String>) -> Self {
+    self.services.push(service.into());
+    self
+  }
+
+  pub fn services<I: IntoIterator<Item = String>>(mut self, services: I) -> Self {
+    self.services.extend(services);
+    self
+  }
+
+  pub fn time_range(mut self, time_range: Option<TimeRange>) -> Self {
+    self.time_range = time_range;
+    self
+  }
+
+  /// chrono format of the date folders, e.g. `%Y-%m-%d`
+  pub fn date_prefix_format(mut self, date_prefix_format: impl Into<String>) -> Self {
+    self.date_prefix_format = date_prefix_format.into();
+    self
+  }
+
+  /// Reuse listings saved by earlier builds rather than listing every prefix again
+  pub fn listing_cache(mut self, listing_cache: ListingCache) -> Self {
+    self.listing_cache = Some(listing_cache);
+    self
+  }
+
+  pub fn get_bucket(&self) -> Option<&str> {
+    self.bucket.as_deref()
+  }
+
+  pub fn get_time_range(&self) -> Option<&TimeRange> {
+    self.time_range.as_ref()
+  }
+
+  /// True once there's enough to build a listing without prompting
+  pub fn is_complete(&self) -> bool {
+    self.bucket.is_some() && (self.prefix.is_some() || self.environment.is_some() || !self.services.is_empty())
+  }
+
+  /// Base prefix of each listing, one per service
+  pub fn base_prefixes(&self) -> Vec<String> {
+    let base = self.root_prefix();
+
+    if self.services.is_empty() {
+      return vec![base];
+    }
+
+    self.services.iter()
+      .map(|service| join_prefix(&[Some(base.as_str()), Some(service.as_str())]))
+      .collect()
+  }
+
+  /// Every prefix to list, expanded into date prefixes when there's a time range
+  pub fn prefixes(&self) -> Vec<String> {
+    let base_prefixes = self.base_prefixes();
+
+    match &self.time_range {
+      Some(time_range) => base_prefixes.iter()
+        .flat_map(|base| time_range.date_prefixes(base, &self.date_prefix_format))
+        .collect(),
+      None => base_prefixes,
+    }
+  }
+
+  /// List each service, filtering objects by the time range
+  pub async fn build<C: ObjectStore>(&self, client: &C) -> Result<Vec<Query>, QueryBuilderError> {
+    let bucket = self.bucket.clone().ok_or(QueryBuilderError::MissingBucket)?;
+
+    let mut queries = Vec::new();
+    for base in self.base_prefixes() {
+      let query = match &self.time_range {
+        Some(time_range) => {
+          let mut query = empty_query(&bucket, &base);
+          let now = Utc::now();
+          let settled_day = (now - Duration::hours(LATE_LOG_HOURS)).date_naive();
+          // folders of days that can still get logs, which with a format like `%Y-%m` is the
+          // folder of the whole month, even for days of it the range ends before
+          let open_prefixes: HashSet<String> = settled_day.iter_days()
+            .take_while(|day| *day <= now.date_naive())
+            .map(|day| date_prefix(&base, day, &self.date_prefix_format))
+            .collect();
+          for (date_prefix, last_day) in time_range.date_prefix_days(&base, &self.date_prefix_format) {
+            let immutable = last_day < settled_day && !open_prefixes.contains(&date_prefix);
+            query.extend(self.list(client, &bucket, &date_prefix, immutable).await?);
+          }
+          query.retain(|object| time_range.contains(object));
+          query
+        }
+        None => self.list(client, &bucket, &base, false).await?,
+      };
+
+      debug!("Built query for {} with {} objects", base, query.objects.len());
+      queries.push(query);
+    }
+
+    Ok(queries)
+  }
+
+  /// List every service and merge the results into a single query
+  pub async fn build_merged<C: ObjectStore>(&self, client: &C) -> Result<Query, QueryBuilderError> {
+    let bucket = self.bucket.clone().ok_or(QueryBuilderError::MissingBucket)?;
+
+    let mut merged = empty_query(&bucket, &self.root_prefix());
+    for query in self.build(client).await? {
+      merged.extend(query);
+    }
+
+    Ok(merged)
+  }
+
+  /// List a prefix, through the listing cache when there is one. `immutable` is true for the date
+  /// folder of a day that's long over, whose listing is kept for good
+  async fn list<C: ObjectStore>(&self, client: &C, bucket: &str, prefix: &str, immutable: bool) -> Result<Query, QueryBuilderError> {
+    let listing = match &self.listing_cache {
+      Some(cache) => cache.list(client, bucket, prefix, immutable).await,
+      None => list_keys(client, bucket, prefix).await,
+    };
+
+    listing.map_err(QueryBuilderError::ListFailed)
+  }
+
+  /// Prefix shared by every listing. A bare `prefix` is used as given so partial key prefixes still work
+  fn root_prefix(&self) -> String {
+    match (&self.prefix, &self.environment) {
+      (Some(prefix), None) if self.services.is_empty() => prefix.clone(),
+      _ => join_prefix(&[self.prefix.as_deref(), self.environment.as_deref()]),
+    }
+  }
+
+  /// Prompt for whatever hasn't been set yet: bucket, environment, services and time range
+  pub async fn interactive<C: ObjectStore>(mut self, client: &C, catalog: &BucketCatalog) -> Result<Self, QueryBuilderError> {
+    let theme = ColorfulTheme::default();
+
+    let bucket = match self.bucket.clone() {
+      Some(bucket) => bucket,
+      None => {
+        let bucket = select_bucket(client, catalog).await?;
+        self.bucket = Some(bucket.clone());
+        bucket
+      }
+    };
+
+    if self.environment.is_none() {
+      let base = join_prefix(&[self.prefix.as_deref()]);
+      let environments = list_folders(client, &bucket, &base).await?;
+      if !environments.is_empty() {
+        self.environment = Some(select(&theme, "Select an environment", &environments)?);
+      }
+    }
+
+    if self.services.is_empty() {
+      let base = join_prefix(&[self.prefix.as_deref(), self.environment.as_deref()]);
+      let services = list_folders(client, &bucket, &base).await?;
+      if !services.is_empty() {
+        let selected = MultiSelect::with_theme(&theme)
+          .with_prompt("Select services (space to toggle, enter to confirm)")
+          .items(&services)
+          .interact_opt()
+          .map_err(|_| QueryBuilderError::SelectionCancelled)?
+          .ok_or(QueryBuilderError::SelectionCancelled)?;
+        self.services = selected.into_iter().map(|index| services[index].clone()).collect();
+      }
+    }
+
+    if self.time_range.is_none() {
+      let since: String = Input::with_theme(&theme)
+        .with_prompt("Since (e.g. 6h, 2d, 2024-04-01..2024-04-03)")
+        .default(DEFAULT_SINCE.to_string())
+        .interact_text()
+        .map_err(|_| QueryBuilderError::SelectionCancelled)?;
+      self.time_range = TimeRange::parse(Some(&since), None, Utc::now())?;
+    }
+
+    Ok(self)
+  }
+}
+
+/// Fuzzy pick one of the buckets in the profile's catalog, which is listed from S3 the first time
+/// and again whenever the refresh item is picked
+pub async fn select_bucket<C: ObjectStore>(client: &C, catalog: &BucketCatalog) -> Result<String, QueryBuilderError> {
+  let theme = ColorfulTheme::default();
+  let mut buckets = catalog.names(client).await.map_err(|e| QueryBuilderError::ListFailed(e.into()))?;
+
+  loop {
+    let mut items = buckets.clone();
+    items.push(REFRESH_BUCKETS_ITEM.to_string());
+
+    let bucket = select(&theme, &format!("Select a bucket ({})", catalog.profile()), &items)?;
+    if bucket != REFRESH_BUCKETS_ITEM {
+      return Ok(bucket);
+    }
+
+    buckets = catalog.refresh(client).await
+      .map_err(|e| QueryBuilderError::ListFailed(e.into()))?
+      .into_iter()
+      .filter_map(|bucket| bucket.name)
+      .collect();
+  }
+}
+
+fn empty_query(bucket: &str, prefix: &str) -> Query {
+  Query {
+    objects: Default::default(),
+    prefix: prefix.to_string(),
+    bucket: bucket.to_string(),
+    size: 0,
+  }
+}
+
+/// Join prefix segments with `/`, always ending in `/` unless empty
+fn join_prefix(parts: &[Option<&str>]) -> String {
+  let mut prefix = String::new();
+  for part in parts.iter().flatten() {
+    let part = part.trim_matches('/');
+    if part.is_empty() {
+      continue;
+    }
+    prefix.push_str(part);
+    prefix.push_str(DELIMITER);
+  }
+
+  prefix
+}
+
+/// Names of the folders directly beneath a prefix
+async fn list_folders<C: ObjectStore>(client: &C, bucket: &str, prefix: &str) -> Result<Vec<String>, QueryBuilderError> {
+  let listing = list_prefix(client, bucket, prefix).await.map_err(QueryBuilderError::ListFailed)?;
+
+  Ok(listing.folder_names())
+}
+
+fn select(theme: &ColorfulTheme, prompt: &str, items: &[String]) -> Result<String, QueryBuilderError> {
+  let index = FuzzySelect::with_theme(theme)
+    .with_prompt(prompt)
+    .items(items)
+    .interact_opt()
+    .map_err(|_| QueryBuilderError::SelectionCancelled)?
+    .ok_or(QueryBuilderError::SelectionCancelled)?;
+
+  Ok(items[index].clone())
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone;
+
+  use super::*;
+
+  #[test]
+  fn joins_segments_with_a_trailing_delimiter() {
+    assert_eq!(join_prefix(&[Some("logs"), None, Some("/production/"), Some("")]), "logs/production/");
+    assert_eq!(join_prefix(&[None, Some("/")]), "");
+  }
+
+  #[test]
+  fn uses_a_bare_prefix_as_given() {
+    assert_eq!(QueryBuilder::new().prefix("production/ap").root_prefix(), "production/ap");
+    assert_eq!(QueryBuilder::new().root_prefix(), "");
+  }
+
+  #[test]
+  fn nests_environment_and_services_under_the_prefix() {
+    let query = QueryBuilder::new().prefix("logs").environment("production").service("api").service("web");
+
+    assert_eq!(query.root_prefix(), "logs/production/");
+    assert_eq!(query.base_prefixes(), ["logs/production/api/", "logs/production/web/"]);
+    assert_eq!(QueryBuilder::new().prefix("logs").service("api").root_prefix(), "logs/");
+  }
+
+  #[test]
+  fn expands_each_service_into_date_prefixes() {
+    let time_range = TimeRange {
+      start: Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+      end: Utc.with_ymd_and_hms(2024, 5, 2, 12, 0, 0).unwrap(),
+    };
+    let query = QueryBuilder::new().environment("production").service("api").service("web").time_range(Some(time_range));
+
+    assert_eq!(query.prefixes(), [
+      "production/api/2024-05-01/",
+      "production/api/2024-05-02/",
+      "production/web/2024-05-01/",
+      "production/web/2024-05-02/",
+    ]);
+    assert_eq!(QueryBuilder::new().environment("production").prefixes(), ["production/"]);
+  }
+}
@@ -0,0 +1,272 @@
+use std::{fs::{self, File, TryLockError}, future::Future, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::Duration};
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+use tokio::{sync::{OwnedSemaphorePermit, Semaphore}, task::{JoinHandle, JoinSet}};
+
+/// Holds a file per workspace being fetched into, written by the `concurrency` command and
+/// picked up by the fetch
+const CONCURRENCY_DIRECTORY: &str = "concurrency";
+/// Beneath the concurrency directory, a lock file per workspace held by the fetch into it, so the
+/// files of a fetch that was killed can be told apart from those of a running one
+const LOCK_DIRECTORY: &str = "locks";
+const CONCURRENCY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Limit on how many downloads run at once, which can be changed while they're running
+#[derive(Debug, Clone)]
+pub struct ConcurrencyHandle {
+  semaphore: Arc<Semaphore>,
+  state: Arc<Mutex<LimitState>>,
+}
+
+#[derive(Debug)]
+struct LimitState {
+  limit: usize,
+  /// Permits still held by running tasks that must be dropped rather than returned after lowering the limit
+  debt: usize,
+}
+
+impl ConcurrencyHandle {
+  pub fn new(limit: usize) -> Self {
+    let limit = limit.max(1);
+    Self {
+      semaphore: Arc::new(Semaphore::new(limit)),
+      state: Arc::new(Mutex::new(LimitState { limit, debt: 0 })),
+    }
+  }
+
+  pub fn limit(&self) -> usize {
+    self.state.lock().unwrap().limit
+  }
+
+  /// Raise or lower the limit. Lowering it lets running tasks finish and holds back new ones
+  pub fn set_limit(&self, limit: usize) {
+    let limit = limit.max(1);
+    let mut state = self.state.lock().unwrap();
+
+    if limit > state.limit {
+      let grow = limit - state.limit;
+      let repaid = grow.min(state.debt);
+      state.debt -= repaid;
+      self.semaphore.add_permits(grow - repaid);
+    } else {
+      let shrink = state.limit - limit;
+      let forgotten = self.semaphore.forget_permits(shrink);
+      state.debt += shrink - forgotten;
+    }
+    state.limit = limit;
+  }
+
+  async fn acquire(&self) -> OwnedSemaphorePermit {
+    // the semaphore is never closed
+    self.semaphore.clone().acquire_owned().await.unwrap()
+  }
+
+  fn release(&self, permit: OwnedSemaphorePermit) {
+    let mut state = self.state.lock().unwrap();
+    if state.debt > 0 {
+      state.debt -= 1;
+      permit.forget();
+    }
+  }
+}
+
+/// Run `work` for every item, at most `concurrency.limit()` at a time, in the order given.
+///
+/// Items are handed out from a single queue as soon as a slot frees up, so one slow item never
+/// holds back the rest. The returned handle completes once every item is done.
+pub fn spawn_scheduled<T, F, Fut>(items: Vec<T>, concurrency: ConcurrencyHandle, work: F) -> JoinHandle<()>
+where
+  T: Send + 'static,
+  F: Fn(T) -> Fut + Send + 'static,
+  Fut: Future<Output = ()> + Send + 'static,
+{
+  tokio::spawn(async move {
+    let mut running = JoinSet::new();
+    for item in items {
+      let permit = concurrency.acquire().await;
+      let task = work(item);
+      let concurrency = concurrency.clone();
+      running.spawn(async move {
+        task.await;
+        concurrency.release(permit);
+      });
+
+      // reap finished tasks so the set doesn't grow with the queue
+      while running.try_join_next().is_some() {}
+    }
+
+    while running.join_next().await.is_some() {}
+  })
+}
+
+pub fn concurrency_directory(data_directory: &Path) -> PathBuf {
+  data_directory.join(CONCURRENCY_DIRECTORY)
+}
+
+/// Where the limit of a fetch into the workspace is read from
+pub fn concurrency_file(data_directory: &Path, workspace: &str) -> PathBuf {
+  concurrency_directory(data_directory).join(workspace)
+}
+
+fn lock_file(data_directory: &Path, workspace: &str) -> PathBuf {
+  concurrency_directory(data_directory).join(LOCK_DIRECTORY).join(workspace)
+}
+
+/// True while a fetch into the workspace holds its lock
+pub fn is_fetch_running(data_directory: &Path, workspace: &str) -> bool {
+  match File::open(lock_file(data_directory, workspace)) {
+    Ok(file) => matches!(file.try_lock(), Err(TryLockError::WouldBlock)),
+    Err(_) => false,
+  }
+}
+
+/// A fetch into a workspace, holding the workspace's lock and concurrency file until it's
+/// dropped, so both are removed on errors and early returns too
+#[derive(Debug)]
+pub struct RunningFetch {
+  _lock: File,
+  lock_file: PathBuf,
+  concurrency_file: PathBuf,
+}
+
+impl RunningFetch {
+  /// Take the workspace's lock and write this fetch's limit, replacing any left by a fetch that
+  /// was killed
+  pub fn start(data_directory: &Path, workspace: &str, limit: usize) -> Result<Self> {
+    let lock_file = lock_file(data_directory, workspace);
+    if let Some(parent) = lock_file.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    let lock = File::create(&lock_file)?;
+    match lock.try_lock() {
+      Ok(()) => {}
+      Err(TryLockError::WouldBlock) => bail!("A fetch into workspace {} is already running", workspace),
+      Err(TryLockError::Error(e)) => return Err(e.into()),
+    }
+
+    let concurrency_file = concurrency_file(data_directory, workspace);
+    write_concurrency_file(&concurrency_file, limit)?;
+
+    Ok(Self { _lock: lock, lock_file, concurrency_file })
+  }
+
+  pub fn concurrency_file(&self) -> &Path {
+    &self.concurrency_file
+  }
+}
+
+impl Drop for RunningFetch {
+  fn drop(&mut self) {
+    for path in [&self.concurrency_file, &self.lock_file] {
+      if let Err(e) = fs::remove_file(path) {
+        warn!("Failed to remove {}: {}", path.display(), e);
+      }
+    }
+  }
+}
+
+pub fn write_concurrency_file(path: &Path, limit: usize) -> std::io::Result<()> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+
+  fs::write(path, limit.to_string())
+}
+
+/// Follow the concurrency file, applying any new limit written to it until the task is aborted
+pub fn watch_concurrency_file<F>(path: PathBuf, concurrency: ConcurrencyHandle, on_change: F) -> JoinHandle<()>
+where
+  F: Fn(usize) + Send + 'static,
+{
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(CONCURRENCY_POLL_INTERVAL);
+    loop {
+      interval.tick().await;
+
+      let limit = match fs::read_to_string(&path).ok().and_then(|contents| contents.trim().parse::<usize>().ok()) {
+        Some(limit) if limit > 0 => limit,
+        _ => continue,
+      };
+      if limit != concurrency.limit() {
+        info!("Changing download concurrency from {} to {}", concurrency.limit(), limit);
+        concurrency.set_limit(limit);
+        on_change(limit);
+      }
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hold(concurrency: &ConcurrencyHandle) -> OwnedSemaphorePermit {
+    concurrency.semaphore.clone().try_acquire_owned().unwrap()
+  }
+
+  fn debt(concurrency: &ConcurrencyHandle) -> usize {
+    concurrency.state.lock().unwrap().debt
+  }
+
+  #[test]
+  fn raising_the_limit_adds_permits() {
+    let concurrency = ConcurrencyHandle::new(2);
+
+    concurrency.set_limit(4);
+
+    assert_eq!(concurrency.limit(), 4);
+    assert_eq!(concurrency.semaphore.available_permits(), 4);
+  }
+
+  #[test]
+  fn lowering_the_limit_forgets_free_permits() {
+    let concurrency = ConcurrencyHandle::new(4);
+
+    concurrency.set_limit(1);
+
+    assert_eq!(concurrency.semaphore.available_permits(), 1);
+    assert_eq!(debt(&concurrency), 0);
+  }
+
+  #[test]
+  fn lowering_the_limit_below_running_tasks_drops_their_permits_as_they_finish() {
+    let concurrency = ConcurrencyHandle::new(2);
+    let first = hold(&concurrency);
+    let second = hold(&concurrency);
+
+    concurrency.set_limit(1);
+    assert_eq!(debt(&concurrency), 1);
+
+    concurrency.release(first);
+    assert_eq!(concurrency.semaphore.available_permits(), 0, "the first permit back pays the debt");
+    concurrency.release(second);
+    assert_eq!(concurrency.semaphore.available_permits(), 1);
+    assert_eq!(debt(&concurrency), 0);
+  }
+
+  #[test]
+  fn raising_the_limit_repays_debt_before_adding_permits() {
+    let concurrency = ConcurrencyHandle::new(2);
+    let held = [hold(&concurrency), hold(&concurrency)];
+    concurrency.set_limit(1);
+
+    concurrency.set_limit(3);
+
+    assert_eq!(debt(&concurrency), 0);
+    assert_eq!(concurrency.semaphore.available_permits(), 1);
+    for permit in held {
+      concurrency.release(permit);
+    }
+    assert_eq!(concurrency.semaphore.available_permits(), 3);
+  }
+
+  #[test]
+  fn the_limit_is_at_least_one() {
+    let concurrency = ConcurrencyHandle::new(0);
+    concurrency.set_limit(0);
+
+    assert_eq!(concurrency.limit(), 1);
+    assert_eq!(concurrency.semaphore.available_permits(), 1);
+  }
+}
@@ -0,0 +1,190 @@
+use std::{cmp, io::{self, Read}, sync::{atomic::{AtomicBool, Ordering}, Arc}};
+
+use aws::s3::{download::open_object, store::ObjectStore, Query};
+use aws_sdk_s3::types::Object;
+use bytes::{Buf, Bytes};
+use anyhow::Result;
+use log::{debug, error as log_error};
+use tokio::sync::mpsc;
+
+use crate::output::{errors::OutputError, stdout, OutputOptions};
+
+use super::{download::DownloadFailure, scheduler::{self, ConcurrencyHandle}, App};
+
+/// Chunks of a body buffered between S3 and the decoder, per object
+const STREAM_BUFFER_CHUNKS: usize = 16;
+
+/// Objects that were streamed and objects that failed
+#[derive(Debug, Default)]
+pub struct StreamReport {
+  pub objects: usize,
+  pub failures: Vec<DownloadFailure>,
+  /// Stdout was closed part way, so the objects left weren't streamed
+  pub stdout_closed: bool,
+}
+
+/// Write the records of every object in the query to stdout without storing anything on disk.
+///
+/// At most `concurrency` objects are in flight at once, started in key order. Each object is
+/// decoded by a single reader, so its records come out in the order they're stored, though records
+/// of different objects interleave. Only the request is retried, as a body that fails part way has
+/// already been written out. Once stdout is closed nothing more is read.
+pub async fn stream_query_results<C: ObjectStore>(query: &Query, app: &App, client: &C, options: OutputOptions, concurrency: usize) -> Result<StreamReport> {
+  let retry_policy = {
+    let cfg_binding = app.config.lock().unwrap();
+    cfg_binding.as_ref().unwrap().retry_policy()
+  };
+
+  let mut objects: Vec<Object> = query.objects.values().cloned().collect();
+  objects.sort_by(|a, b| a.key.cmp(&b.key));
+
+  let (tx, mut rx) = mpsc::channel::<Result<String, DownloadFailure>>(128);
+  let stdout_closed = Arc::new(AtomicBool::new(false));
+
+  let client = client.clone();
+  let bucket = query.bucket.clone();
+  let options = Arc::new(options);
+  let closed = stdout_closed.clone();
+  let scheduled = scheduler::spawn_scheduled(objects, ConcurrencyHandle::new(concurrency), move |object| {
+    let tx = tx.clone();
+    let client = client.clone();
+    let bucket = bucket.clone();
+    let options = options.clone();
+    let closed = closed.clone();
+
+    async move {
+      if closed.load(Ordering::Relaxed) {
+        return;
+      }
+      let key = object.key.clone().unwrap_or_default();
+      let outcome = match open_object(&client, &bucket, &object, &retry_policy).await {
+        Ok(mut body) => {
+          let (chunk_tx, chunk_rx) = mpsc::channel(STREAM_BUFFER_CHUNKS);
+          let name = key.clone();
+          let output = tokio::task::spawn_blocking(move || stdout::output_stream(&name, ChunkReader::new(chunk_rx), &options));
+
+          loop {
+            let chunk = match body.try_next().await {
+              Ok(Some(bytes)) => Ok(bytes),
+              Ok(None) => break,
+              Err(e) => Err(io::Error::other(e)),
+            };
+            let failed = chunk.is_err();
+            // the reader only hangs up early when it has failed, which it reports below
+            if chunk_tx.send(chunk).await.is_err() || failed {
+              break;
+            }
+          }
+          drop(chunk_tx);
+
+          match output.await {
+            Ok(Ok(())) => Ok(key),
+            Ok(Err(OutputError::StdoutClosed)) => {
+              closed.store(true, Ordering::Relaxed);
+              return;
+            }
+            Ok(Err(e)) => Err(DownloadFailure { key, reason: e.to_string(), attempts: 1 }),
+            Err(e) => Err(DownloadFailure { key, reason: e.to_string(), attempts: 1 }),
+          }
+        }
+        Err((e, attempts)) => Err(DownloadFailure { key, reason: e.to_string(), attempts }),
+      };
+      let _ = tx.send(outcome).await;
+    }
+  });
+
+  // the channel closes once the scheduler has finished every object
+  let mut report = StreamReport::default();
+  while let Some(outcome) = rx.recv().await {
+    match outcome {
+      Ok(key) => {
+        debug!("Streamed object: {}", key);
+        report.objects += 1;
+      }
+      Err(failure) => {
+        log_error!("Failed to stream {} after {} attempts: {}", failure.key, failure.attempts, failure.reason);
+        report.failures.push(failure);
+      }
+    }
+  }
+  scheduled.await?;
+  report.stdout_closed = stdout_closed.load(Ordering::Relaxed);
+
+  Ok(report)
+}
+
+/// Blocking reader over body chunks sent from an async task, ending when the sender is dropped
+struct ChunkReader {
+  receiver: mpsc::Receiver<io::Result<Bytes>>,
+  chunk: Bytes,
+}
+
+impl ChunkReader {
+  fn new(receiver: mpsc::Receiver<io::Result<Bytes>>) -> Self {
+    Self { receiver, chunk: Bytes::new() }
+  }
+}
+
+impl Read for ChunkReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    while self.chunk.is_empty() {
+      match self.receiver.blocking_recv() {
+        Some(chunk) => self.chunk = chunk?,
+        None => return Ok(0),
+      }
+    }
+
+    let read = cmp::min(buf.len(), self.chunk.len());
+    buf[..read].copy_from_slice(&self.chunk[..read]);
+    self.chunk.advance(read);
+
+    Ok(read)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn reader(chunks: Vec<io::Result<Bytes>>) -> ChunkReader {
+    let (tx, rx) = mpsc::channel(chunks.len().max(1));
+    for chunk in chunks {
+      tx.try_send(chunk).unwrap();
+    }
+
+    ChunkReader::new(rx)
+  }
+
+  #[test]
+  fn reads_across_chunks_until_the_sender_hangs_up() {
+    let mut reader = reader(vec![Ok(Bytes::from("ab")), Ok(Bytes::new()), Ok(Bytes::from("cde"))]);
+
+    let mut read = String::new();
+    reader.read_to_string(&mut read).unwrap();
+
+    assert_eq!(read, "abcde");
+  }
+
+  #[test]
+  fn splits_a_chunk_over_small_reads() {
+    let mut reader = reader(vec![Ok(Bytes::from("abc"))]);
+    let mut buf = [0; 2];
+
+    assert_eq!(reader.read(&mut buf).unwrap(), 2);
+    assert_eq!(&buf, b"ab");
+    assert_eq!(reader.read(&mut buf).unwrap(), 1);
+    assert_eq!(buf[0], b'c');
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+  }
+
+  #[test]
+  fn passes_on_body_errors() {
+    let mut reader = reader(vec![Ok(Bytes::from("ab")), Err(io::Error::other("connection reset"))]);
+
+    let mut read = Vec::new();
+    let e = reader.read_to_end(&mut read).unwrap_err();
+
+    assert_eq!(e.to_string(), "connection reset");
+    assert_eq!(read, b"ab");
+  }
+}
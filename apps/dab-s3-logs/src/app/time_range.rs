@@ -0,0 +1,262 @@
+use std::sync::LazyLock;
+
+use aws_sdk_s3::types::Object;
+use chrono::{DateTime, Days, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use regex::Regex;
+
+use super::errors::TimeRangeError;
+
+const RANGE_SEPARATOR: &str = "..";
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const DATETIME_FORMATS: [&str; 4] = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"];
+const RELATIVE_PATTERN: &str = r"^(?:last\s+)?(\d+)\s*(m|mins?|minutes?|h|hrs?|hours?|d|days?|w|weeks?)(?:\s+ago)?$";
+
+static RELATIVE: LazyLock<Regex> = LazyLock::new(|| Regex::new(RELATIVE_PATTERN).unwrap());
+
+/// Half-open window of time `[start, end)` used to narrow down a fetch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+  pub start: DateTime<Utc>,
+  pub end: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Bound {
+  Start,
+  End,
+}
+
+impl TimeRange {
+  /// Build a range from the `--since` / `--until` arguments.
+  ///
+  /// `since` accepts a relative duration (`6h`, `last 6 hours`, `2d ago`), a date, a datetime or
+  /// a full `start..end` range. `until` defaults to now. Dates given as an end bound are inclusive.
+  pub fn parse(since: Option<&str>, until: Option<&str>, now: DateTime<Utc>) -> Result<Option<Self>, TimeRangeError> {
+    let (since, until) = match since {
+      Some(since) if since.contains(RANGE_SEPARATOR) => {
+        if until.is_some() {
+          return Err(TimeRangeError::RangeWithUntil);
+        }
+        let (start, end) = since.split_once(RANGE_SEPARATOR).unwrap();
+        (Some(start), Some(end))
+      }
+      _ => (since, until),
+    };
+
+    let start = match since {
+      Some(since) => parse_bound(since, Bound::Start, now)?,
+      None => match until {
+        Some(_) => return Err(TimeRangeError::MissingSince),
+        None => return Ok(None),
+      },
+    };
+    let end = match until {
+      Some(until) => parse_bound(until, Bound::End, now)?,
+      None => now,
+    };
+
+    if start >= end {
+      return Err(TimeRangeError::EmptyRange(start.to_rfc3339(), end.to_rfc3339()));
+    }
+
+    Ok(Some(Self { start, end }))
+  }
+
+  /// Every day touched by the range, formatted with `date_format` and appended to `base_prefix`.
+  /// Days sharing a folder, as they do with a format like `%Y-%m`, give it once
+  pub fn date_prefixes(&self, base_prefix: &str, date_format: &str) -> Vec<String> {
+    self.date_prefix_days(base_prefix, date_format).into_iter().map(|(prefix, _)| prefix).collect()
+  }
+
+  /// Each of [`Self::date_prefixes`] with the last day of the range that falls in it
+  pub fn date_prefix_days(&self, base_prefix: &str, date_format: &str) -> Vec<(String, NaiveDate)> {
+    let mut prefixes: Vec<(String, NaiveDate)> = Vec::new();
+    for day in self.days() {
+      let prefix = date_prefix(base_prefix, day, date_format);
+      match prefixes.iter_mut().find(|(existing, _)| *existing == prefix) {
+        Some((_, last_day)) => *last_day = day,
+        None => prefixes.push((prefix, day)),
+      }
+    }
+
+    prefixes
+  }
+
+  /// Every UTC day the range touches, in order
+  pub fn days(&self) -> Vec<NaiveDate> {
+    let last_day = (self.end - Duration::nanoseconds(1)).date_naive();
+    let mut day = self.start.date_naive();
+    let mut days = Vec::new();
+    while day <= last_day {
+      days.push(day);
+      day = match day.checked_add_days(Days::new(1)) {
+        Some(next) => next,
+        None => break,
+      };
+    }
+
+    days
+  }
+
+  /// True when the object was last modified inside the range
+  pub fn contains(&self, object: &Object) -> bool {
+    match object.last_modified {
+      Some(last_modified) => {
+        let secs = last_modified.secs();
+        secs >= self.start.timestamp() && secs < self.end.timestamp()
+      }
+      None => false,
+    }
+  }
+}
+
+/// The folder a day's logs are under beneath `base_prefix`, e.g. `production/api/2024-05-01/`
+pub fn date_prefix(base_prefix: &str, day: NaiveDate, date_format: &str) -> String {
+  if base_prefix.is_empty() || base_prefix.ends_with('/') {
+    format!("{}{}/", base_prefix, day.format(date_format))
+  } else {
+    format!("{}/{}/", base_prefix, day.format(date_format))
+  }
+}
+
+/// A single point in time, in any of the forms `TimeRange::parse` accepts, e.g. `7d` or `2024-05-01`
+pub fn parse_time(input: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, TimeRangeError> {
+  parse_bound(input, Bound::Start, now)
+}
+
+fn parse_bound(input: &str, bound: Bound, now: DateTime<Utc>) -> Result<DateTime<Utc>, TimeRangeError> {
+  let input = input.trim().to_lowercase();
+
+  match input.as_str() {
+    "now" => return Ok(now),
+    "today" => return Ok(start_of_day(now.date_naive(), bound)),
+    "yesterday" => return Ok(start_of_day(now.date_naive() - Days::new(1), bound)),
+    _ => {}
+  }
+
+  if let Some(captures) = RELATIVE.captures(&input) {
+    let amount: i64 = captures[1].parse().map_err(|_| TimeRangeError::InvalidTime(input.clone()))?;
+    let duration = match &captures[2][..1] {
+      "m" => Duration::try_minutes(amount),
+      "h" => Duration::try_hours(amount),
+      "d" => Duration::try_days(amount),
+      _ => Duration::try_weeks(amount),
+    };
+    return duration
+      .and_then(|duration| now.checked_sub_signed(duration))
+      .ok_or(TimeRangeError::InvalidTime(input));
+  }
+
+  // input was lowercased for the keywords above, but the `T` between date and time is upper case
+  let upper = input.to_uppercase();
+  if let Ok(datetime) = DateTime::parse_from_rfc3339(&upper) {
+    return Ok(datetime.with_timezone(&Utc));
+  }
+
+  for format in DATETIME_FORMATS {
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(&upper, format) {
+      return Ok(Utc.from_utc_datetime(&datetime));
+    }
+  }
+
+  if let Ok(date) = NaiveDate::parse_from_str(&input, DATE_FORMAT) {
+    return Ok(start_of_day(date, bound));
+  }
+
+  Err(TimeRangeError::InvalidTime(input))
+}
+
+/// Midnight at the start of the day, or of the following day for an end bound so the date is inclusive
+fn start_of_day(date: NaiveDate, bound: Bound) -> DateTime<Utc> {
+  let date = match bound {
+    Bound::Start => date,
+    Bound::End => date.checked_add_days(Days::new(1)).unwrap_or(date),
+  };
+
+  Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn now() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 5, 10, 15, 30, 0).unwrap()
+  }
+
+  fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+  }
+
+  fn parse(since: Option<&str>, until: Option<&str>) -> Result<Option<TimeRange>, TimeRangeError> {
+    TimeRange::parse(since, until, now())
+  }
+
+  #[test]
+  fn no_since_means_no_range() {
+    assert_eq!(parse(None, None).unwrap(), None);
+  }
+
+  #[test]
+  fn parses_relative_durations_back_from_now() {
+    for since in ["6h", "6 hours", "last 6 hours", "6h ago", "360m"] {
+      let range = parse(Some(since), None).unwrap().unwrap();
+      assert_eq!(range, TimeRange { start: at(2024, 5, 10, 9, 30), end: now() }, "{}", since);
+    }
+    assert_eq!(parse(Some("2d"), None).unwrap().unwrap().start, at(2024, 5, 8, 15, 30));
+    assert_eq!(parse(Some("1w"), None).unwrap().unwrap().start, at(2024, 5, 3, 15, 30));
+  }
+
+  #[test]
+  fn parses_absolute_dates_and_times() {
+    let range = parse(Some("2024-05-01"), Some("2024-05-02")).unwrap().unwrap();
+    // an end date includes the whole day
+    assert_eq!(range, TimeRange { start: at(2024, 5, 1, 0, 0), end: at(2024, 5, 3, 0, 0) });
+
+    let range = parse(Some("2024-05-01T10:15"), Some("2024-05-01 11:00:00")).unwrap().unwrap();
+    assert_eq!(range, TimeRange { start: at(2024, 5, 1, 10, 15), end: at(2024, 5, 1, 11, 0) });
+
+    let range = parse(Some("2024-05-01T10:00:00+02:00"), None).unwrap().unwrap();
+    assert_eq!(range.start, at(2024, 5, 1, 8, 0));
+  }
+
+  #[test]
+  fn parses_ranges_and_named_days() {
+    let range = parse(Some("2024-05-01..2024-05-03"), None).unwrap().unwrap();
+    assert_eq!(range, TimeRange { start: at(2024, 5, 1, 0, 0), end: at(2024, 5, 4, 0, 0) });
+
+    let range = parse(Some("yesterday"), Some("today")).unwrap().unwrap();
+    assert_eq!(range, TimeRange { start: at(2024, 5, 9, 0, 0), end: at(2024, 5, 11, 0, 0) });
+  }
+
+  #[test]
+  fn rejects_empty_and_inverted_ranges() {
+    assert!(matches!(parse(Some("2024-05-03T00:00"), Some("2024-05-03T00:00")), Err(TimeRangeError::EmptyRange(..))));
+    assert!(matches!(parse(Some("2024-05-03"), Some("2024-05-01")), Err(TimeRangeError::EmptyRange(..))));
+    assert!(matches!(parse(Some("2024-05-03..2024-05-01"), None), Err(TimeRangeError::EmptyRange(..))));
+  }
+
+  #[test]
+  fn rejects_bad_input() {
+    assert!(matches!(parse(Some("soon"), None), Err(TimeRangeError::InvalidTime(_))));
+    assert!(matches!(parse(None, Some("1d")), Err(TimeRangeError::MissingSince)));
+    assert!(matches!(parse(Some("2024-05-01..2024-05-02"), Some("now")), Err(TimeRangeError::RangeWithUntil)));
+  }
+
+  #[test]
+  fn lists_one_date_prefix_per_day_touched() {
+    let range = TimeRange { start: at(2024, 5, 1, 22, 0), end: at(2024, 5, 3, 0, 0) };
+
+    assert_eq!(range.date_prefixes("production/api", "%Y-%m-%d"), ["production/api/2024-05-01/", "production/api/2024-05-02/"]);
+  }
+
+  #[test]
+  fn lists_a_shared_date_prefix_once_with_its_last_day() {
+    let range = TimeRange { start: at(2024, 4, 30, 0, 0), end: at(2024, 5, 3, 0, 0) };
+
+    assert_eq!(range.date_prefix_days("logs/", "%Y-%m"), [
+      ("logs/2024-04/".to_string(), NaiveDate::from_ymd_opt(2024, 4, 30).unwrap()),
+      ("logs/2024-05/".to_string(), NaiveDate::from_ymd_opt(2024, 5, 2).unwrap()),
+    ]);
+  }
+}
@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum BrowseError {
+  #[error("Browsing requires an interactive terminal")]
+  NotATerminal,
+  #[error("Failed to list bucket")]
+  ListFailed,
+  #[error("Selection cancelled")]
+  SelectionCancelled,
+}
@@ -0,0 +1,140 @@
+use aws::s3::{prefixes::list_prefix, store::ObjectStore, DELIMITER};
+use dialoguer::{theme::ColorfulTheme, FuzzySelect};
+use human_bytes::human_bytes;
+use is_terminal::is_terminal;
+use log::error as log_error;
+
+use crate::{app::query_builder::select_bucket, storage::catalog::BucketCatalog};
+
+pub mod errors;
+
+const UP_ITEM: &str = "..";
+const FETCH_ITEM: &str = "[fetch this prefix]";
+const PREVIEW_ITEM: &str = "[preview this prefix]";
+
+/// What to do with the prefix picked while browsing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrowseAction {
+  Fetch,
+  Preview,
+}
+
+#[derive(Debug, Clone)]
+pub struct BrowseSelection {
+  pub bucket: String,
+  pub prefix: String,
+  pub action: BrowseAction,
+}
+
+/// Drill into a bucket one folder at a time until a prefix is picked to fetch or preview
+pub async fn browse<C: ObjectStore> (client: &C, catalog: &BucketCatalog, bucket: Option<String>, prefix: Option<String>) -> Result<BrowseSelection, errors::BrowseError> {
+  if !is_terminal(std::io::stdout()) {
+    return Err(errors::BrowseError::NotATerminal);
+  }
+
+  let bucket = match bucket {
+    Some(bucket) => bucket,
+    None => select_bucket(client, catalog).await.map_err(|e| {
+      log_error!("{}", e);
+      errors::BrowseError::SelectionCancelled
+    })?,
+  };
+
+  let theme = ColorfulTheme::default();
+  let mut prefix = prefix.unwrap_or_default();
+
+  loop {
+    let level = list_level(client, &bucket, &prefix).await?;
+    let items = level.items();
+
+    let index = FuzzySelect::with_theme(&theme)
+      .with_prompt(level.prompt(&bucket))
+      .items(&items)
+      .default(0)
+      .interact_opt()
+      .map_err(|_| errors::BrowseError::SelectionCancelled)?
+      .ok_or(errors::BrowseError::SelectionCancelled)?;
+
+    match navigate(&prefix, &items[index]) {
+      Navigation::Prefix(next) => prefix = next,
+      Navigation::Select(action) => return Ok(BrowseSelection { bucket, prefix, action }),
+    }
+  }
+}
+
+/// One folder of a bucket while browsing
+#[derive(Debug, Clone)]
+pub struct BrowseLevel {
+  pub prefix: String,
+  /// Sub folder names, without the trailing delimiter
+  pub folders: Vec<String>,
+  /// Objects stored directly in this folder, and their total size
+  pub objects: usize,
+  pub size: u64,
+}
+
+impl BrowseLevel {
+  /// Choices offered at this level: up, fetch, preview, then each folder
+  pub fn items(&self) -> Vec<String> {
+    let mut items: Vec<String> = Vec::new();
+    if !self.prefix.is_empty() {
+      items.push(UP_ITEM.to_string());
+    }
+    items.push(FETCH_ITEM.to_string());
+    items.push(PREVIEW_ITEM.to_string());
+    items.extend(self.folders.iter().map(|folder| format!("{}{}", folder, DELIMITER)));
+
+    items
+  }
+
+  fn prompt(&self, bucket: &str) -> String {
+    format!(
+      "s3://{}/{} ({} folders, {} objects, {})",
+      bucket, self.prefix, self.folders.len(), self.objects, human_bytes(self.size as f64)
+    )
+  }
+}
+
+/// Where picking an item leads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Navigation {
+  Prefix(String),
+  Select(BrowseAction),
+}
+
+/// List the folders and objects directly beneath a prefix
+pub async fn list_level<C: ObjectStore> (client: &C, bucket: &str, prefix: &str) -> Result<BrowseLevel, errors::BrowseError> {
+  let listing = match list_prefix(client, bucket, prefix).await {
+    Ok(listing) => listing,
+    Err(e) => {
+      log_error!("{}", e);
+      return Err(errors::BrowseError::ListFailed);
+    }
+  };
+
+  Ok(BrowseLevel {
+    prefix: prefix.to_string(),
+    folders: listing.folder_names(),
+    objects: listing.objects.len(),
+    size: listing.objects.iter().filter_map(|object| object.size).map(|size| size.max(0) as u64).sum(),
+  })
+}
+
+/// Apply an item picked at `prefix`
+pub fn navigate (prefix: &str, item: &str) -> Navigation {
+  match item {
+    UP_ITEM => Navigation::Prefix(parent_prefix(prefix)),
+    FETCH_ITEM => Navigation::Select(BrowseAction::Fetch),
+    PREVIEW_ITEM => Navigation::Select(BrowseAction::Preview),
+    folder => Navigation::Prefix(format!("{}{}", prefix, folder)),
+  }
+}
+
+/// `production/api/` -> `production/`
+fn parent_prefix(prefix: &str) -> String {
+  let trimmed = prefix.trim_end_matches(DELIMITER);
+  match trimmed.rfind(DELIMITER) {
+    Some(index) => trimmed[..=index].to_string(),
+    None => String::new(),
+  }
+}
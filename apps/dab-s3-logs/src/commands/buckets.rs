@@ -0,0 +1,40 @@
+use anyhow::Result;
+use aws::s3::store::ObjectStore;
+use chrono::{DateTime, Local};
+
+use crate::storage::catalog::BucketCatalog;
+
+/// List the profile's buckets from S3 and save them as its catalog
+pub async fn refresh_buckets<C: ObjectStore> (client: &C, catalog: &BucketCatalog) -> Result<()> {
+  let buckets = catalog.refresh(client).await?;
+  eprintln!("Saved {} buckets for profile {}", buckets.len(), catalog.profile());
+
+  Ok(())
+}
+
+/// List the buckets in the profile's catalog, listing them from S3 first if it was never saved
+pub async fn list_buckets<C: ObjectStore> (client: &C, catalog: &BucketCatalog) -> Result<()> {
+  let container = match catalog.load()? {
+    Some(container) => container,
+    None => {
+      catalog.refresh(client).await?;
+      catalog.load()?.unwrap_or_default()
+    }
+  };
+
+  let width = container.buckets.iter().map(|bucket| bucket.name.len()).max().unwrap_or(0);
+  for bucket in &container.buckets {
+    println!("{:width$}  {}", bucket.name, bucket.creation_date.as_deref().unwrap_or("-"), width = width);
+  }
+
+  if let Some(refreshed_at) = DateTime::from_timestamp(container.refreshed_at, 0).filter(|_| container.refreshed_at > 0) {
+    eprintln!(
+      "{} buckets for profile {}, listed {}. Run `buckets refresh` to update",
+      container.buckets.len(),
+      catalog.profile(),
+      refreshed_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+    );
+  }
+
+  Ok(())
+}
@@ -0,0 +1,40 @@
+# Complete --profile, --bucket and --prefix values by asking dab-s3-logs, and everything else
+# with the generated completion above
+_dab_s3_logs_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local connection_args=() bucket="" i
+
+    # the profile, region and endpoint already typed decide which buckets and prefixes there are
+    for (( i = 1; i < COMP_CWORD; i++ )); do
+        case "${COMP_WORDS[i]}" in
+            --profile|--region|--endpoint-url)
+                (( i + 1 < COMP_CWORD )) && connection_args+=("${COMP_WORDS[i]}" "${COMP_WORDS[i+1]}")
+                ;;
+            --force-path-style) connection_args+=(--force-path-style) ;;
+            -b|--bucket) (( i + 1 < COMP_CWORD )) && bucket="${COMP_WORDS[i+1]}" ;;
+        esac
+    done
+
+    case "${prev}" in
+        --profile)
+            COMPREPLY=($(dab-s3-logs complete-value profiles -- "${cur}" 2>/dev/null))
+            return 0
+            ;;
+        -b|--bucket)
+            COMPREPLY=($(dab-s3-logs "${connection_args[@]}" complete-value buckets -- "${cur}" 2>/dev/null))
+            return 0
+            ;;
+        -p|--prefix)
+            if [[ -n "${bucket}" ]]; then
+                COMPREPLY=($(dab-s3-logs "${connection_args[@]}" complete-value prefixes --bucket "${bucket}" -- "${cur}" 2>/dev/null))
+                compopt -o nospace 2>/dev/null
+                return 0
+            fi
+            ;;
+    esac
+
+    _dab-s3-logs "$@"
+}
+
+complete -F _dab_s3_logs_dynamic -o bashdefault -o default dab-s3-logs
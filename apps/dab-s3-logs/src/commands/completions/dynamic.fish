@@ -0,0 +1,32 @@
+# Values of --profile, --bucket and --prefix come from dab-s3-logs itself
+function __dab_s3_logs_option_value
+    set -l tokens (commandline -opc)
+    for index in (seq (math (count $tokens) - 1))
+        if contains -- $tokens[$index] $argv
+            echo $tokens[(math $index + 1)]
+        end
+    end
+end
+
+function __dab_s3_logs_complete
+    # the profile, region and endpoint already typed decide which buckets and prefixes there are
+    set -l args
+    for option in --profile --region --endpoint-url
+        set -l value (__dab_s3_logs_option_value $option)
+        if test -n "$value"
+            set args $args $option $value[-1]
+        end
+    end
+    if contains -- --force-path-style (commandline -opc)
+        set args $args --force-path-style
+    end
+
+    switch $argv[1]
+        case prefixes
+            set -l bucket (__dab_s3_logs_option_value -b --bucket)
+            test -n "$bucket"; or return
+            dab-s3-logs $args complete-value prefixes --bucket $bucket[-1] -- (commandline -ct) 2>/dev/null
+        case '*'
+            dab-s3-logs $args complete-value $argv[1] -- (commandline -ct) 2>/dev/null
+    end
+end
@@ -0,0 +1,129 @@
+use std::{io::{self, Write}, time::Duration};
+
+use anyhow::Result;
+use aws::{profiles::get_aws_profiles::get_aws_profiles, s3::{prefixes::list_prefix, store::ObjectStore, DELIMITER}};
+use clap::{Command, ValueEnum};
+use clap_complete::{generate, Shell};
+
+use crate::storage::{cache::FileCache, catalog::BucketCatalog};
+
+/// Prefix listings are only reused briefly, so completion keeps up with new folders
+const PREFIX_COMPLETION_TTL: Duration = Duration::from_secs(60);
+
+const BASH_DYNAMIC: &str = include_str!("dynamic.bash");
+const ZSH_DYNAMIC: &str = include_str!("dynamic.zsh");
+const FISH_DYNAMIC: &str = include_str!("dynamic.fish");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompletionShell {
+  Bash,
+  Zsh,
+  Fish,
+}
+
+/// Values the `complete-value` command the completion scripts call can list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompletionKind {
+  Profiles,
+  Buckets,
+  Prefixes,
+}
+
+/// Write a completion script for the command line to stdout
+pub fn print_completions (shell: CompletionShell, cmd: Command) -> Result<()> {
+  io::stdout().write_all(completion_script(shell, cmd)?.as_bytes())?;
+
+  Ok(())
+}
+
+/// Completion script for the command line, which completes subcommands and flags from the
+/// command itself, and profiles, buckets and prefixes by calling back into `complete-value`
+pub fn completion_script (shell: CompletionShell, mut cmd: Command) -> Result<String> {
+  let bin_name = cmd.get_name().to_string();
+  let mut script = Vec::new();
+  let generator = match shell {
+    CompletionShell::Bash => Shell::Bash,
+    CompletionShell::Zsh => Shell::Zsh,
+    CompletionShell::Fish => Shell::Fish,
+  };
+  generate(generator, &mut cmd, &bin_name, &mut script);
+  let script = String::from_utf8(script)?;
+
+  let script = match shell {
+    CompletionShell::Bash => format!("{}\n{}", script, BASH_DYNAMIC),
+    CompletionShell::Zsh => with_zsh_completers(&script),
+    CompletionShell::Fish => with_fish_completers(&script),
+  };
+
+  Ok(script)
+}
+
+/// Point the value of each `--profile`, `--bucket` and `--prefix` at the dynamic completers,
+/// which are defined before the generated function runs
+fn with_zsh_completers (script: &str) -> String {
+  let script = script
+    .replace(":PROFILE: '", ":PROFILE:__dab_s3_logs_profiles'")
+    .replace(":BUCKET: '", ":BUCKET:__dab_s3_logs_buckets'")
+    .replace(":PREFIX: '", ":PREFIX:__dab_s3_logs_prefixes'");
+
+  match script.split_once('\n') {
+    Some((compdef, rest)) => format!("{}\n\n{}\n{}", compdef, ZSH_DYNAMIC, rest),
+    None => script,
+  }
+}
+
+fn with_fish_completers (script: &str) -> String {
+  let lines: Vec<String> = script.lines()
+    .map(|line| {
+      let kind = if line.contains(" -l profile ") {
+        "profiles"
+      } else if line.contains(" -l bucket ") {
+        "buckets"
+      } else if line.contains(" -l prefix ") {
+        "prefixes"
+      } else {
+        return line.to_string();
+      };
+      format!("{} -f -a \"(__dab_s3_logs_complete {})\"", line, kind)
+    })
+    .collect();
+
+  format!("{}\n{}\n", FISH_DYNAMIC, lines.join("\n"))
+}
+
+/// Profile names from the AWS config and credentials files
+pub fn complete_profiles (current: &str) -> Vec<String> {
+  let profiles = get_aws_profiles().unwrap_or_default();
+
+  starting_with(profiles, current)
+}
+
+/// Bucket names from the profile's saved catalog, without calling S3
+pub fn complete_buckets (catalog: &BucketCatalog, current: &str) -> Vec<String> {
+  starting_with(catalog.saved_names(), current)
+}
+
+/// Folders one level beneath the last complete folder of `current`, listed from S3 and reused for
+/// a minute so repeated tab presses don't list the same level again
+pub async fn complete_prefixes<C: ObjectStore> (client: &C, cache: &FileCache, catalog: &BucketCatalog, bucket: &str, current: &str) -> Result<Vec<String>> {
+  let parent = match current.rfind(DELIMITER) {
+    Some(index) => &current[..index + DELIMITER.len()],
+    None => "",
+  };
+  let key = format!("completion\n{}\n{}\n{}", catalog.profile(), bucket, parent);
+
+  let folders: Vec<String> = match cache.get(&key) {
+    Some(folders) => folders,
+    None => {
+      let folders = list_prefix(client, bucket, parent).await?.folders;
+      cache.put(&key, &folders, Some(PREFIX_COMPLETION_TTL))?;
+      folders
+    }
+  };
+
+  Ok(starting_with(folders, current))
+}
+
+fn starting_with (values: Vec<String>, current: &str) -> Vec<String> {
+  values.into_iter().filter(|value| value.starts_with(current)).collect()
+}
@@ -0,0 +1,43 @@
+use std::fs;
+
+use anyhow::{bail, Result};
+
+use crate::{app::{scheduler::{concurrency_directory, concurrency_file, is_fetch_running, write_concurrency_file}, App}, storage::workspace::Workspace};
+
+/// Ask running fetches to download `limit` objects at once from now on. With a workspace, only
+/// the fetch into it. Without one, every running fetch
+pub fn set_fetch_concurrency (app: &App, limit: usize, workspace: Option<String>) -> Result<()> {
+  let cfg = app.config.lock().unwrap().clone().unwrap();
+
+  if let Some(workspace) = workspace {
+    let workspace = Workspace::new(&cfg.download_directory, &workspace)?;
+    if !is_fetch_running(&cfg.data_directory, &workspace.name) {
+      bail!("No fetch into workspace {} is running. Pass --concurrency to fetch to set its limit", workspace.name);
+    }
+    write_concurrency_file(&concurrency_file(&cfg.data_directory, &workspace.name), limit)?;
+    eprintln!("The fetch into workspace {} will download {} objects at once", workspace.name, limit);
+    return Ok(());
+  }
+
+  let files: Vec<_> = match fs::read_dir(concurrency_directory(&cfg.data_directory)) {
+    Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| path.is_file()).collect(),
+    Err(_) => Vec::new(),
+  };
+  let mut running = 0;
+  for path in &files {
+    let workspace = path.file_name().unwrap_or_default().to_string_lossy();
+    if is_fetch_running(&cfg.data_directory, &workspace) {
+      write_concurrency_file(path, limit)?;
+      running += 1;
+    } else {
+      // left by a fetch that was killed before it could clean up
+      let _ = fs::remove_file(path);
+    }
+  }
+  if running == 0 {
+    bail!("No fetch is running");
+  }
+  eprintln!("{} running fetches will download {} objects at once", running, limit);
+
+  Ok(())
+}
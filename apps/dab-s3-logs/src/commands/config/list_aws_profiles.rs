@@ -0,0 +1,35 @@
+use anyhow::Result;
+use aws::profiles::get_aws_profiles::load_aws_profiles;
+
+/// List the profiles in the shared AWS config and credentials files, with how each gets credentials
+pub fn list_aws_profiles () -> Result<()> {
+  let profiles = load_aws_profiles()?;
+  if profiles.profiles.is_empty() {
+    eprintln!("No AWS profiles found");
+    return Ok(());
+  }
+
+  for profile in profiles.profiles.values() {
+    println!("{}", profile.name);
+    println!("  Credentials: {}", profile.credential_kind());
+    if let Some(region) = profile.region() {
+      println!("  Region: {}", region);
+    }
+    if let Some(role_arn) = profile.role_arn() {
+      println!("  Role: {}", role_arn);
+    }
+    if let Some(source) = profile.source_profile().or(profile.credential_source()) {
+      println!("  Source: {}", source);
+    }
+    if let Some(session) = profiles.sso_session(profile) {
+      println!("  SSO session: {} ({})", session.name, session.start_url().unwrap_or("no start URL"));
+    } else if let Some(start_url) = profile.sso_start_url() {
+      println!("  SSO start URL: {}", start_url);
+    }
+    if let (Some(account_id), Some(role_name)) = (profile.sso_account_id(), profile.sso_role_name()) {
+      println!("  SSO account: {} as {}", account_id, role_name);
+    }
+  }
+
+  Ok(())
+}
@@ -10,9 +10,21 @@ pub fn list_vars () -> Result<()> {
   eprintln!("Output Thread Concurrency: {}", conf.output_thread_concurrency);
   eprintln!("Max Storage: {}", human_bytes(conf.max_storage as f64));
   eprintln!("AWS Config Path: {:?}", conf.aws_config_path);
+  eprintln!("AWS Region: {}", conf.aws_region.as_deref().unwrap_or("(from environment)"));
+  eprintln!("S3 Endpoint URL: {}", conf.s3_endpoint_url.as_deref().unwrap_or("(AWS)"));
+  eprintln!("S3 Force Path Style: {}", conf.s3_force_path_style);
   eprintln!("Download Directory Path: {:?}", conf.download_directory);
   eprintln!("Cache Directory Path: {:?}", conf.cache_directory);
+  eprintln!("Listing Cache TTL: {}s", conf.listing_cache_ttl_secs);
   eprintln!("Home Directory Path: {:?}", conf.home_directory);
+  eprintln!("Date Prefix Format: {}", conf.date_prefix_format);
+  eprintln!("Timestamp Field: {}", conf.timestamp_field);
+  eprintln!("Download Max Attempts: {}", conf.download_max_attempts);
+  eprintln!("Retry Base Delay: {}ms", conf.retry_base_delay_ms);
+  eprintln!("Retry Max Delay: {}ms", conf.retry_max_delay_ms);
+  eprintln!("Multipart Threshold: {}", human_bytes(conf.multipart_threshold as f64));
+  eprintln!("Multipart Part Size: {}", human_bytes(conf.multipart_part_size as f64));
+  eprintln!("Multipart Part Concurrency: {}", conf.multipart_part_concurrency);
   
   Ok(())
 }
\ No newline at end of file
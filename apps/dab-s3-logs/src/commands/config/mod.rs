@@ -1,4 +1,5 @@
+pub mod list_aws_profiles;
 pub mod list_vars;
 pub mod set_download_dir;
 pub mod set_max_storage;
-pub mod select_aws_profile;
\ No newline at end of file
+pub mod select_aws_profile;
@@ -11,7 +11,7 @@ pub fn select_aws_profile() -> Result<()> {
 
 
   let selection_index = FuzzySelect::new()
-    .with_prompt("Select an AWS profile from `~/.aws/config` to use as the default.")
+    .with_prompt("Select an AWS profile to use as the default.")
     .items(&profiles).interact().unwrap();
 
   let selected_profile = profiles[selection_index].clone();
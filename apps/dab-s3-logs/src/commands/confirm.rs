@@ -0,0 +1,30 @@
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use is_terminal::is_terminal;
+
+/// The answer to a prompt before deleting anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+  Confirmed,
+  Declined,
+  /// Nobody can be asked as stdin isn't a terminal, and `--yes` wasn't passed
+  NeedsYes,
+}
+
+/// Ask before doing something destructive. `--yes` confirms up front, and is the only way to when
+/// not running in a terminal
+pub fn confirm(prompt: &str, yes: bool) -> Confirmation {
+  if yes {
+    return Confirmation::Confirmed;
+  }
+  if !is_terminal(std::io::stdin()) {
+    return Confirmation::NeedsYes;
+  }
+
+  let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+    .with_prompt(prompt)
+    .default(false)
+    .interact()
+    .unwrap_or(false);
+
+  if confirmed { Confirmation::Confirmed } else { Confirmation::Declined }
+}
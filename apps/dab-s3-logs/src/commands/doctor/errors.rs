@@ -0,0 +1,12 @@
+use aws::{identity::errors::IdentityError, profiles::errors::GetProfilesError};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DoctorError {
+  #[error("Failed to read AWS profiles: {0}")]
+  ProfilesFailed(#[from] GetProfilesError),
+  #[error("{0} profiles failed the check")]
+  Failed(usize),
+  #[error("Credentials of {profile} don't work: {error}\n{remediation}")]
+  CredentialsFailed { profile: String, error: IdentityError, remediation: String },
+}
@@ -0,0 +1,81 @@
+use aws::{client::ClientOptions, identity::{check_identity, remediation, CallerIdentity}, profiles::{get_aws_profiles::load_aws_profiles, profile::AwsProfiles}};
+use chrono::{DateTime, Local, Utc};
+use tokio::task::JoinSet;
+
+pub mod errors;
+
+/// Label for the credentials found without a profile, from the environment or instance metadata
+const NO_PROFILE: &str = "(no profile)";
+
+/// Check the credentials of one profile, or of every profile in the AWS config and credentials
+/// files, printing who they belong to, when they expire, and how to fix those that don't work
+pub async fn check_profiles (options: &ClientOptions, profile: Option<String>) -> Result<(), errors::DoctorError> {
+  let profiles = load_aws_profiles()?;
+  let names = match profile {
+    Some(profile) => vec![Some(profile)],
+    None if profiles.profiles.is_empty() => vec![None],
+    None => profiles.names().into_iter().map(Some).collect(),
+  };
+
+  let mut checks = JoinSet::new();
+  for (position, name) in names.into_iter().enumerate() {
+    let options = ClientOptions { profile: name.clone(), ..options.clone() };
+    checks.spawn(async move { (position, name, check_identity(&options).await) });
+  }
+  let mut results = Vec::new();
+  let mut failed = 0;
+  while let Some(result) = checks.join_next().await {
+    match result {
+      Ok(result) => results.push(result),
+      Err(e) => {
+        failed += 1;
+        eprintln!("A profile check didn't finish: {}", e);
+      }
+    }
+  }
+  results.sort_by_key(|(position, _, _)| *position);
+
+  for (_, name, result) in results {
+    println!("{}", name.as_deref().unwrap_or(NO_PROFILE));
+    match result {
+      Ok(identity) => print_identity(&identity),
+      Err(e) => {
+        failed += 1;
+        println!("  Failed: {}", e);
+        println!("  Fix: {}", remediation(&e, name.as_deref(), &profiles));
+      }
+    }
+  }
+
+  match failed {
+    0 => Ok(()),
+    failed => Err(errors::DoctorError::Failed(failed)),
+  }
+}
+
+/// Check the credentials a command is about to use, so an expired session is reported up front
+/// with its fix rather than as failures deep in listing and downloading
+pub async fn check_credentials (options: &ClientOptions) -> Result<CallerIdentity, errors::DoctorError> {
+  match check_identity(options).await {
+    Ok(identity) => Ok(identity),
+    Err(error) => {
+      let profiles = load_aws_profiles().unwrap_or_else(|_| AwsProfiles::default());
+      let remediation = remediation(&error, options.profile.as_deref(), &profiles);
+      let profile = options.profile.clone().unwrap_or_else(|| NO_PROFILE.to_string());
+      Err(errors::DoctorError::CredentialsFailed { profile, error, remediation })
+    }
+  }
+}
+
+fn print_identity (identity: &CallerIdentity) {
+  println!("  Account: {}", identity.account.as_deref().unwrap_or("-"));
+  println!("  ARN: {}", identity.arn.as_deref().unwrap_or("-"));
+  match identity.expires_at {
+    Some(expires_at) => {
+      let expires_at = DateTime::<Utc>::from(expires_at);
+      let remaining = expires_at - Utc::now();
+      println!("  Expires: {} (in {}h {}m)", expires_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"), remaining.num_hours(), remaining.num_minutes() % 60);
+    }
+    None => println!("  Expires: never, long-term access keys"),
+  }
+}
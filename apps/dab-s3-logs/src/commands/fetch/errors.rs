@@ -4,10 +4,30 @@ use thiserror::Error;
 pub enum FetchError {
   #[error("Not enough storage space available")]
   NotEnoughStorage,
-  #[error("Failed to delete logs")]
-  LogDeletionFailed,
+  #[error("Failed to evict logs")]
+  EvictionFailed,
+  #[error("Not enough storage space available without evicting logs")]
+  EvictionDeclined,
   #[error("Failed to download logs")]
   DownloadFailed,
+  #[error("Failed to read download index")]
+  IndexFailed,
+  #[error("Failed to list objects")]
+  ListFailed,
+  #[error("Failed to open workspace")]
+  WorkspaceFailed,
+  #[error("Partial fetch: {failed} of {total} objects failed to download")]
+  PartialFetch { failed: usize, total: usize },
+}
+
+impl FetchError {
+  /// Process exit status: 2 when some objects were downloaded but others failed, 1 for anything else
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      FetchError::PartialFetch { .. } => 2,
+      _ => 1,
+    }
+  }
 }
 
 #[derive(Error, Debug)]
@@ -1,77 +1,154 @@
-use aws_sdk_s3::Client;
-use dialoguer::{theme::ColorfulTheme, Confirm};
+use std::{collections::HashSet, path::PathBuf};
+
+use aws::s3::store::ObjectStore;
+use chrono::DateTime;
 use human_bytes::human_bytes;
-use is_terminal::is_terminal;
-use log::error as log_error;
+use log::{info, error as log_error};
 use anyhow::Result;
-use aws::s3::list_keys;
-use crate::{app::{download, App}, storage::get_used_storage};
+use crate::{app::{download::{self, DownloadReport}, query_builder::QueryBuilder, App}, commands::confirm::{confirm, Confirmation}, storage::{get_used_storage, index::DownloadIndex, manager::{EvictionPlan, StorageManager}, workspace::{Manifest, Workspace}}};
 
 pub mod errors;
 
-use super::reset;
-
 const MAX_STORAGE_MSG: &str = "Not enough storage to download logs.";
-const STORAGE_PROMPT: &str = "Would you like to delete existing downloaded logs?";
+const EVICTION_PROMPT: &str = "Evict the least recently used logs listed above?";
+
+/// Where a fetch is written and how it is recorded in the workspace manifest
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+  /// Workspace to download into, named after the bucket and prefix when not set
+  pub workspace: Option<String>,
+  /// AWS profile the fetch is made with
+  pub profile: Option<String>,
+  /// Downloads to run at once, `download_thread_concurrency` from the config when not set
+  pub concurrency: Option<usize>,
+  /// Evict logs to make room without asking, which is required when not running in a terminal
+  pub yes: bool,
+}
 
-/// Fetch logs from S3
-pub async fn fetch (client: &Client, app: &App, bucket: String, prefix: String) -> Result<Vec<std::string::String>, errors::FetchError> {
-  let query = list_keys(client, &bucket, &prefix).await.unwrap();
-  let used_storage = get_used_storage(app).unwrap();
+/// Fetch logs from S3 into a workspace, skipping objects that are already downloaded and unchanged
+pub async fn fetch<C: ObjectStore> (client: &C, app: &App, query_builder: QueryBuilder, options: FetchOptions) -> Result<Vec<std::string::String>, errors::FetchError> {
+  let cfg = app.config.lock().unwrap().clone().unwrap();
+
+  let mut index = match DownloadIndex::load(&cfg.data_directory) {
+    Ok(index) => index,
+    Err(e) => {
+      log_error!("{}", e);
+      return Err(errors::FetchError::IndexFailed);
+    }
+  };
 
-  let max_storage = {
-    let cfg = app.config.lock().unwrap().clone().unwrap();
-    cfg.max_storage
+  let query = match query_builder.build_merged(client).await {
+    Ok(query) => query,
+    Err(e) => {
+      log_error!("{}", e);
+      return Err(errors::FetchError::ListFailed);
+    }
   };
+  let workspace_name = options.workspace.unwrap_or_else(|| Workspace::default_name(&query.bucket, &query.prefix));
+  let workspace = match Workspace::new(&cfg.download_directory, &workspace_name) {
+    Ok(workspace) => workspace,
+    Err(e) => {
+      log_error!("{}", e);
+      return Err(errors::FetchError::WorkspaceFailed);
+    }
+  };
+  let manifest = Manifest::new(&workspace.name, &query, options.profile, query_builder.get_time_range());
+
+  match index.adopt_legacy(&query, &workspace.name, &cfg.download_directory) {
+    Ok(0) => {}
+    Ok(adopted) => info!("Moved {} objects downloaded before workspaces into workspace {}", adopted, workspace.name),
+    Err(e) => {
+      log_error!("{}", e);
+      return Err(errors::FetchError::IndexFailed);
+    }
+  }
+
+  // files this query needs are never evicted to make room for the rest of it
+  let keep: HashSet<PathBuf> = query.objects.keys().map(|key| workspace.path.join(key)).collect();
+  let query = index.pending(query, &workspace.name, &cfg.download_directory);
+
+  if query.objects.is_empty() {
+    info!("All objects are already downloaded");
+    eprintln!("All objects under {} are already downloaded to workspace {}", query.prefix, workspace.name);
+    return save_manifest(&workspace, manifest).map(|_| Vec::new());
+  }
 
-  let available_storage = max_storage as i64 - used_storage as i64;
+  let used_storage = get_used_storage(app).unwrap();
+  let manager = StorageManager::new(&cfg.download_directory, cfg.max_storage);
+  let available_storage = manager.available(used_storage);
 
   let mut storage_messages: Vec<String> = Vec::new();
   storage_messages.push(format!("Storage space required for query download: {}", human_bytes(query.size as f64)));
   storage_messages.push(format!("Currently used space: {}", human_bytes(used_storage as f64)));
   storage_messages.push(format!("Available storage space: {}", human_bytes(available_storage as f64)));
 
-  if query.size as i64 > max_storage as i64 {
+  if query.size > cfg.max_storage {
     log_error!("{}", MAX_STORAGE_MSG);
     for msg in storage_messages {
       log_error!("{}", msg);
     }
     return Err(errors::FetchError::NotEnoughStorage);
   }
-  
-  if query.size as i64 > available_storage {
-    let mut should_delete_logs = true;
 
-    if is_terminal(std::io::stdout()) {
+  if query.size > available_storage {
+    let plan = match manager.plan_eviction(&index, used_storage, query.size, &keep) {
+      Ok(plan) => plan,
+      Err(e) => {
+        log_error!("Failed to plan eviction: {}", e);
+        return Err(errors::FetchError::EvictionFailed);
+      }
+    };
 
+    if available_storage + plan.size < query.size {
       log_not_enough_storage_space_messages(storage_messages);
+      return Err(errors::FetchError::NotEnoughStorage);
+    }
 
-      let confirmed = Confirm::with_theme(&ColorfulTheme::default())
-        .with_prompt(STORAGE_PROMPT)
-        .interact()
-        .unwrap();
+    // the plan is always shown, so nothing is deleted without a record of what it was
+    log_not_enough_storage_space_messages(storage_messages);
+    log_eviction_plan(&plan);
 
-      should_delete_logs = confirmed;
+    match confirm(&format!("{} ({} files, {})", EVICTION_PROMPT, plan.files.len(), human_bytes(plan.size as f64)), options.yes) {
+      Confirmation::Confirmed => {}
+      Confirmation::Declined => return Err(errors::FetchError::EvictionDeclined),
+      Confirmation::NeedsYes => {
+        eprintln!("Pass --yes to evict these logs when not running in a terminal");
+        return Err(errors::FetchError::EvictionDeclined);
+      }
     }
 
-    if should_delete_logs {
-      let result = reset::delete_downloaded_logs().await;
-      match result {
-        Ok(_) => {}
-        Err(e) => {
-          log_error!("Failed to delete logs: {:?}", e);
-          return Err(errors::FetchError::LogDeletionFailed);
-        }
+    match manager.evict(&plan, &mut index) {
+      Ok(freed) => info!("Freed {} by evicting least recently used logs", human_bytes(freed as f64)),
+      Err(e) => {
+        log_error!("Failed to evict logs: {}", e);
+        return Err(errors::FetchError::EvictionFailed);
       }
-    } else {
-      return Err(errors::FetchError::NotEnoughStorage);
     }
   }
 
-  let result = download::download_query_results(&query, bucket, app, client).await;
+  if let Err(e) = workspace.create() {
+    log_error!("{}", e);
+    return Err(errors::FetchError::WorkspaceFailed);
+  }
+
+  let bucket = query.bucket.clone();
+  let concurrency = options.concurrency.unwrap_or(cfg.download_thread_concurrency);
+  let result = download::download_query_results(&query, bucket, app, client, &mut index, &workspace, concurrency).await;
   match result {
-    Ok(files) => {
-      Ok(files)
+    Ok(report) if report.failures.is_empty() => {
+      save_manifest(&workspace, manifest)?;
+      eprintln!("Downloaded {} objects to workspace {}", report.files.len(), workspace.name);
+      Ok(report.files)
+    }
+    Ok(report) => {
+      // only what actually arrived belongs in the manifest
+      let mut manifest = manifest;
+      let failed: HashSet<&str> = report.failures.iter().map(|failure| failure.key.as_str()).collect();
+      manifest.objects.retain(|object| !failed.contains(object.key.as_str()));
+      save_manifest(&workspace, manifest)?;
+
+      log_failure_report(&report);
+      Err(errors::FetchError::PartialFetch { failed: report.failures.len(), total: report.failures.len() + report.files.len() })
     }
     Err(e) => {
       log_error!("Failed to download logs: {:?}", e);
@@ -88,16 +165,66 @@ fn log_not_enough_storage_space_messages(msgs: Vec<String>) {
   }
 }
 
+/// Record the fetch in the workspace manifest, keeping objects from earlier fetches into it
+fn save_manifest(workspace: &Workspace, manifest: Manifest) -> Result<(), errors::FetchError> {
+  let result = workspace.load_manifest().and_then(|existing| {
+    let manifest = match existing {
+      Some(mut existing) => {
+        existing.update(manifest);
+        existing
+      }
+      None => manifest,
+    };
+    workspace.save_manifest(&manifest)
+  });
+
+  result.map_err(|e| {
+    log_error!("Failed to save workspace manifest: {}", e);
+    errors::FetchError::WorkspaceFailed
+  })
+}
+
+fn log_failure_report(report: &DownloadReport) {
+  eprintln!("Downloaded {} objects, {} failed:", report.files.len(), report.failures.len());
+  for failure in &report.failures {
+    eprintln!("  {}: {} (after {} attempts)", failure.key, failure.reason, failure.attempts);
+  }
+}
+
+fn log_eviction_plan(plan: &EvictionPlan) {
+  eprintln!("These logs will be evicted, least recently used first:");
+  for file in &plan.files {
+    let last_used = file.last_used
+      .and_then(|secs| DateTime::from_timestamp(secs, 0))
+      .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+      .unwrap_or_else(|| "unknown".to_string());
+    eprintln!("  {:<16}  {:>10}  {}", last_used, human_bytes(file.size as f64), file.path.display());
+  }
+}
+
 /// Preview query results before fetching
-pub async fn preview (client: &Client, bucket: String, prefix: String) -> Result<(), errors::PreviewError>{
-  let result = list_keys(client, &bucket, &prefix).await;
+pub async fn preview<C: ObjectStore> (client: &C, query_builder: QueryBuilder) -> Result<(), errors::PreviewError>{
+  let result = query_builder.build(client).await;
 
   match result {
-    Ok(query) => {
-      println!("Bucket: {}", query.bucket);
-      println!("Prefix: {}", query.prefix);
-      println!("Size: {}", human_bytes(query.size as f64));
-      println!("Objects: {}", query.objects.len());
+    Ok(queries) => {
+      let mut total_size = 0;
+      let mut total_objects = 0;
+      for query in &queries {
+        println!("Bucket: {}", query.bucket);
+        println!("Prefix: {}", query.prefix);
+        println!("Size: {}", human_bytes(query.size as f64));
+        println!("Objects: {}", query.objects.len());
+        total_size += query.size;
+        total_objects += query.objects.len();
+      }
+      if let Some(time_range) = query_builder.get_time_range() {
+        println!("Time range: {} to {}", time_range.start.to_rfc3339(), time_range.end.to_rfc3339());
+      }
+      if queries.len() > 1 {
+        println!("Total size: {}", human_bytes(total_size as f64));
+        println!("Total objects: {}", total_objects);
+      }
       Ok(())
     }
     Err(e) => {
@@ -105,4 +232,4 @@ pub async fn preview (client: &Client, bucket: String, prefix: String) -> Result
       Err(errors::PreviewError::PreviewFailed)
     }
   }
-}
\ No newline at end of file
+}
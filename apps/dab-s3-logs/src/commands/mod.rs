@@ -1,5 +1,13 @@
+pub mod browse;
+pub mod buckets;
+pub mod completions;
+pub mod concurrency;
 pub mod config;
+pub mod confirm;
+pub mod doctor;
 pub mod fetch;
 pub mod output;
 pub mod reset;
-
+pub mod stream;
+pub mod verify;
+pub mod workspaces;
@@ -1,27 +1,72 @@
 
 
-use std::path::Path;
+use std::{path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, Arc}};
 
-use anyhow::Result;
-use log::debug;
+use anyhow::{anyhow, Result};
+use glob::Pattern;
+use log::{debug, error as log_error, warn};
 use tokio::sync::mpsc;
 
-use crate::{app::App, output::stdout, storage::get_all_files};
+use crate::{app::App, config::ApplicationConfig, output::{errors::OutputError, stdout, OutputOptions}, storage::{get_all_files, index::DownloadIndex, workspace::Workspace}};
+
+/// Write downloaded log files to stdout, from the workspaces matching the selector or from everywhere
+pub async fn output_files (app: &App, options: OutputOptions, workspace: Option<String>) -> Result<()> {
+  let cfg = app.config.lock().unwrap().clone().unwrap();
+  let files = match workspace {
+    Some(selector) => {
+      let selector = Pattern::new(&selector)?;
+      let workspaces = Workspace::select(&cfg.download_directory, Some(&selector))?;
+      if workspaces.is_empty() {
+        return Err(anyhow!("No workspace matches `{}`", selector));
+      }
+      let mut files = Vec::new();
+      for workspace in workspaces {
+        files.extend(workspace.files()?);
+      }
+      files
+    }
+    None => get_all_files(app).unwrap(),
+  };
+  let paths: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
+
+  // a closed stdout, e.g. `| head`, stops the output without failing it
+  match stdout::output_header(&options) {
+    Err(e) if stdout::is_stdout_closed(&e) => return Ok(()),
+    result => result?,
+  }
+
+  if let Some(timestamp_field) = options.sort_by.clone() {
+    let sorted_paths = paths.clone();
+    match tokio::task::spawn_blocking(move || stdout::output_sorted(&sorted_paths, &timestamp_field, &options)).await? {
+      Ok(()) | Err(OutputError::StdoutClosed) => {}
+      Err(e) => return Err(e.into()),
+    }
+    record_access(&cfg, &paths);
+    return Ok(());
+  }
 
-pub async fn output_files (app: &App) -> Result<()> {
-  let cfg = app.config.lock().unwrap().clone();
-  let files = get_all_files(app).unwrap();
   let num_files = files.len();
-  let files_per_thread = num_files / cfg.unwrap().output_thread_concurrency;
+  let files_per_thread = (num_files / cfg.output_thread_concurrency).max(1);
+  let options = Arc::new(options);
 
   let nested_files = files.chunks(files_per_thread).map(|x| x.to_vec()).collect::<Vec<Vec<String>>>();
 
   let (tx, mut rx) = mpsc::channel::<String>(128);
+  let stdout_closed = Arc::new(AtomicBool::new(false));
   for slice in nested_files {
       let tx_clone = tx.clone();
+      let options = options.clone();
+      let stdout_closed = stdout_closed.clone();
       tokio::spawn(async move {
           for file in slice {
-              stdout::output_logfile(Path::new(file.as_str()));
+              if stdout_closed.load(Ordering::Relaxed) {
+                  break;
+              }
+              match stdout::output_logfile(Path::new(file.as_str()), &options) {
+                  Ok(()) => {}
+                  Err(OutputError::StdoutClosed) => stdout_closed.store(true, Ordering::Relaxed),
+                  Err(e) => log_error!("Failed to output {}: {}", file, e),
+              }
               tx_clone.send(file).await.unwrap();
           }
       });
@@ -32,5 +77,19 @@ pub async fn output_files (app: &App) -> Result<()> {
       debug!("processed file: {}", message);
   }
 
+  record_access(&cfg, &paths);
+
   Ok(())
+}
+
+/// Mark the files as recently used so eviction removes them last
+fn record_access (cfg: &ApplicationConfig, paths: &[PathBuf]) {
+  let result = DownloadIndex::load(&cfg.data_directory).and_then(|mut index| {
+    index.record_access(&cfg.download_directory, paths);
+    index.compact()
+  });
+
+  if let Err(e) = result {
+    warn!("Failed to record access in download index: {}", e);
+  }
 }
\ No newline at end of file
@@ -0,0 +1,15 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ResetError {
+  #[error("Invalid pattern: {0}")]
+  InvalidPattern(#[from] glob::PatternError),
+  #[error(transparent)]
+  InvalidAge(#[from] crate::app::errors::TimeRangeError),
+  #[error("Failed to read download index")]
+  IndexFailed,
+  #[error("Failed to delete logs")]
+  DeleteFailed,
+  #[error("Logs were not deleted")]
+  Declined,
+}
@@ -0,0 +1,131 @@
+use chrono::Utc;
+use glob::Pattern;
+use human_bytes::human_bytes;
+use log::error as log_error;
+
+use crate::{app::{scheduler::is_fetch_running, time_range::parse_time, App}, commands::confirm::{confirm, Confirmation}, storage::{cache::FileCache, index::DownloadIndex, integrity::IntegrityChecker, listings::LISTING_CACHE_DIRECTORY, manager::{StorageManager, StorageScope}}};
+
+pub mod errors;
+
+/// Which downloaded logs to delete. Everything when nothing is set
+#[derive(Debug, Clone, Default)]
+pub struct ResetOptions {
+  /// Glob matched against workspace names
+  pub workspace: Option<String>,
+  pub bucket: Option<String>,
+  /// Glob matched against the start of each object key
+  pub prefix: Option<String>,
+  /// Only logs last fetched or read before this, e.g. `7d` or `2024-05-01`
+  pub older_than: Option<String>,
+  /// List what would be deleted without deleting anything
+  pub dry_run: bool,
+  /// Delete without asking, required when not running in a terminal
+  pub yes: bool,
+}
+
+/// Delete downloaded logs from the configured download directory, and the saved S3 listings
+/// as well when nothing narrows the reset down
+pub async fn delete_downloaded_logs (app: &App, options: ResetOptions) -> Result<(), errors::ResetError> {
+  let cfg = app.config.lock().unwrap().clone().unwrap();
+  // listings of finished days are saved for good, so this is the way to be rid of them
+  let clear_listings = options.workspace.is_none() && options.bucket.is_none() && options.prefix.is_none() && options.older_than.is_none();
+
+  let scope = StorageScope {
+    workspace: options.workspace.as_deref().map(Pattern::new).transpose()?,
+    bucket: options.bucket,
+    prefix: options.prefix.as_deref().map(StorageScope::prefix_pattern).transpose()?,
+    older_than: options.older_than.as_deref()
+      .map(|older_than| parse_time(older_than, Utc::now()))
+      .transpose()?
+      .map(|cutoff| cutoff.timestamp()),
+  };
+
+  let mut index = match DownloadIndex::load(&cfg.data_directory) {
+    Ok(index) => index,
+    Err(e) => {
+      log_error!("{}", e);
+      return Err(errors::ResetError::IndexFailed);
+    }
+  };
+
+  let manager = StorageManager::new(&cfg.download_directory, cfg.max_storage);
+  let mut plan = match manager.plan_scoped(&index, &scope) {
+    Ok(plan) => plan,
+    Err(e) => {
+      log_error!("{}", e);
+      return Err(errors::ResetError::DeleteFailed);
+    }
+  };
+
+  // a full reset also clears out what interrupted downloads left behind, but not the downloads
+  // of a fetch that's still running
+  if clear_listings {
+    let checker = IntegrityChecker::new(&cfg.download_directory);
+    let incomplete = match checker.incomplete(None) {
+      Ok(incomplete) => incomplete,
+      Err(e) => {
+        log_error!("{}", e);
+        return Err(errors::ResetError::DeleteFailed);
+      }
+    };
+    let incomplete = incomplete.into_iter()
+      .filter(|path| !checker.workspace_of(path).is_some_and(|workspace| is_fetch_running(&cfg.data_directory, &workspace)))
+      .collect();
+    match manager.plan_files(incomplete) {
+      Ok(incomplete) => {
+        plan.size += incomplete.size;
+        plan.files.extend(incomplete.files);
+      }
+      Err(e) => {
+        log_error!("{}", e);
+        return Err(errors::ResetError::DeleteFailed);
+      }
+    }
+  }
+
+  for file in &plan.files {
+    println!("{}", file.path.display());
+  }
+  println!("Would free {} across {} files", human_bytes(plan.size as f64), plan.files.len());
+  if clear_listings {
+    println!("Would clear saved S3 listings");
+  }
+  if options.dry_run {
+    return Ok(());
+  }
+
+  let prompt = match (plan.files.is_empty(), clear_listings) {
+    (true, false) => return Ok(()),
+    (true, true) => "Clear saved S3 listings?",
+    (false, true) => "Delete these logs and clear saved S3 listings?",
+    (false, false) => "Delete these logs?",
+  };
+  match confirm(prompt, options.yes) {
+    Confirmation::Confirmed => {}
+    Confirmation::Declined => return Err(errors::ResetError::Declined),
+    Confirmation::NeedsYes => {
+      eprintln!("Pass --yes to reset when not running in a terminal");
+      return Err(errors::ResetError::Declined);
+    }
+  }
+
+  if !plan.files.is_empty() {
+    match manager.evict(&plan, &mut index) {
+      Ok(freed) => println!("Freed {} across {} files", human_bytes(freed as f64), plan.files.len()),
+      Err(e) => {
+        log_error!("{}", e);
+        return Err(errors::ResetError::DeleteFailed);
+      }
+    }
+  }
+
+  if clear_listings {
+    if let Err(e) = FileCache::new(&cfg.cache_directory.join(LISTING_CACHE_DIRECTORY)).clear() {
+      log_error!("{}", e);
+      return Err(errors::ResetError::DeleteFailed);
+    }
+    println!("Cleared saved S3 listings");
+  }
+
+  Ok(())
+}
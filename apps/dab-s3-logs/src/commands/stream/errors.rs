@@ -0,0 +1,21 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StreamError {
+  #[error("Failed to list objects")]
+  ListFailed,
+  #[error("Failed to stream logs")]
+  StreamFailed,
+  #[error("Partial stream: {failed} of {total} objects failed to stream")]
+  PartialStream { failed: usize, total: usize },
+}
+
+impl StreamError {
+  /// Process exit status: 2 when some objects were streamed but others failed, 1 for anything else
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      StreamError::PartialStream { .. } => 2,
+      _ => 1,
+    }
+  }
+}
@@ -0,0 +1,48 @@
+use aws::s3::store::ObjectStore;
+use log::error as log_error;
+
+use crate::{app::{query_builder::QueryBuilder, stream::{self, StreamReport}, App}, output::{stdout, OutputOptions}};
+
+pub mod errors;
+
+/// Write logs from S3 straight to stdout without downloading them
+pub async fn stream<C: ObjectStore> (client: &C, app: &App, query_builder: QueryBuilder, options: OutputOptions, concurrency: Option<usize>) -> Result<(), errors::StreamError> {
+  let cfg = app.config.lock().unwrap().clone().unwrap();
+
+  let query = match query_builder.build_merged(client).await {
+    Ok(query) => query,
+    Err(e) => {
+      log_error!("{}", e);
+      return Err(errors::StreamError::ListFailed);
+    }
+  };
+
+  if let Err(e) = stdout::output_header(&options) {
+    if stdout::is_stdout_closed(&e) {
+      return Ok(());
+    }
+    log_error!("{}", e);
+    return Err(errors::StreamError::StreamFailed);
+  }
+
+  let concurrency = concurrency.unwrap_or(cfg.output_thread_concurrency);
+  match stream::stream_query_results(&query, app, client, options, concurrency).await {
+    // whatever read the output stopped early, e.g. `| head`, which isn't a failure
+    Ok(report) if report.failures.is_empty() || report.stdout_closed => Ok(()),
+    Ok(report) => {
+      log_failure_report(&report);
+      Err(errors::StreamError::PartialStream { failed: report.failures.len(), total: report.failures.len() + report.objects })
+    }
+    Err(e) => {
+      log_error!("Failed to stream logs: {:?}", e);
+      Err(errors::StreamError::StreamFailed)
+    }
+  }
+}
+
+fn log_failure_report(report: &StreamReport) {
+  eprintln!("Streamed {} objects, {} failed:", report.objects, report.failures.len());
+  for failure in &report.failures {
+    eprintln!("  {}: {} (after {} attempts)", failure.key, failure.reason, failure.attempts);
+  }
+}
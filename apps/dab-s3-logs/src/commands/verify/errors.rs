@@ -0,0 +1,15 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum VerifyError {
+  #[error("Invalid pattern: {0}")]
+  InvalidPattern(#[from] glob::PatternError),
+  #[error("Failed to read download index")]
+  IndexFailed,
+  #[error("Failed to verify logs")]
+  VerifyFailed,
+  #[error("Failed to remove logs that failed verification")]
+  RemoveFailed,
+  #[error("{0} files failed verification")]
+  Failed(usize),
+}
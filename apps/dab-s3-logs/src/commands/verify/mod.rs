@@ -0,0 +1,74 @@
+use glob::Pattern;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::error as log_error;
+
+use crate::{app::{scheduler::is_fetch_running, App}, storage::{index::DownloadIndex, integrity::{IntegrityChecker, Problem}}};
+
+pub mod errors;
+
+const PROGRESS_BAR_TEMPLATE: &str = "[{elapsed_precise}] {bar:40.cyan/blue} {bytes}/{total_bytes} {msg}";
+
+/// Which downloaded logs to verify and what to do with those that fail
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+  /// Glob matched against workspace names
+  pub workspace: Option<String>,
+  /// Delete files that fail verification so the next fetch downloads them again
+  pub remove: bool,
+}
+
+/// Check downloaded logs against the size and MD5 recorded when they were downloaded, and look for
+/// downloads left incomplete by an interrupted fetch, skipping those of fetches still running. Fails when any are damaged, unless they're removed
+pub async fn verify_downloaded_logs (app: &App, options: VerifyOptions) -> Result<(), errors::VerifyError> {
+  let cfg = app.config.lock().unwrap().clone().unwrap();
+  let selector = options.workspace.as_deref().map(Pattern::new).transpose()?;
+
+  let mut index = match DownloadIndex::load(&cfg.data_directory) {
+    Ok(index) => index,
+    Err(e) => {
+      log_error!("{}", e);
+      return Err(errors::VerifyError::IndexFailed);
+    }
+  };
+
+  let checker = IntegrityChecker::new(&cfg.download_directory);
+  let total = checker.entries(&index, selector.as_ref()).iter().map(|entry| entry.size).sum();
+  let progress_bar = ProgressBar::new(total);
+  progress_bar.set_style(ProgressStyle::with_template(PROGRESS_BAR_TEMPLATE).unwrap());
+
+  let mut report = match checker.verify(&index, selector.as_ref(), |entry| progress_bar.inc(entry.size)) {
+    Ok(report) => report,
+    Err(e) => {
+      progress_bar.abandon();
+      log_error!("{}", e);
+      return Err(errors::VerifyError::VerifyFailed);
+    }
+  };
+  progress_bar.finish_and_clear();
+
+  // a fetch that's still running is writing these, they weren't left by an interrupted one
+  report.findings.retain(|finding| {
+    finding.problem != Problem::Incomplete
+      || !checker.workspace_of(&finding.path).is_some_and(|workspace| is_fetch_running(&cfg.data_directory, &workspace))
+  });
+
+  for finding in &report.findings {
+    println!("{}: {}", finding.path.display(), finding.problem);
+  }
+  println!("{} files verified, {} checked by size only, {} failed", report.verified, report.size_only, report.findings.len());
+
+  if report.findings.is_empty() {
+    return Ok(());
+  }
+
+  if options.remove {
+    if let Err(e) = checker.remove(&report.findings, &mut index) {
+      log_error!("{}", e);
+      return Err(errors::VerifyError::RemoveFailed);
+    }
+    println!("Removed {} files, fetch again to download them", report.findings.len());
+    return Ok(());
+  }
+
+  Err(errors::VerifyError::Failed(report.findings.len()))
+}
@@ -0,0 +1,52 @@
+use anyhow::Result;
+use chrono::DateTime;
+use fs_extra::dir::get_size;
+use glob::Pattern;
+use human_bytes::human_bytes;
+
+use crate::{app::App, storage::workspace::Workspace};
+
+/// List the workspaces matching the selector, or every workspace
+pub fn list_workspaces (app: &App, selector: Option<String>) -> Result<()> {
+  let cfg = app.config.lock().unwrap().clone().unwrap();
+  let selector = selector.as_deref().map(Pattern::new).transpose()?;
+
+  let workspaces = Workspace::select(&cfg.download_directory, selector.as_ref())?;
+  let unmanaged = Workspace::select_unmanaged(&cfg.download_directory, selector.as_ref())?;
+  if workspaces.is_empty() && unmanaged.is_empty() {
+    eprintln!("No workspaces found in {:?}", cfg.download_directory);
+    return Ok(());
+  }
+
+  for workspace in workspaces {
+    let manifest = match workspace.load_manifest()? {
+      Some(manifest) => manifest,
+      None => continue,
+    };
+    let on_disk = get_size(&workspace.path).unwrap_or(0);
+    let updated = DateTime::from_timestamp(manifest.updated_at, 0)
+      .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+      .unwrap_or_default();
+
+    println!("{}", workspace.name);
+    println!("  Bucket: {}", manifest.bucket);
+    println!("  Prefix: {}", manifest.prefix);
+    if let Some(profile) = &manifest.profile {
+      println!("  Profile: {}", profile);
+    }
+    if let (Some(since), Some(until)) = (&manifest.since, &manifest.until) {
+      println!("  Time range: {} to {}", since, until);
+    }
+    println!("  Objects: {} ({})", manifest.objects.len(), human_bytes(manifest.size() as f64));
+    println!("  On disk: {}", human_bytes(on_disk as f64));
+    println!("  Updated: {}", updated);
+  }
+
+  // folders nothing was fetched into through a workspace, e.g. downloads from before workspaces
+  for folder in unmanaged {
+    println!("{} (unmanaged)", folder.name);
+    println!("  On disk: {}", human_bytes(get_size(&folder.path).unwrap_or(0) as f64));
+  }
+
+  Ok(())
+}
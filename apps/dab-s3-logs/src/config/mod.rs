@@ -1,4 +1,6 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
+
+use aws::{client::ClientOptions, s3::{download::DownloadOptions, retry::RetryPolicy}};
 
 use log::debug;
 use serde_derive::{Deserialize, Serialize};
@@ -6,8 +8,12 @@ use bytesize::ByteSize;
 use dirs;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct ApplicationConfig {
   pub aws_profile: Option<String>,
+  pub aws_region: Option<String>,
+  pub s3_endpoint_url: Option<String>,
+  pub s3_force_path_style: bool,
   pub download_thread_concurrency: usize,
   pub output_thread_concurrency: usize,
   pub download_directory: PathBuf,
@@ -16,6 +22,15 @@ pub struct ApplicationConfig {
   pub data_directory: PathBuf,
   pub home_directory: PathBuf,
   pub max_storage: u64,
+  pub date_prefix_format: String,
+  pub timestamp_field: String,
+  pub download_max_attempts: u32,
+  pub retry_base_delay_ms: u64,
+  pub retry_max_delay_ms: u64,
+  pub multipart_threshold: u64,
+  pub multipart_part_size: u64,
+  pub multipart_part_concurrency: usize,
+  pub listing_cache_ttl_secs: u64,
 }
 
 pub const APPLICATION_NAME: &str = "dab-s3-logs"; /// "dab-s3-logs"
@@ -23,7 +38,16 @@ pub const APPLICATION_CONFIG_NAME: &str = "application"; /// "application"
 const DEFAULT_AWS_CONFIG_PATH_SUFFIX: &str = ".aws/config"; /// .aws/config
 const DEFAULT_DOWNLOAD_THREAD_CONCURRENCY: usize = 100; /// 100 tokio async threads
 const DEFAULT_OUTPUT_THREAD_CONCURRENCY: usize = 10; /// 10 tokio async threads
-const DEFAULT_MAX_STORAGE: u64 = ByteSize::gb(20).as_u64(); /// 20Gb
+const DEFAULT_MAX_STORAGE: u64 = ByteSize::gb(20).as_u64(); // 20Gb
+const DEFAULT_TIMESTAMP_FIELD: &str = "timestamp"; // field used to order records when sorting output
+const DEFAULT_DOWNLOAD_MAX_ATTEMPTS: u32 = 5; // attempts per object, including the first
+const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 200; // backoff doubles from here on each retry
+const DEFAULT_RETRY_MAX_DELAY_MS: u64 = 20_000; // longest wait between retries
+const DEFAULT_MULTIPART_THRESHOLD: u64 = ByteSize::mib(64).as_u64(); // objects this big are fetched as byte ranges
+const DEFAULT_MULTIPART_PART_SIZE: u64 = ByteSize::mib(16).as_u64(); // size of each byte range
+const DEFAULT_MULTIPART_PART_CONCURRENCY: usize = 8; // byte ranges fetched at once per object
+const DEFAULT_LISTING_CACHE_TTL_SECS: u64 = 900; // listings of prefixes still being written to are reused for 15 minutes
+pub const DEFAULT_DATE_PREFIX_FORMAT: &str = "%Y-%m-%d"; // production/<service>/2024-04-01/
 
 
 impl ::std::default::Default for ApplicationConfig {
@@ -35,6 +59,9 @@ impl ::std::default::Default for ApplicationConfig {
 
     Self {
       aws_profile: None,
+      aws_region: None,
+      s3_endpoint_url: None,
+      s3_force_path_style: false,
       aws_config_path,
       download_directory,
       cache_directory,
@@ -43,6 +70,49 @@ impl ::std::default::Default for ApplicationConfig {
       output_thread_concurrency: DEFAULT_OUTPUT_THREAD_CONCURRENCY,
       max_storage: DEFAULT_MAX_STORAGE,
       home_directory: dirs::home_dir().unwrap(),
+      date_prefix_format: DEFAULT_DATE_PREFIX_FORMAT.to_string(),
+      timestamp_field: DEFAULT_TIMESTAMP_FIELD.to_string(),
+      download_max_attempts: DEFAULT_DOWNLOAD_MAX_ATTEMPTS,
+      retry_base_delay_ms: DEFAULT_RETRY_BASE_DELAY_MS,
+      retry_max_delay_ms: DEFAULT_RETRY_MAX_DELAY_MS,
+      multipart_threshold: DEFAULT_MULTIPART_THRESHOLD,
+      multipart_part_size: DEFAULT_MULTIPART_PART_SIZE,
+      multipart_part_concurrency: DEFAULT_MULTIPART_PART_CONCURRENCY,
+      listing_cache_ttl_secs: DEFAULT_LISTING_CACHE_TTL_SECS,
+    }
+  }
+}
+
+impl ApplicationConfig {
+  /// S3 client settings, using the profile given rather than the configured one when there is one
+  pub fn client_options(&self, profile: Option<String>) -> ClientOptions {
+    ClientOptions {
+      profile: profile.or_else(|| self.aws_profile.clone()),
+      region: self.aws_region.clone(),
+      endpoint_url: self.s3_endpoint_url.clone(),
+      force_path_style: self.s3_force_path_style,
+    }
+  }
+
+  /// How long a listing of a prefix that may still change is reused for
+  pub fn listing_cache_ttl(&self) -> Duration {
+    Duration::from_secs(self.listing_cache_ttl_secs)
+  }
+
+  pub fn retry_policy(&self) -> RetryPolicy {
+    RetryPolicy {
+      max_attempts: self.download_max_attempts.max(1),
+      base_delay: Duration::from_millis(self.retry_base_delay_ms),
+      max_delay: Duration::from_millis(self.retry_max_delay_ms),
+    }
+  }
+
+  pub fn download_options(&self) -> DownloadOptions {
+    DownloadOptions {
+      retry: self.retry_policy(),
+      multipart_threshold: self.multipart_threshold,
+      part_size: self.multipart_part_size.max(1),
+      part_concurrency: self.multipart_part_concurrency.max(1),
     }
   }
 }
@@ -1,38 +1,64 @@
 use std::{path::PathBuf, rc::Rc};
 use log::info;
-use clap::{arg, command, Args, Parser, Subcommand};
-use anyhow::Result as OtherResult;
-use dab_s3_logs::{app, commands};
-use aws::{client, profiles};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use anyhow::{anyhow, Result as OtherResult};
+use aws_sdk_s3::Client;
+use chrono::Utc;
+use is_terminal::is_terminal;
+use dab_s3_logs::{app::{self, query_builder::QueryBuilder, time_range::TimeRange}, commands::{self, browse::BrowseAction, completions::{CompletionKind, CompletionShell}, fetch::FetchOptions, reset::ResetOptions, verify::VerifyOptions}, config::ApplicationConfig, storage::{cache::FileCache, catalog::BucketCatalog, listings::{ListingCache, LISTING_CACHE_DIRECTORY}}, output::{errors::FieldsError, fields::Field, filter::{FieldPath, Filter}, format::OutputFormat, OutputOptions}};
+use aws::client::{self, ClientOptions};
+
+/// Directory beneath the cache directory holding prefix listings made for completion
+const COMPLETION_CACHE_DIRECTORY: &str = "completions";
 
 #[tokio::main]
 async fn main() -> OtherResult<()> {
-    let app = app::setup().unwrap();
-    let conf = Rc::new(app.config.lock().unwrap().clone().unwrap());
+    let app = app::setup()?;
+    let conf = app.config.lock().unwrap().clone().ok_or_else(|| anyhow!("No configuration loaded"))?;
+    let conf = Rc::new(conf);
+    // completion scripts call back in on every tab press, so answer before anything slower
+    if let Ok(complete) = CompleteValueArgs::try_parse() {
+        return complete_value(&app, &conf, complete).await;
+    }
     let args = CliArgs::parse();
 
-    let profile = {
-        let from_conf = &conf.aws_profile;
-        match args.profile {
-            Some(profile) => Some(profile),
-            None => from_conf.clone(),
+    let client_options = args.connection.client_options(&conf);
+    let profile = client_options.profile.clone();
+    let catalog = app.bucket_catalog(&client_options);
+    let listings = ListingCache::new(&conf.cache_directory.join(LISTING_CACHE_DIRECTORY), &client_options, conf.listing_cache_ttl());
+
+    // generating a script doesn't need AWS, so it works before any credentials are set up
+    if let Commands::Completions { shell } = &args.cmd {
+        commands::completions::print_completions(*shell, CliArgs::command())?;
+        return Ok(());
+    }
+
+    // S3 compatible stores don't issue AWS credentials, so there's nothing to check there
+    if args.cmd.uses_s3() && client_options.endpoint_url.is_none() {
+        if let Err(e) = commands::doctor::check_credentials(&client_options).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
-    };
-    
-    let client = client::get_aws_client(profile).await.unwrap();
+    }
   
     match args.cmd {
-        Commands::Fetch { bucket, prefix } => {
-            let result = commands::fetch::fetch(&client, &app, bucket, prefix).await;
+        Commands::Fetch { query, workspace, concurrency, yes } => {
+            let client = client::get_aws_client(client_options).await?;
+            let query_builder = query.into_query_builder(&client, &catalog, &listings, &conf).await?;
+            let options = FetchOptions { workspace, profile, concurrency, yes };
+            let result = commands::fetch::fetch(&client, &app, query_builder, options).await;
             match result {
                 Ok(_) => {}
                 Err(e) => {
-                    eprintln!("Failed to fetch logs: {:?}", e);
+                    eprintln!("Failed to fetch logs: {}", e);
+                    std::process::exit(e.exit_code());
                 }
             }
         }
-        Commands::Preview { bucket, prefix } => {
-            let result = commands::fetch::preview(&client, bucket, prefix).await;
+        Commands::Preview { query } => {
+            let client = client::get_aws_client(client_options).await?;
+            let query_builder = query.into_query_builder(&client, &catalog, &listings, &conf).await?;
+            let result = commands::fetch::preview(&client, query_builder).await;
             match result {
                 Ok(_) => {}
                 Err(e) => {
@@ -40,6 +66,35 @@ async fn main() -> OtherResult<()> {
                 }
             }
         }
+        Commands::Browse { bucket, prefix } => {
+            let client = client::get_aws_client(client_options).await?;
+            let selection = match commands::browse::browse(&client, &catalog, bucket, prefix).await {
+                Ok(selection) => selection,
+                Err(e) => {
+                    eprintln!("Failed to browse bucket: {:?}", e);
+                    return Ok(());
+                }
+            };
+            let query_builder = QueryBuilder::new()
+                .bucket(selection.bucket)
+                .prefix(selection.prefix)
+                .date_prefix_format(conf.date_prefix_format.clone())
+                .listing_cache(listings);
+            match selection.action {
+                BrowseAction::Fetch => {
+                    let options = FetchOptions { workspace: None, profile, concurrency: None, yes: false };
+                    if let Err(e) = commands::fetch::fetch(&client, &app, query_builder, options).await {
+                        eprintln!("Failed to fetch logs: {}", e);
+                        std::process::exit(e.exit_code());
+                    }
+                }
+                BrowseAction::Preview => {
+                    if let Err(e) = commands::fetch::preview(&client, query_builder).await {
+                        eprintln!("Failed to preview logs: {:?}", e);
+                    }
+                }
+            }
+        }
         Commands::Config(config) => if let Some(config) = config.cmd {
             match config {
                 ConfigCommands::SetDownloadDir { path } => {
@@ -70,13 +125,9 @@ async fn main() -> OtherResult<()> {
                     }
                 }
                 ConfigCommands::ListAwsProfiles => {
-                    let result = profiles::get_aws_profiles::get_aws_profiles();
+                    let result = commands::config::list_aws_profiles::list_aws_profiles();
                     match result {
-                        Ok(profiles) => {
-                            for profile in profiles {
-                                println!("{}", profile);
-                            }
-                        }
+                        Ok(_) => {}
                         Err(e) => {
                             eprintln!("Failed to list AWS profiles: {:?}", e);
                         }
@@ -93,14 +144,80 @@ async fn main() -> OtherResult<()> {
                 }
             }
         }
-        Commands::Output => {
-            commands::output::output_files(&app).await?;
+        Commands::Output { workspace, sort, output } => {
+            let mut options = match output.into_options(&conf) {
+                Ok(options) => options,
+                Err(e) => {
+                    eprintln!("Invalid output options: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if sort {
+                options.sort_by = options.timestamp_field.clone();
+            }
+            commands::output::output_files(&app, options, workspace).await?;
+        }
+        Commands::Stream { query, concurrency, output } => {
+            let options = match output.into_options(&conf) {
+                Ok(options) => options,
+                Err(e) => {
+                    eprintln!("Invalid output options: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let client = client::get_aws_client(client_options).await?;
+            let query_builder = query.into_query_builder(&client, &catalog, &listings, &conf).await?;
+            if let Err(e) = commands::stream::stream(&client, &app, query_builder, options, concurrency).await {
+                eprintln!("Failed to stream logs: {}", e);
+                std::process::exit(e.exit_code());
+            }
+        }
+        Commands::Reset { workspace, bucket, prefix, older_than, dry_run, yes } => {
+            let options = ResetOptions { workspace, bucket, prefix, older_than, dry_run, yes };
+            if let Err(e) = commands::reset::delete_downloaded_logs(&app, options).await {
+                eprintln!("Failed to reset: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Verify { workspace, remove } => {
+            let options = VerifyOptions { workspace, remove };
+            if let Err(e) = commands::verify::verify_downloaded_logs(&app, options).await {
+                eprintln!("Failed to verify logs: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Doctor => {
+            if let Err(e) = commands::doctor::check_profiles(&client_options, args.connection.profile).await {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Completions { .. } => {}
+        Commands::Concurrency { limit, workspace } => {
+            if let Err(e) = commands::concurrency::set_fetch_concurrency(&app, limit as usize, workspace) {
+                eprintln!("Failed to change concurrency: {}", e);
+                std::process::exit(1);
+            }
         }
-        Commands::Reset => {
-            commands::reset::delete_downloaded_logs().await?;
+        Commands::Buckets(buckets) => if let Some(buckets) = buckets.cmd {
+            let client = client::get_aws_client(client_options).await?;
+            let result = match buckets {
+                BucketsCommands::Refresh => commands::buckets::refresh_buckets(&client, &catalog).await,
+                BucketsCommands::List => commands::buckets::list_buckets(&client, &catalog).await,
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to list buckets: {}", e);
+                std::process::exit(1);
+            }
         }
-        Commands::Test => {
-            aws::s3::cli_wrapper::test_cmd();
+        Commands::Workspaces(workspaces) => if let Some(workspaces) = workspaces.cmd {
+            match workspaces {
+                WorkspacesCommands::List { workspace } => {
+                    if let Err(e) = commands::workspaces::list_workspaces(&app, workspace) {
+                        eprintln!("Failed to list workspaces: {:?}", e);
+                    }
+                }
+            }
         }
     }
 
@@ -112,6 +229,34 @@ fn exit() {
     info!("Exiting");
 }
 
+/// Print the values a completion script offers for `--profile`, `--bucket` or `--prefix`
+async fn complete_value(app: &app::App, conf: &ApplicationConfig, args: CompleteValueArgs) -> OtherResult<()> {
+    let CompleteValueCommand::CompleteValue { kind, bucket, current } = args.cmd;
+    let client_options = args.connection.client_options(conf);
+    let catalog = app.bucket_catalog(&client_options);
+
+    match kind {
+        CompletionKind::Profiles => print_lines(commands::completions::complete_profiles(&current)),
+        CompletionKind::Buckets => print_lines(commands::completions::complete_buckets(&catalog, &current)),
+        // completing a prefix needs to know the bucket, and there's nothing to offer without one
+        CompletionKind::Prefixes => if let Some(bucket) = bucket {
+            let client = client::get_aws_client(client_options).await?;
+            let cache = FileCache::new(&conf.cache_directory.join(COMPLETION_CACHE_DIRECTORY));
+            if let Ok(prefixes) = commands::completions::complete_prefixes(&client, &cache, &catalog, &bucket, &current).await {
+                print_lines(prefixes);
+            }
+        },
+    }
+
+    Ok(())
+}
+
+fn print_lines(lines: Vec<String>) {
+    for line in lines {
+        println!("{}", line);
+    }
+}
+
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
 #[command(name = "dab-s3-logs")]
@@ -121,44 +266,303 @@ pub struct CliArgs {
   /// The subcommand to run
   #[command(subcommand)]
   cmd: Commands,
+  #[command(flatten)]
+  connection: ConnectionArgs,
+}
+
+/// Where the S3 client connects, taken by both the command line and `complete-value`
+#[derive(Args, Debug, Clone)]
+struct ConnectionArgs {
   /// AWS Profile to use when initializing the S3 client
   #[arg(long)]
   profile: Option<String>,
+  /// AWS Region to use, overriding the configured region and the profile's
+  #[arg(long)]
+  region: Option<String>,
+  /// Endpoint of an S3 compatible store to use instead of AWS, e.g. `http://localhost:9000`
+  #[arg(long)]
+  endpoint_url: Option<String>,
+  /// Address buckets by path rather than subdomain, as MinIO and LocalStack expect
+  #[arg(long)]
+  force_path_style: bool,
+}
+
+impl ConnectionArgs {
+    /// Client options from the config, overridden by whatever was passed
+    fn client_options(&self, conf: &ApplicationConfig) -> ClientOptions {
+        let mut client_options = conf.client_options(self.profile.clone());
+        if self.region.is_some() {
+            client_options.region = self.region.clone();
+        }
+        if self.endpoint_url.is_some() {
+            client_options.endpoint_url = self.endpoint_url.clone();
+        }
+        if self.force_path_style {
+            client_options.force_path_style = true;
+        }
+
+        client_options
+    }
+}
+
+/// Command line of the completion scripts calling back in, kept apart from [`CliArgs`] so the
+/// scripts generated from it don't offer it
+#[derive(Parser, Debug)]
+#[command(name = "dab-s3-logs")]
+struct CompleteValueArgs {
+    #[command(subcommand)]
+    cmd: CompleteValueCommand,
+    #[command(flatten)]
+    connection: ConnectionArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum CompleteValueCommand {
+    /// List completions of a value
+    CompleteValue {
+        #[arg(value_enum)]
+        kind: CompletionKind,
+
+        /// Bucket to complete prefixes in
+        #[arg(long)]
+        bucket: Option<String>,
+
+        /// What has been typed so far
+        #[arg(default_value = "", allow_hyphen_values = true)]
+        current: String,
+    },
 }
 
 #[derive(Subcommand, Debug, Clone)]
 enum Commands {
-    /// Command to run whatever test fn is currently in main
-    Test,
     /// Preview fetch results
-    #[command(arg_required_else_help = true)]
     Preview {
-        /// Name of the bucket to pull logs from
+        #[command(flatten)]
+        query: QueryArgs,
+    },
+    /// Fetch logs from S3
+    Fetch {
+        #[command(flatten)]
+        query: QueryArgs,
+
+        /// Workspace to download into, named after the bucket and prefix by default
         #[arg(short, long)]
-        bucket: String,
-    
-        /// Prefix to search for logs
+        workspace: Option<String>,
+
+        /// Downloads to run at once, change it mid fetch with the `concurrency` command
+        #[arg(short, long)]
+        concurrency: Option<usize>,
+
+        /// Evict least recently used logs to make room without asking, required when not in a terminal
         #[arg(short, long)]
-        prefix: String,
+        yes: bool,
     },
-    /// Fetch logs from S3
+    /// Change how many downloads running fetches make at once
     #[command(arg_required_else_help = true)]
-    Fetch {
-        /// Name of the bucket to pull logs from
+    Concurrency {
+        /// Downloads to run at once
+        #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+        limit: u32,
+
+        /// Only the running fetch into this workspace
+        #[arg(short, long)]
+        workspace: Option<String>,
+    },
+    /// Browse a bucket folder by folder, then fetch or preview the selected prefix
+    Browse {
+        /// Name of the bucket to browse, picked from a list when omitted
         #[arg(short, long)]
-        bucket: String,
-    
-        /// Prefix to search for logs
+        bucket: Option<String>,
+
+        /// Prefix to start browsing from
         #[arg(short, long)]
-        prefix: String,
+        prefix: Option<String>,
     },
     /// Output downloaded logs to stdout
-    Output,
+    Output {
+        /// Only output logs from workspaces matching this name or glob, e.g. `prod-*`
+        #[arg(short, long)]
+        workspace: Option<String>,
+
+        /// Merge records from every file into timestamp order
+        #[arg(long)]
+        sort: bool,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Output logs straight from S3 to stdout without downloading them
+    Stream {
+        #[command(flatten)]
+        query: QueryArgs,
+
+        /// Objects to read at once, defaults to the configured output concurrency
+        #[arg(short, long)]
+        concurrency: Option<usize>,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Check the credentials of every AWS profile, or only of --profile, and how to fix those that fail
+    Doctor,
+    /// Print a completion script, e.g. `dab-s3-logs completions zsh > ~/.zfunc/_dab-s3-logs`
+    #[command(arg_required_else_help = true)]
+    Completions {
+        /// Shell to complete in
+        #[arg(value_enum)]
+        shell: CompletionShell,
+    },
     /// Manage configuration options
     Config(ConfigArgs),
-    /// Clear storage directory
-    Reset,
+    /// Manage fetch workspaces
+    Workspaces(WorkspacesArgs),
+    /// Manage the saved list of buckets each profile can see
+    Buckets(BucketsArgs),
+    /// Delete downloaded logs, all of them and the saved S3 listings unless scoped
+    Reset {
+        /// Only logs from workspaces matching this name or glob, e.g. `prod-*`
+        #[arg(short, long)]
+        workspace: Option<String>,
+
+        /// Only logs downloaded from this bucket
+        #[arg(short, long)]
+        bucket: Option<String>,
+
+        /// Only logs whose key starts with this glob, e.g. `logs/*/2024-05-`
+        #[arg(short, long)]
+        prefix: Option<String>,
+
+        /// Only logs last fetched or read before this, e.g. `7d` or `2024-05-01`
+        #[arg(long)]
+        older_than: Option<String>,
+
+        /// List what would be deleted without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Delete without asking, required when not in a terminal
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Check downloaded logs are intact, exiting with an error if any aren't and weren't removed
+    Verify {
+        /// Only logs from workspaces matching this name or glob, e.g. `prod-*`
+        #[arg(short, long)]
+        workspace: Option<String>,
+
+        /// Delete logs that fail so the next fetch downloads them again
+        #[arg(long)]
+        remove: bool,
+    },
+}
+
+impl Commands {
+    /// True for commands that list or read from S3, whose credentials are checked up front
+    fn uses_s3(&self) -> bool {
+        match self {
+            Commands::Fetch { .. } | Commands::Preview { .. } | Commands::Browse { .. } | Commands::Stream { .. } => true,
+            Commands::Buckets(buckets) => buckets.cmd.is_some(),
+            _ => false,
+        }
+    }
+}
+
+/// Which logs to list. Anything left out is prompted for interactively
+#[derive(Debug, Args, Clone)]
+struct QueryArgs {
+    /// Name of the bucket to pull logs from
+    #[arg(short, long)]
+    bucket: Option<String>,
+
+    /// Prefix to search for logs
+    #[arg(short, long)]
+    prefix: Option<String>,
+
+    /// Environment folder, e.g. `production`
+    #[arg(short, long)]
+    environment: Option<String>,
+
+    /// Service folder beneath the environment, may be repeated
+    #[arg(short, long = "service")]
+    services: Vec<String>,
+
+    /// Start of the time window, e.g. `6h`, `last 2 days`, `2024-04-01` or `2024-04-01..2024-04-03`
+    #[arg(long)]
+    since: Option<String>,
+
+    /// End of the time window, defaults to now
+    #[arg(long)]
+    until: Option<String>,
+
+    /// List the prefixes from S3 again rather than reusing a cached listing
+    #[arg(long)]
+    refresh: bool,
+}
+
+impl QueryArgs {
+    /// Build a query from the arguments, prompting for the rest when running in a terminal
+    async fn into_query_builder(self, client: &Client, catalog: &BucketCatalog, listings: &ListingCache, conf: &ApplicationConfig) -> OtherResult<QueryBuilder> {
+        let time_range = TimeRange::parse(self.since.as_deref(), self.until.as_deref(), Utc::now())?;
+
+        let mut query_builder = QueryBuilder::new()
+            .services(self.services)
+            .time_range(time_range)
+            .date_prefix_format(conf.date_prefix_format.clone())
+            .listing_cache(listings.clone().refresh(self.refresh));
+        if let Some(bucket) = self.bucket {
+            query_builder = query_builder.bucket(bucket);
+        }
+        if let Some(prefix) = self.prefix {
+            query_builder = query_builder.prefix(prefix);
+        }
+        if let Some(environment) = self.environment {
+            query_builder = query_builder.environment(environment);
+        }
+
+        if !query_builder.is_complete() && is_terminal(std::io::stdout()) {
+            query_builder = query_builder.interactive(client, catalog).await?;
+        }
+
+        Ok(query_builder)
+    }
+}
+
+/// How records are filtered and written
+#[derive(Debug, Args, Clone)]
+struct OutputArgs {
+    /// Only output records matching an expression, e.g. `level=error and service~=api`
+    #[arg(short, long)]
+    filter: Option<String>,
+
+    /// Field holding each record's timestamp, defaults to the configured field
+    #[arg(long)]
+    timestamp_field: Option<String>,
+
+    /// How to write each record
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+
+    /// Comma separated fields to keep, renamed with `as`, e.g. `timestamp,level,kubernetes.pod_name as pod`
+    #[arg(long, value_delimiter = ',')]
+    fields: Vec<String>,
+}
+
+impl OutputArgs {
+    /// Parse the arguments into output options
+    fn into_options(self, conf: &ApplicationConfig) -> OtherResult<OutputOptions> {
+        if self.format.requires_fields() && self.fields.is_empty() {
+            let format = self.format.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+            return Err(FieldsError::FieldsRequired(format).into());
+        }
+
+        let filter = self.filter.as_deref().map(Filter::parse).transpose()?;
+        let timestamp_field = FieldPath::parse(self.timestamp_field.as_deref().unwrap_or(&conf.timestamp_field));
+        let fields = Field::parse_all(&self.fields)?;
+
+        Ok(OutputOptions { filter, sort_by: None, timestamp_field: Some(timestamp_field), format: self.format, fields })
+    }
 }
+
 #[derive(Debug, Args, Clone)]
 #[command(args_conflicts_with_subcommands = true)]
 #[command(flatten_help = true)]
@@ -167,6 +571,39 @@ struct ConfigArgs {
     cmd: Option<ConfigCommands>,
 }
 
+#[derive(Debug, Args, Clone)]
+#[command(args_conflicts_with_subcommands = true)]
+#[command(flatten_help = true)]
+struct WorkspacesArgs {
+    #[command(subcommand)]
+    cmd: Option<WorkspacesCommands>,
+}
+
+#[derive(Debug, Args, Clone)]
+#[command(args_conflicts_with_subcommands = true)]
+#[command(flatten_help = true)]
+struct BucketsArgs {
+    #[command(subcommand)]
+    cmd: Option<BucketsCommands>,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum BucketsCommands {
+    /// List the profile's buckets from S3 and save them
+    Refresh,
+    /// List the profile's saved buckets, listing them from S3 if they were never saved
+    List,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum WorkspacesCommands {
+    /// List workspaces and what was fetched into them
+    List {
+        /// Only workspaces matching this name or glob, e.g. `prod-*`
+        workspace: Option<String>,
+    },
+}
+
 #[derive(Debug, Subcommand, Clone)]
 enum ConfigCommands {
     /// Set the download directory
@@ -185,7 +622,7 @@ enum ConfigCommands {
     },
     /// List configuration values
     List,
-    /// List AWS Profiles
+    /// List AWS Profiles from the shared config and credentials files
     ListAwsProfiles,
     SelectAwsProfile,
 }
\ No newline at end of file
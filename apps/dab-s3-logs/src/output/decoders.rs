@@ -0,0 +1,150 @@
+use std::{fs::File, io::{self, BufRead, BufReader, Read}, path::Path};
+
+use bzip2::read::MultiBzDecoder;
+use file_format::FileFormat;
+use flate2::read::MultiGzDecoder;
+use log::debug;
+use xz2::read::XzDecoder;
+
+use super::errors::OutputError;
+
+/// Decompressed log contents, ready to be split into records
+pub type LogReader = Box<dyn BufRead + Send>;
+
+/// Wraps a raw (possibly compressed) stream in a decoder
+pub type DecoderFn = fn(Box<dyn BufRead + Send>) -> io::Result<Box<dyn Read + Send>>;
+
+/// Maps detected file formats to the decoder that can read them
+pub struct DecoderRegistry {
+  decoders: Vec<(FileFormat, DecoderFn)>,
+}
+
+impl Default for DecoderRegistry {
+  /// Plain text, gzip, zstd, bzip2 and xz. Every compressed format accepts multiple concatenated members
+  fn default() -> Self {
+    let mut registry = Self::new();
+    registry.register(FileFormat::PlainText, decode_plain_text);
+    registry.register(FileFormat::Gzip, decode_gzip);
+    registry.register(FileFormat::Zstandard, decode_zstd);
+    registry.register(FileFormat::Bzip2, decode_bzip2);
+    registry.register(FileFormat::Xz, decode_xz);
+    registry
+  }
+}
+
+impl DecoderRegistry {
+  /// An empty registry
+  pub fn new() -> Self {
+    Self { decoders: Vec::new() }
+  }
+
+  /// Add a decoder, replacing any existing decoder for the format
+  pub fn register(&mut self, format: FileFormat, decoder: DecoderFn) {
+    self.decoders.retain(|(existing, _)| *existing != format);
+    self.decoders.push((format, decoder));
+  }
+
+  pub fn supports(&self, format: FileFormat) -> bool {
+    self.get(format).is_some()
+  }
+
+  fn get(&self, format: FileFormat) -> Option<DecoderFn> {
+    self.decoders.iter()
+      .find(|(existing, _)| *existing == format)
+      .map(|(_, decoder)| *decoder)
+  }
+
+  /// Decode a stream of the given format, or `None` if the format isn't supported. Content that
+  /// isn't recognised as any format is read as plain text
+  pub fn decode(&self, format: FileFormat, reader: Box<dyn BufRead + Send>) -> Result<Option<LogReader>, OutputError> {
+    let decoder = match format {
+      // text with colour escapes, or a sample cut off part way through a character, isn't recognised as text
+      FileFormat::ArbitraryBinaryData => self.get(format).or_else(|| self.get(FileFormat::PlainText)),
+      _ => self.get(format),
+    };
+
+    match decoder {
+      Some(decoder) => {
+        let decoded = decoder(reader)?;
+        Ok(Some(Box::new(BufReader::new(decoded))))
+      }
+      None => {
+        debug!("No decoder registered for {}", format.name());
+        Ok(None)
+      }
+    }
+  }
+
+  /// Detect the format of a file and open it with the matching decoder
+  pub fn open(&self, path: &Path) -> Result<Option<LogReader>, OutputError> {
+    let format = FileFormat::from_file(path)?;
+    let file = File::open(path)?;
+
+    self.decode(format, Box::new(BufReader::new(file)))
+  }
+}
+
+fn decode_plain_text(reader: Box<dyn BufRead + Send>) -> io::Result<Box<dyn Read + Send>> {
+  Ok(reader)
+}
+
+fn decode_gzip(reader: Box<dyn BufRead + Send>) -> io::Result<Box<dyn Read + Send>> {
+  Ok(Box::new(MultiGzDecoder::new(reader)))
+}
+
+fn decode_zstd(reader: Box<dyn BufRead + Send>) -> io::Result<Box<dyn Read + Send>> {
+  Ok(Box::new(zstd::stream::read::Decoder::with_buffer(reader)?))
+}
+
+fn decode_bzip2(reader: Box<dyn BufRead + Send>) -> io::Result<Box<dyn Read + Send>> {
+  Ok(Box::new(MultiBzDecoder::new(reader)))
+}
+
+fn decode_xz(reader: Box<dyn BufRead + Send>) -> io::Result<Box<dyn Read + Send>> {
+  Ok(Box::new(XzDecoder::new_multi_decoder(reader)))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use super::*;
+
+  fn decode(contents: &[u8]) -> Option<String> {
+    let format = FileFormat::from_bytes(contents);
+    let reader = DecoderRegistry::default().decode(format, Box::new(Cursor::new(contents.to_vec()))).unwrap()?;
+    let mut decoded = Vec::new();
+    Box::new(reader).read_to_end(&mut decoded).unwrap();
+
+    Some(String::from_utf8_lossy(&decoded).into_owned())
+  }
+
+  #[test]
+  fn reads_text_with_colour_escapes() {
+    let contents = "\x1b[32mINFO\x1b[0m started\n";
+
+    assert_eq!(decode(contents.as_bytes()).as_deref(), Some(contents));
+  }
+
+  #[test]
+  fn reads_text_cut_off_part_way_through_a_character() {
+    let contents = "caf\u{e9}\n".as_bytes();
+
+    assert_eq!(decode(&contents[..4]).as_deref(), Some("caf\u{fffd}"));
+  }
+
+  #[test]
+  fn decompresses_gzip() {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, b"{\"n\":1}\n").unwrap();
+
+    assert_eq!(decode(&encoder.finish().unwrap()).as_deref(), Some("{\"n\":1}\n"));
+  }
+
+  #[test]
+  fn skips_formats_without_a_decoder() {
+    let registry = DecoderRegistry::new();
+
+    assert!(registry.decode(FileFormat::Gzip, Box::new(Cursor::new(Vec::new()))).unwrap().is_none());
+  }
+}
@@ -0,0 +1,40 @@
+use std::io::Error as IoError;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum OutputError {
+  #[error("Failed to read log file: {0}")]
+  ReadError(#[from] IoError),
+  #[error("Failed to parse log file: {0}")]
+  ParseError(#[from] serde_json::Error),
+  /// Whatever read stdout has gone, e.g. `head` once it had enough lines
+  #[error("Stdout was closed")]
+  StdoutClosed,
+}
+
+#[derive(Error, Debug)]
+pub enum FilterError {
+  #[error("Unexpected `{0}` in filter")]
+  UnexpectedToken(String),
+  #[error("Unexpected `{0}` in filter")]
+  UnexpectedCharacter(char),
+  #[error("Filter ended unexpectedly")]
+  UnexpectedEnd,
+  #[error("Unterminated string in filter")]
+  UnterminatedString,
+  #[error("Invalid regex in filter: {0}")]
+  InvalidRegex(#[from] regex::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum FieldsError {
+  #[error("Invalid field `{0}`, expected `path` or `path as name`")]
+  InvalidField(String),
+  #[error("Field `{0}` is selected more than once")]
+  DuplicateField(String),
+  #[error("Field `{1}` is inside field `{0}`, select one or the other")]
+  NestedField(String, String),
+  #[error("The {0} format needs --fields")]
+  FieldsRequired(String),
+}
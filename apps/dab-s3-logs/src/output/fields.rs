@@ -0,0 +1,170 @@
+use serde_json::{Map, Value};
+
+use super::{errors::FieldsError, filter::FieldPath};
+
+/// One entry of `--fields`, e.g. `kubernetes.pod_name` or `kubernetes.pod_name as pod`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+  path: FieldPath,
+  alias: Option<String>,
+}
+
+impl Field {
+  pub fn parse(spec: &str) -> Result<Self, FieldsError> {
+    let words: Vec<&str> = spec.split_whitespace().collect();
+
+    match words.as_slice() {
+      [path] => Ok(Self { path: FieldPath::parse(path), alias: None }),
+      [path, keyword, alias] if keyword.eq_ignore_ascii_case("as") => Ok(Self {
+        path: FieldPath::parse(path),
+        alias: Some(alias.to_string()),
+      }),
+      _ => Err(FieldsError::InvalidField(spec.trim().to_string())),
+    }
+  }
+
+  /// Parse a list of fields, rejecting two fields that would be written under the same name, or
+  /// one that would be written inside another, like `a` and `a.b`
+  pub fn parse_all<S: AsRef<str>>(specs: &[S]) -> Result<Vec<Self>, FieldsError> {
+    let mut fields: Vec<Self> = Vec::new();
+    for spec in specs {
+      let field = Self::parse(spec.as_ref())?;
+      if fields.iter().any(|existing| existing.name() == field.name()) {
+        return Err(FieldsError::DuplicateField(field.name()));
+      }
+      for existing in &fields {
+        let (outer, inner) = if existing.destination().len() <= field.destination().len() { (existing, &field) } else { (&field, existing) };
+        if inner.destination().starts_with(outer.destination()) {
+          return Err(FieldsError::NestedField(outer.name(), inner.name()));
+        }
+      }
+      fields.push(field);
+    }
+
+    Ok(fields)
+  }
+
+  pub fn path(&self) -> &FieldPath {
+    &self.path
+  }
+
+  /// The alias if there is one, otherwise the dotted path
+  pub fn name(&self) -> String {
+    match &self.alias {
+      Some(alias) => alias.clone(),
+      None => self.path.to_string(),
+    }
+  }
+
+  pub fn lookup<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+    self.path.lookup(value)
+  }
+
+  /// Where the field is written in a projected record, see [`project`]
+  fn destination(&self) -> &[String] {
+    match &self.alias {
+      Some(alias) => std::slice::from_ref(alias),
+      None => self.path.segments(),
+    }
+  }
+}
+
+/// Project a record down to the selected fields.
+///
+/// Aliased fields are written at the top level under their alias. Other fields keep their place in
+/// the record, so `kubernetes.pod_name` stays nested under `kubernetes`. Fields are written in the
+/// order they were selected and missing fields are left out.
+pub fn project(value: &Value, fields: &[Field]) -> Value {
+  let mut projected = Map::new();
+
+  for field in fields {
+    let selected = match field.lookup(value) {
+      Some(selected) => selected.clone(),
+      None => continue,
+    };
+
+    match &field.alias {
+      Some(alias) => {
+        projected.insert(alias.clone(), selected);
+      }
+      None => insert_nested(&mut projected, field.path.segments(), selected),
+    }
+  }
+
+  Value::Object(projected)
+}
+
+fn insert_nested(map: &mut Map<String, Value>, segments: &[String], value: Value) {
+  match segments {
+    [] => {}
+    [last] => {
+      map.insert(last.clone(), value);
+    }
+    [first, rest @ ..] => {
+      let child = map.entry(first.clone()).or_insert_with(|| Value::Object(Map::new()));
+      if !child.is_object() {
+        *child = Value::Object(Map::new());
+      }
+      if let Value::Object(child) = child {
+        insert_nested(child, rest, value);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  #[test]
+  fn parses_paths_and_aliases() {
+    let field = Field::parse(" kubernetes.pod_name AS pod ").unwrap();
+
+    assert_eq!(field.path().segments(), ["kubernetes", "pod_name"]);
+    assert_eq!(field.name(), "pod");
+    assert_eq!(Field::parse("level").unwrap().name(), "level");
+    assert!(matches!(Field::parse("a b"), Err(FieldsError::InvalidField(_))));
+    assert!(matches!(Field::parse(""), Err(FieldsError::InvalidField(_))));
+  }
+
+  #[test]
+  fn rejects_two_fields_with_the_same_name() {
+    assert!(matches!(Field::parse_all(&["level", "severity as level"]), Err(FieldsError::DuplicateField(name)) if name == "level"));
+    assert_eq!(Field::parse_all(&["a.b", "b"]).unwrap().len(), 2);
+  }
+
+  #[test]
+  fn rejects_a_field_inside_another() {
+    assert!(matches!(Field::parse_all(&["a.b", "a"]), Err(FieldsError::NestedField(outer, inner)) if outer == "a" && inner == "a.b"));
+    assert!(matches!(Field::parse_all(&["x as a", "a.b.c"]), Err(FieldsError::NestedField(outer, inner)) if outer == "a" && inner == "a.b.c"));
+    assert_eq!(Field::parse_all(&["a.b", "a.c", "ab"]).unwrap().len(), 3);
+  }
+
+  #[test]
+  fn looks_up_nested_fields_and_array_items() {
+    let record = json!({"kubernetes": {"labels": {"app": "api"}}, "tags": ["a", "b"]});
+
+    assert_eq!(Field::parse("kubernetes.labels.app").unwrap().lookup(&record), Some(&json!("api")));
+    assert_eq!(Field::parse("tags.1").unwrap().lookup(&record), Some(&json!("b")));
+    assert_eq!(Field::parse("tags.x").unwrap().lookup(&record), None);
+    assert_eq!(Field::parse("kubernetes.labels.app.name").unwrap().lookup(&record), None);
+  }
+
+  #[test]
+  fn projects_nested_fields_in_place_and_aliases_at_the_top() {
+    let record = json!({"kubernetes": {"pod_name": "api-1", "namespace": "prod"}, "level": "info", "n": 1});
+    let fields = Field::parse_all(&["kubernetes.pod_name", "level as severity", "missing"]).unwrap();
+
+    assert_eq!(project(&record, &fields), json!({"kubernetes": {"pod_name": "api-1"}, "severity": "info"}));
+  }
+
+  #[test]
+  fn projects_fields_in_the_order_selected() {
+    let record = json!({"a": 1, "b": {"x": 2, "y": 3}, "c": 4});
+    let fields = Field::parse_all(&["c", "b.y", "a", "b.x"]).unwrap();
+
+    assert_eq!(project(&record, &fields).to_string(), r#"{"c":4,"b":{"y":3,"x":2},"a":1}"#);
+  }
+}
@@ -0,0 +1,474 @@
+use std::{cmp::Ordering, iter::Peekable, str::Chars};
+
+use regex::Regex;
+use serde_json::Value;
+
+use super::{errors::FilterError, reader::Record};
+
+/// A parsed `--filter` expression, e.g. `level=error and (service~=api or not kubernetes.pod_name)`.
+///
+/// Supported comparisons:
+/// - `field = value`, `field == value`, `field != value`
+/// - `field ~= regex`, `field !~ regex`
+/// - `field < value`, `<=`, `>`, `>=` (numeric when both sides are numbers, otherwise lexical)
+/// - `field` on its own tests that the field exists
+///
+/// Comparisons combine with `and`, `or`, `not` and parentheses. Fields are dot separated paths into
+/// nested objects, with numeric segments indexing into arrays. Values may be quoted with `"` or `'`.
+/// An unquoted value runs to the next whitespace or to a `)` closing a paren opened before it, so
+/// `service~=(api|web)` works, but a value containing spaces has to be quoted.
+#[derive(Debug, Clone)]
+pub enum Filter {
+  And(Box<Filter>, Box<Filter>),
+  Or(Box<Filter>, Box<Filter>),
+  Not(Box<Filter>),
+  Exists(FieldPath),
+  Compare(FieldPath, Comparison),
+}
+
+#[derive(Debug, Clone)]
+pub enum Comparison {
+  Eq(String),
+  NotEq(String),
+  Matches(Regex),
+  NotMatches(Regex),
+  Lt(String),
+  Lte(String),
+  Gt(String),
+  Gte(String),
+}
+
+/// Dot separated path into a JSON record, e.g. `kubernetes.pod_name`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldPath {
+  segments: Vec<String>,
+}
+
+impl FieldPath {
+  pub fn parse(path: &str) -> Self {
+    Self {
+      segments: path.split('.').map(|segment| segment.to_string()).collect(),
+    }
+  }
+
+  /// Look the path up in a record, indexing into arrays for numeric segments
+  pub fn lookup<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+    self.segments.iter().try_fold(value, |current, segment| match current {
+      Value::Object(map) => map.get(segment),
+      Value::Array(items) => segment.parse::<usize>().ok().and_then(|index| items.get(index)),
+      _ => None,
+    })
+  }
+
+  pub fn segments(&self) -> &[String] {
+    &self.segments
+  }
+}
+
+impl std::fmt::Display for FieldPath {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.segments.join("."))
+  }
+}
+
+impl Filter {
+  pub fn parse(input: &str) -> Result<Self, FilterError> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser { tokens, position: 0 };
+    let filter = parser.parse_or()?;
+
+    match parser.peek() {
+      None => Ok(filter),
+      Some(token) => Err(FilterError::UnexpectedToken(token.to_string())),
+    }
+  }
+
+  /// True when the record satisfies the expression. Text records have no fields
+  pub fn matches(&self, record: &Record) -> bool {
+    match record {
+      Record::Json(value) => self.matches_value(value),
+      Record::Text(_) => self.matches_value(&Value::Null),
+    }
+  }
+
+  pub fn matches_value(&self, value: &Value) -> bool {
+    match self {
+      Filter::And(left, right) => left.matches_value(value) && right.matches_value(value),
+      Filter::Or(left, right) => left.matches_value(value) || right.matches_value(value),
+      Filter::Not(inner) => !inner.matches_value(value),
+      Filter::Exists(path) => path.lookup(value).is_some_and(|field| !field.is_null()),
+      Filter::Compare(path, comparison) => match path.lookup(value) {
+        Some(field) => comparison.matches(field),
+        None => matches!(comparison, Comparison::NotEq(_) | Comparison::NotMatches(_)),
+      },
+    }
+  }
+}
+
+impl Comparison {
+  fn matches(&self, field: &Value) -> bool {
+    match self {
+      Comparison::Eq(expected) => equals(field, expected),
+      Comparison::NotEq(expected) => !equals(field, expected),
+      Comparison::Matches(regex) => regex.is_match(&as_text(field)),
+      Comparison::NotMatches(regex) => !regex.is_match(&as_text(field)),
+      Comparison::Lt(expected) => compare(field, expected) == Some(Ordering::Less),
+      Comparison::Lte(expected) => matches!(compare(field, expected), Some(Ordering::Less | Ordering::Equal)),
+      Comparison::Gt(expected) => compare(field, expected) == Some(Ordering::Greater),
+      Comparison::Gte(expected) => matches!(compare(field, expected), Some(Ordering::Greater | Ordering::Equal)),
+    }
+  }
+}
+
+/// Strings compare as is, everything else by its JSON representation, numbers numerically
+fn equals(field: &Value, expected: &str) -> bool {
+  match field {
+    Value::Number(number) => match (number.as_f64(), expected.parse::<f64>()) {
+      (Some(actual), Ok(expected)) => actual == expected,
+      _ => number.to_string() == expected,
+    },
+    _ => as_text(field) == expected,
+  }
+}
+
+fn compare(field: &Value, expected: &str) -> Option<Ordering> {
+  let actual = match field {
+    Value::Null | Value::Array(_) | Value::Object(_) => return None,
+    _ => as_text(field),
+  };
+
+  match (actual.parse::<f64>(), expected.parse::<f64>()) {
+    (Ok(actual), Ok(expected)) => actual.partial_cmp(&expected),
+    _ => Some(actual.as_str().cmp(expected)),
+  }
+}
+
+fn as_text(field: &Value) -> String {
+  match field {
+    Value::String(text) => text.clone(),
+    _ => field.to_string(),
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Word(String),
+  Quoted(String),
+  Operator(&'static str),
+  OpenParen,
+  CloseParen,
+}
+
+impl std::fmt::Display for Token {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Token::Word(word) => write!(f, "{}", word),
+      Token::Quoted(text) => write!(f, "\"{}\"", text),
+      Token::Operator(op) => write!(f, "{}", op),
+      Token::OpenParen => write!(f, "("),
+      Token::CloseParen => write!(f, ")"),
+    }
+  }
+}
+
+/// Longest operators first so `<=` isn't read as `<`
+const OPERATORS: [&str; 9] = ["==", "!=", "~=", "!~", "<=", ">=", "=", "<", ">"];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, FilterError> {
+  let mut tokens = Vec::new();
+  let mut chars = input.chars().peekable();
+
+  while let Some(&c) = chars.peek() {
+    let after_operator = matches!(tokens.last(), Some(Token::Operator(_)));
+    if c.is_whitespace() {
+      chars.next();
+    } else if after_operator && c != '"' && c != '\'' && c != ')' {
+      tokens.push(Token::Word(read_value(&mut chars)));
+    } else if c == '(' {
+      chars.next();
+      tokens.push(Token::OpenParen);
+    } else if c == ')' {
+      chars.next();
+      tokens.push(Token::CloseParen);
+    } else if c == '"' || c == '\'' {
+      chars.next();
+      tokens.push(Token::Quoted(read_quoted(&mut chars, c)?));
+    } else if let Some(op) = read_operator(&mut chars) {
+      tokens.push(Token::Operator(op));
+    } else {
+      let mut word = String::new();
+      while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || c == '(' || c == ')' || is_operator_start(c) {
+          break;
+        }
+        word.push(c);
+        chars.next();
+      }
+      if word.is_empty() {
+        return Err(FilterError::UnexpectedCharacter(c));
+      }
+      tokens.push(Token::Word(word));
+    }
+  }
+
+  Ok(tokens)
+}
+
+fn is_operator_start(c: char) -> bool {
+  matches!(c, '=' | '!' | '~' | '<' | '>')
+}
+
+fn read_operator(chars: &mut Peekable<Chars>) -> Option<&'static str> {
+  let first = *chars.peek()?;
+  if !is_operator_start(first) {
+    return None;
+  }
+
+  let mut lookahead = chars.clone();
+  lookahead.next();
+  let second = lookahead.peek().copied();
+
+  for op in OPERATORS {
+    let mut op_chars = op.chars();
+    let matches = op_chars.next() == Some(first) && match op_chars.next() {
+      Some(expected) => second == Some(expected),
+      None => true,
+    };
+    if matches {
+      for _ in 0..op.len() {
+        chars.next();
+      }
+      return Some(op);
+    }
+  }
+
+  None
+}
+
+/// An unquoted value, which may hold operators and balanced parens as regexes often do
+fn read_value(chars: &mut Peekable<Chars>) -> String {
+  let mut value = String::new();
+  let mut depth = 0;
+  while let Some(&c) = chars.peek() {
+    match c {
+      c if c.is_whitespace() => break,
+      ')' if depth == 0 => break,
+      ')' => depth -= 1,
+      '(' => depth += 1,
+      _ => {}
+    }
+    value.push(c);
+    chars.next();
+  }
+
+  value
+}
+
+fn read_quoted(chars: &mut Peekable<Chars>, quote: char) -> Result<String, FilterError> {
+  let mut text = String::new();
+  loop {
+    match chars.next() {
+      Some('\\') => match chars.next() {
+        Some(escaped) => text.push(escaped),
+        None => return Err(FilterError::UnterminatedString),
+      },
+      Some(c) if c == quote => return Ok(text),
+      Some(c) => text.push(c),
+      None => return Err(FilterError::UnterminatedString),
+    }
+  }
+}
+
+struct Parser {
+  tokens: Vec<Token>,
+  position: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.position)
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.position).cloned();
+    self.position += 1;
+    token
+  }
+
+  fn peek_keyword(&self, keyword: &str) -> bool {
+    matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+  }
+
+  fn parse_or(&mut self) -> Result<Filter, FilterError> {
+    let mut left = self.parse_and()?;
+    while self.peek_keyword("or") {
+      self.next();
+      let right = self.parse_and()?;
+      left = Filter::Or(Box::new(left), Box::new(right));
+    }
+
+    Ok(left)
+  }
+
+  fn parse_and(&mut self) -> Result<Filter, FilterError> {
+    let mut left = self.parse_unary()?;
+    while self.peek_keyword("and") {
+      self.next();
+      let right = self.parse_unary()?;
+      left = Filter::And(Box::new(left), Box::new(right));
+    }
+
+    Ok(left)
+  }
+
+  fn parse_unary(&mut self) -> Result<Filter, FilterError> {
+    if self.peek_keyword("not") {
+      self.next();
+      return Ok(Filter::Not(Box::new(self.parse_unary()?)));
+    }
+
+    match self.next() {
+      Some(Token::OpenParen) => {
+        let inner = self.parse_or()?;
+        match self.next() {
+          Some(Token::CloseParen) => Ok(inner),
+          Some(token) => Err(FilterError::UnexpectedToken(token.to_string())),
+          None => Err(FilterError::UnexpectedEnd),
+        }
+      }
+      Some(Token::Word(field)) | Some(Token::Quoted(field)) => self.parse_comparison(FieldPath::parse(&field)),
+      Some(token) => Err(FilterError::UnexpectedToken(token.to_string())),
+      None => Err(FilterError::UnexpectedEnd),
+    }
+  }
+
+  fn parse_comparison(&mut self, path: FieldPath) -> Result<Filter, FilterError> {
+    let op = match self.peek() {
+      Some(Token::Operator(op)) => *op,
+      _ => return Ok(Filter::Exists(path)),
+    };
+    self.next();
+
+    let value = match self.next() {
+      Some(Token::Word(value)) | Some(Token::Quoted(value)) => value,
+      Some(token) => return Err(FilterError::UnexpectedToken(token.to_string())),
+      None => return Err(FilterError::UnexpectedEnd),
+    };
+
+    let comparison = match op {
+      "=" | "==" => Comparison::Eq(value),
+      "!=" => Comparison::NotEq(value),
+      "~=" => Comparison::Matches(Regex::new(&value)?),
+      "!~" => Comparison::NotMatches(Regex::new(&value)?),
+      "<" => Comparison::Lt(value),
+      "<=" => Comparison::Lte(value),
+      ">" => Comparison::Gt(value),
+      _ => Comparison::Gte(value),
+    };
+
+    Ok(Filter::Compare(path, comparison))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  fn matches(filter: &str, value: Value) -> bool {
+    Filter::parse(filter).unwrap().matches_value(&value)
+  }
+
+  fn error(filter: &str) -> String {
+    Filter::parse(filter).unwrap_err().to_string()
+  }
+
+  #[test]
+  fn compares_with_each_operator() {
+    let record = json!({"level": "error", "status": 503, "service": "api"});
+
+    assert!(matches("level=error", record.clone()));
+    assert!(matches("level == error", record.clone()));
+    assert!(matches("level != info", record.clone()));
+    assert!(matches("service ~= ^ap", record.clone()));
+    assert!(matches("service !~ web", record.clone()));
+    assert!(matches("status > 500", record.clone()));
+    assert!(matches("status >= 503", record.clone()));
+    assert!(matches("status < 600", record.clone()));
+    assert!(matches("status <= 503", record.clone()));
+    assert!(matches("status = 503.0", record.clone()));
+    assert!(!matches("status < 60", record.clone()), "numbers compare numerically");
+    assert!(matches("level < fatal", record), "strings compare lexically");
+  }
+
+  #[test]
+  fn tests_fields_exist() {
+    assert!(matches("trace_id", json!({"trace_id": "abc"})));
+    assert!(!matches("trace_id", json!({"trace_id": null})));
+    assert!(!matches("trace_id", json!({})));
+    assert!(matches("missing != x", json!({})), "a missing field is never equal");
+    assert!(!matches("missing = x", json!({})));
+  }
+
+  #[test]
+  fn follows_nested_paths_and_array_indexes() {
+    let record = json!({"kubernetes": {"pod_name": "api-1"}, "tags": ["a", "b"]});
+
+    assert!(matches("kubernetes.pod_name = api-1", record.clone()));
+    assert!(matches("tags.1 = b", record.clone()));
+    assert!(!matches("tags.5", record));
+  }
+
+  #[test]
+  fn binds_not_then_and_then_or() {
+    let record = json!({"a": 1});
+
+    // `a=2 and a=1 or a=1` is `(a=2 and a=1) or a=1`
+    assert!(matches("a=2 and a=1 or a=1", record.clone()));
+    // `a=1 or a=1 and a=2` is `a=1 or (a=1 and a=2)`
+    assert!(matches("a=1 or a=1 and a=2", record.clone()));
+    assert!(!matches("(a=1 or a=1) and a=2", record.clone()));
+    assert!(!matches("not a=1 and a=1", record.clone()));
+    assert!(matches("NOT (a=1 AND a=2)", record));
+  }
+
+  #[test]
+  fn reads_quoted_values() {
+    let record = json!({"message": "disk \"full\" (sda)", "name": "it's"});
+
+    assert!(matches(r#"message = "disk \"full\" (sda)""#, record.clone()));
+    assert!(matches(r#"name = 'it\'s'"#, record.clone()));
+    assert!(matches(r#""name" = "it's""#, record));
+  }
+
+  #[test]
+  fn reads_unquoted_values_with_operators_and_parens() {
+    let record = json!({"service": "web", "query": "a=b"});
+
+    assert!(matches("service~=(api|web)", record.clone()));
+    assert!(matches("(service~=(api|web))", record.clone()));
+    assert!(matches("(query = a=b and service=web)", record.clone()));
+    assert!(!matches("service~=^(api|worker)$", record));
+  }
+
+  #[test]
+  fn text_records_have_no_fields() {
+    let filter = Filter::parse("level != error").unwrap();
+
+    assert!(filter.matches(&Record::Text("level=error".to_string())));
+    assert!(!Filter::parse("level").unwrap().matches(&Record::Text("level".to_string())));
+  }
+
+  #[test]
+  fn explains_what_is_wrong() {
+    assert_eq!(error("level ="), "Filter ended unexpectedly");
+    assert_eq!(error("(level = error"), "Filter ended unexpectedly");
+    assert_eq!(error("level = error)"), "Unexpected `)` in filter");
+    assert_eq!(error("level = error service"), "Unexpected `service` in filter");
+    assert_eq!(error("level and"), "Filter ended unexpectedly");
+    assert_eq!(error("level = \"error"), "Unterminated string in filter");
+    assert_eq!(error("= error"), "Unexpected `=` in filter");
+    assert!(error("service ~= (api").starts_with("Invalid regex in filter"));
+  }
+}
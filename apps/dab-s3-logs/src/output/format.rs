@@ -0,0 +1,341 @@
+use clap::ValueEnum;
+use console::{measure_text_width, pad_str, style, truncate_str, Alignment};
+use serde_json::{Map, Value};
+
+use super::{fields::{self, Field}, filter::FieldPath, reader::Record, OutputOptions};
+
+/// Wide enough for an RFC 3339 time with milliseconds, e.g. `2024-05-01T12:00:00.000Z`
+const TIME_WIDTH: usize = 24;
+const LEVEL_WIDTH: usize = 5;
+const SERVICE_WIDTH: usize = 20;
+/// Width of a selected field's column in the table format, unless it's one the default layout shows
+const FIELD_WIDTH: usize = 20;
+
+const TIME_FIELDS: [&str; 4] = ["timestamp", "@timestamp", "time", "ts"];
+const LEVEL_FIELDS: [&str; 4] = ["level", "severity", "lvl", "log.level"];
+const SERVICE_FIELDS: [&str; 4] = ["service", "app", "kubernetes.container_name", "kubernetes.labels.app"];
+const MESSAGE_FIELDS: [&str; 3] = ["message", "msg", "log"];
+
+/// How each record is written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+  /// One compact JSON object per line
+  #[default]
+  Ndjson,
+  /// Indented JSON
+  Pretty,
+  /// Comma separated values of the selected fields
+  Csv,
+  /// Tab separated values of the selected fields
+  Tsv,
+  /// `key=value` pairs
+  Logfmt,
+  /// Aligned `time level service message` columns, coloured by level on terminals
+  Table,
+}
+
+impl OutputFormat {
+  /// True for formats that can only write a fixed list of fields
+  pub fn requires_fields(&self) -> bool {
+    matches!(self, OutputFormat::Csv | OutputFormat::Tsv)
+  }
+}
+
+/// Header row written once before any records, if the format has one
+pub fn header(options: &OutputOptions) -> Option<String> {
+  let names: Vec<String> = options.fields.iter().map(Field::name).collect();
+
+  match options.format {
+    OutputFormat::Csv => Some(format!("{}\n", join_csv(&names, ','))),
+    OutputFormat::Tsv => Some(format!("{}\n", join_tsv(&names))),
+    // without fields the table's columns are fixed and need no header
+    OutputFormat::Table if !names.is_empty() => Some(format!("{}\n", table_columns(&names, options))),
+    _ => None,
+  }
+}
+
+/// Render a record in the chosen format, including its trailing newline
+pub fn format_record(record: &Record, options: &OutputOptions) -> String {
+  let mut line = match (options.format, record) {
+    (OutputFormat::Ndjson, Record::Json(value)) => projected(value, &options.fields).to_string(),
+    (OutputFormat::Pretty, Record::Json(value)) => {
+      let value = projected(value, &options.fields);
+      serde_json::to_string_pretty(&value).unwrap_or_else(|_| value.to_string())
+    }
+    (OutputFormat::Ndjson | OutputFormat::Pretty, Record::Text(text)) => text.clone(),
+    (OutputFormat::Csv, _) => join_csv(&column_values(record, &options.fields), ','),
+    (OutputFormat::Tsv, _) => join_tsv(&column_values(record, &options.fields)),
+    (OutputFormat::Logfmt, _) => logfmt(record, &options.fields),
+    (OutputFormat::Table, _) => table_row(record, options),
+  };
+  line.push('\n');
+
+  line
+}
+
+/// The whole record when no fields were selected
+fn projected(value: &Value, fields: &[Field]) -> Value {
+  if fields.is_empty() {
+    value.clone()
+  } else {
+    fields::project(value, fields)
+  }
+}
+
+/// Each field's value as text. Text records fill the `message` field, or the first field
+fn column_values(record: &Record, fields: &[Field]) -> Vec<String> {
+  match record {
+    Record::Json(value) => fields.iter()
+      .map(|field| field.lookup(value).map(as_text).unwrap_or_default())
+      .collect(),
+    Record::Text(text) => {
+      let message_column = fields.iter()
+        .position(|field| MESSAGE_FIELDS.contains(&field.path().to_string().as_str()))
+        .unwrap_or(0);
+      (0..fields.len())
+        .map(|index| if index == message_column { text.clone() } else { String::new() })
+        .collect()
+    }
+  }
+}
+
+fn as_text(value: &Value) -> String {
+  match value {
+    Value::String(text) => text.clone(),
+    Value::Null => String::new(),
+    _ => value.to_string(),
+  }
+}
+
+fn join_csv(values: &[String], delimiter: char) -> String {
+  values.iter()
+    .map(|value| {
+      if value.contains(delimiter) || value.contains('"') || value.contains('\n') || value.contains('\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+      } else {
+        value.clone()
+      }
+    })
+    .collect::<Vec<String>>()
+    .join(&delimiter.to_string())
+}
+
+/// TSV can't quote, so tabs and newlines inside values are escaped
+fn join_tsv(values: &[String]) -> String {
+  values.iter()
+    .map(|value| value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r"))
+    .collect::<Vec<String>>()
+    .join("\t")
+}
+
+/// Every field, flattened into dot separated keys, unless fields were selected
+fn logfmt(record: &Record, fields: &[Field]) -> String {
+  let pairs: Vec<(String, String)> = match record {
+    Record::Text(text) => vec![("msg".to_string(), text.clone())],
+    Record::Json(value) if !fields.is_empty() => fields.iter()
+      .filter_map(|field| field.lookup(value).map(|selected| (field.name(), as_text(selected))))
+      .collect(),
+    Record::Json(Value::Object(map)) => {
+      let mut pairs = Vec::new();
+      flatten(map, "", &mut pairs);
+      pairs
+    }
+    Record::Json(value) => vec![("msg".to_string(), as_text(value))],
+  };
+
+  pairs.iter()
+    .map(|(key, value)| format!("{}={}", key, logfmt_value(value)))
+    .collect::<Vec<String>>()
+    .join(" ")
+}
+
+fn flatten(map: &Map<String, Value>, prefix: &str, pairs: &mut Vec<(String, String)>) {
+  for (key, value) in map {
+    let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+    match value {
+      Value::Object(nested) => flatten(nested, &key, pairs),
+      _ => pairs.push((key, as_text(value))),
+    }
+  }
+}
+
+fn logfmt_value(value: &str) -> String {
+  if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '=' || c == '"') {
+    format!("{:?}", value)
+  } else {
+    value.to_string()
+  }
+}
+
+/// `time level service message`, or the selected fields in columns of their own
+fn table_row(record: &Record, options: &OutputOptions) -> String {
+  if !options.fields.is_empty() {
+    return table_columns(&column_values(record, &options.fields), options);
+  }
+
+  let value = match record {
+    Record::Json(value) => value,
+    // text lines up with the message column
+    Record::Text(text) => return format!("{:<w$}  {}", "", text, w = TIME_WIDTH + LEVEL_WIDTH + SERVICE_WIDTH + 4),
+  };
+
+  let time = match options.timestamp_field.as_ref().and_then(|field| field.lookup(value)) {
+    Some(time) => as_text(time),
+    None => first_field(value, &TIME_FIELDS),
+  };
+  let level = first_field(value, &LEVEL_FIELDS);
+  let service = first_field(value, &SERVICE_FIELDS);
+  let message = match first_field_value(value, &MESSAGE_FIELDS) {
+    Some(message) => as_text(message),
+    None => value.to_string(),
+  };
+
+  let level_cell = pad_str(&level.to_uppercase(), LEVEL_WIDTH, Alignment::Left, None).to_string();
+  let level_cell = match level.to_lowercase().as_str() {
+    "error" | "err" | "fatal" | "critical" | "crit" => style(level_cell).red().bold().to_string(),
+    "warn" | "warning" => style(level_cell).yellow().to_string(),
+    "info" => style(level_cell).green().to_string(),
+    "debug" | "trace" => style(level_cell).blue().to_string(),
+    _ => level_cell,
+  };
+  let service_cell = style(pad_str(&service, SERVICE_WIDTH, Alignment::Left, Some("…"))).cyan().to_string();
+
+  let time_cell = style(pad_str(&time, TIME_WIDTH, Alignment::Left, None)).dim().to_string();
+
+  format!("{}  {}  {}  {}", time_cell, level_cell, service_cell, message)
+}
+
+/// Pad every column but the last to its width, cutting off longer values, so rows line up
+fn table_columns(values: &[String], options: &OutputOptions) -> String {
+  let last = values.len().saturating_sub(1);
+
+  values.iter().zip(&options.fields).enumerate()
+    .map(|(index, (value, field))| if index == last {
+      value.clone()
+    } else {
+      let width = column_width(field, options);
+      let value = if measure_text_width(value) > width { truncate_str(value, width, "…") } else { value.into() };
+      pad_str(&value, width, Alignment::Left, None).to_string()
+    })
+    .collect::<Vec<String>>()
+    .join("  ")
+}
+
+/// As wide as the default layout's column for the same field, and never narrower than the field's name
+fn column_width(field: &Field, options: &OutputOptions) -> usize {
+  let path = field.path().to_string();
+  let width = if options.timestamp_field.as_ref() == Some(field.path()) || TIME_FIELDS.contains(&path.as_str()) {
+    TIME_WIDTH
+  } else if LEVEL_FIELDS.contains(&path.as_str()) {
+    LEVEL_WIDTH
+  } else if SERVICE_FIELDS.contains(&path.as_str()) {
+    SERVICE_WIDTH
+  } else {
+    FIELD_WIDTH
+  };
+
+  width.max(measure_text_width(&field.name()))
+}
+
+fn first_field_value<'a>(value: &'a Value, fields: &[&str]) -> Option<&'a Value> {
+  fields.iter().find_map(|field| FieldPath::parse(field).lookup(value))
+}
+
+fn first_field(value: &Value, fields: &[&str]) -> String {
+  first_field_value(value, fields).map(as_text).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use console::strip_ansi_codes;
+  use serde_json::json;
+
+  use super::*;
+
+  fn options(format: OutputFormat, fields: &[&str]) -> OutputOptions {
+    OutputOptions { format, fields: Field::parse_all(fields).unwrap(), ..Default::default() }
+  }
+
+  fn format(record: &Record, format: OutputFormat, fields: &[&str]) -> String {
+    strip_ansi_codes(&format_record(record, &options(format, fields))).to_string()
+  }
+
+  fn record() -> Record {
+    Record::Json(json!({"timestamp": "2024-05-01T12:00:00Z", "level": "error", "service": "api", "message": "disk \"full\", again", "n": 1}))
+  }
+
+  #[test]
+  fn writes_ndjson_and_pretty_json() {
+    let text = Record::Text("plain".to_string());
+
+    assert_eq!(format(&record(), OutputFormat::Ndjson, &["level", "n as count"]), "{\"level\":\"error\",\"count\":1}\n");
+    assert_eq!(format(&record(), OutputFormat::Ndjson, &["n", "level"]), "{\"n\":1,\"level\":\"error\"}\n");
+    assert_eq!(format(&record(), OutputFormat::Pretty, &["n"]), "{\n  \"n\": 1\n}\n");
+    assert_eq!(format(&text, OutputFormat::Ndjson, &[]), "plain\n");
+  }
+
+  #[test]
+  fn writes_csv_and_tsv_with_a_header() {
+    assert_eq!(header(&options(OutputFormat::Csv, &["level", "message as msg"])).as_deref(), Some("level,msg\n"));
+    assert_eq!(format(&record(), OutputFormat::Csv, &["level", "message", "missing"]), "error,\"disk \"\"full\"\", again\",\n");
+
+    assert_eq!(header(&options(OutputFormat::Tsv, &["level", "n"])).as_deref(), Some("level\tn\n"));
+    let tabbed = Record::Json(json!({"message": "a\tb\nc"}));
+    assert_eq!(format(&tabbed, OutputFormat::Tsv, &["message", "n"]), "a\\tb\\nc\t\n");
+  }
+
+  #[test]
+  fn puts_text_records_in_the_message_column() {
+    let text = Record::Text("plain, text".to_string());
+
+    assert_eq!(format(&text, OutputFormat::Csv, &["level", "message"]), ",\"plain, text\"\n");
+  }
+
+  #[test]
+  fn writes_logfmt_pairs() {
+    let nested = Record::Json(json!({"a": {"b": 1}, "msg": "two words", "empty": ""}));
+
+    assert_eq!(format(&nested, OutputFormat::Logfmt, &[]), "a.b=1 msg=\"two words\" empty=\"\"\n");
+    assert_eq!(format(&record(), OutputFormat::Logfmt, &["level", "n as count"]), "level=error count=1\n");
+    assert_eq!(format(&Record::Text("hi".to_string()), OutputFormat::Logfmt, &[]), "msg=hi\n");
+  }
+
+  #[test]
+  fn writes_a_table_header_for_selected_fields() {
+    assert_eq!(header(&options(OutputFormat::Table, &["level", "kubernetes.pod_name as pod"])).as_deref(), Some("level  pod\n"));
+    assert_eq!(header(&options(OutputFormat::Table, &[])), None);
+    assert_eq!(format(&record(), OutputFormat::Table, &["level", "n"]), "error  1\n");
+  }
+
+  #[test]
+  fn aligns_selected_fields_in_table_columns() {
+    let fields = ["service as name", "request.path", "message"];
+    let short = Record::Json(json!({"service": "api", "request": {"path": "/"}, "message": "first"}));
+    let long = Record::Json(json!({"service": "a-service-with-a-long-name", "request": {"path": "/a/much/longer/request/path"}, "message": "second"}));
+
+    let header = header(&options(OutputFormat::Table, &fields)).unwrap();
+    let columns: Vec<usize> = [(&short, "first"), (&long, "second")].iter()
+      .map(|(record, message)| {
+        let row = format(record, OutputFormat::Table, &fields);
+        measure_text_width(&row[..row.find(message).unwrap()])
+      })
+      .collect();
+
+    assert_eq!(header.find("message"), Some(SERVICE_WIDTH + FIELD_WIDTH + 4));
+    assert_eq!(columns, [SERVICE_WIDTH + FIELD_WIDTH + 4; 2]);
+    assert!(format(&long, OutputFormat::Table, &fields).starts_with("a-service-with-a-lo…"));
+  }
+
+  #[test]
+  fn aligns_table_columns() {
+    let short = Record::Json(json!({"ts": "12:00", "level": "info", "service": "api", "msg": "first"}));
+    let long = Record::Json(json!({"timestamp": "2024-05-01T12:00:00.000Z", "level": "warn", "app": "web", "message": "second"}));
+    let text = Record::Text("third".to_string());
+
+    let columns: Vec<usize> = [(short, "first"), (long, "second"), (text, "third")].iter()
+      .map(|(record, message)| format(record, OutputFormat::Table, &[]).find(message).unwrap())
+      .collect();
+
+    assert_eq!(columns, [TIME_WIDTH + LEVEL_WIDTH + SERVICE_WIDTH + 6; 3]);
+  }
+}
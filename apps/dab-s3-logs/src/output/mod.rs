@@ -1 +1,26 @@
+use fields::Field;
+use filter::{FieldPath, Filter};
+use format::OutputFormat;
+
+pub mod decoders;
+pub mod errors;
+pub mod fields;
+pub mod filter;
+pub mod format;
+pub mod reader;
+pub mod sort;
 pub mod stdout;
+
+/// Options shared by every file written to stdout
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+  /// Only records matching the filter are written
+  pub filter: Option<Filter>,
+  /// Merge every file into order by this timestamp field instead of writing files one by one
+  pub sort_by: Option<FieldPath>,
+  /// Field holding each record's timestamp, shown first in the table format
+  pub timestamp_field: Option<FieldPath>,
+  pub format: OutputFormat,
+  /// Fields every record is projected down to. Also the columns of the CSV, TSV and table formats
+  pub fields: Vec<Field>,
+}
@@ -0,0 +1,441 @@
+use std::{collections::VecDeque, io::{self, BufRead, BufReader, Cursor, Read}, mem};
+
+use log::debug;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{decoders::LogReader, errors::OutputError};
+
+/// Most bytes held back while checking whether a file starting with `[` really is a JSON array
+const MAX_ARRAY_PROBE_BYTES: usize = 1024 * 1024;
+/// Longest multi-line document, such as a pretty printed object, that lines are joined into
+const MAX_DOCUMENT_BYTES: usize = 1024 * 1024;
+
+/// A single entry from a log file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+  /// A line holding a whole JSON object or array, or an element of a JSON array document
+  Json(Value),
+  /// A line that isn't JSON, passed through untouched
+  Text(String),
+}
+
+/// Stream every record in a log file to `emit`, one at a time.
+///
+/// Handles a single JSON array document, newline delimited JSON and pretty printed documents
+/// spread over several lines. Lines that aren't JSON are emitted as text. Only one record is held
+/// in memory at once.
+pub fn for_each_record<F>(reader: LogReader, mut emit: F) -> Result<(), OutputError>
+where
+  F: FnMut(Record) -> io::Result<()>,
+{
+  for record in Records::new(reader) {
+    emit(record?)?;
+  }
+
+  Ok(())
+}
+
+/// Iterator over the records of a log file, see [`for_each_record`]
+pub struct Records {
+  state: State,
+}
+
+enum State {
+  /// At the start of the file, or just after a JSON array closed
+  Start(LogReader),
+  Lines(Lines),
+  Array(Recorder<LogReader>),
+  Done,
+}
+
+impl Records {
+  pub fn new(reader: LogReader) -> Self {
+    Self { state: State::Start(reader) }
+  }
+
+  /// Work out whether the file, or what follows an array in it, is a JSON array or lines,
+  /// returning the first record if any
+  fn start(&mut self, mut reader: LogReader) -> Option<Result<Record, OutputError>> {
+    match first_non_whitespace_byte(&mut reader) {
+      Ok(Some(b'[')) => self.start_array(reader),
+      Ok(Some(_)) => {
+        self.state = State::Lines(Lines::new(reader));
+        self.next_line()
+      }
+      Ok(None) => None,
+      Err(e) => Some(Err(e.into())),
+    }
+  }
+
+  /// Plain text logs often start with `[` too (`[INFO] ...`, `[2024-10-01 12:00:00] ...`), so the
+  /// bytes read are held back until the first element parses and is followed by `,` or `]`. If it
+  /// isn't, the file is replayed as lines instead.
+  fn start_array(&mut self, reader: LogReader) -> Option<Result<Record, OutputError>> {
+    let mut recorder = Recorder { inner: reader, recorded: Vec::new(), recording: true, last: None };
+    recorder.consume(1);
+
+    let result = match read_array_separator(&mut recorder) {
+      Ok(true) => {
+        let mut deserializer = serde_json::Deserializer::from_reader(&mut recorder);
+        match Value::deserialize(&mut deserializer) {
+          Ok(value) => match element_ends(&value, &mut recorder) {
+            Ok(true) => Ok(Some(value)),
+            Ok(false) => Err(OutputError::from(<serde_json::Error as serde::de::Error>::custom("first element isn't followed by `,` or `]`"))),
+            Err(e) => Err(e.into()),
+          },
+          Err(e) => Err(e.into()),
+        }
+      }
+      Ok(false) => Ok(None),
+      Err(e) => Err(e.into()),
+    };
+
+    match result {
+      Ok(Some(value)) => {
+        recorder.recording = false;
+        recorder.recorded = Vec::new();
+        self.state = if closes_array(&value, &recorder) { State::Start(recorder.inner) } else { State::Array(recorder) };
+        Some(Ok(Record::Json(value)))
+      }
+      Ok(None) => self.start(recorder.inner),
+      Err(e) if recorder.recording => {
+        debug!("File isn't a JSON array, reading it as lines: {}", e);
+        let replay: LogReader = Box::new(BufReader::new(Cursor::new(recorder.recorded).chain(recorder.inner)));
+        self.state = State::Lines(Lines::new(replay));
+        self.next_line()
+      }
+      Err(e) => Some(Err(e)),
+    }
+  }
+
+  fn next_line(&mut self) -> Option<Result<Record, OutputError>> {
+    let record = match &mut self.state {
+      State::Lines(lines) => lines.next_record(),
+      _ => return None,
+    };
+    if record.is_none() {
+      self.state = State::Done;
+    }
+
+    record
+  }
+
+  fn next_element(&mut self, mut reader: Recorder<LogReader>) -> Option<Result<Record, OutputError>> {
+    match read_array_separator(&mut reader) {
+      Ok(true) => {}
+      // whatever follows the array is read like a file of its own: another array, lines or nothing
+      Ok(false) => return self.start(reader.inner),
+      Err(e) => return Some(Err(e.into())),
+    }
+
+    // a new deserializer per element keeps memory bounded; each element ends on its own closing
+    // delimiter so nothing is lost between them, apart from the byte swallowed after a number
+    let value = Value::deserialize(&mut serde_json::Deserializer::from_reader(&mut reader));
+    match value {
+      Ok(value) => {
+        self.state = if closes_array(&value, &reader) { State::Start(reader.inner) } else { State::Array(reader) };
+        Some(Ok(Record::Json(value)))
+      }
+      Err(e) => Some(Err(e.into())),
+    }
+  }
+}
+
+impl Iterator for Records {
+  type Item = Result<Record, OutputError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    match mem::replace(&mut self.state, State::Done) {
+      State::Start(reader) => self.start(reader),
+      state @ State::Lines(_) => {
+        self.state = state;
+        self.next_line()
+      }
+      State::Array(reader) => self.next_element(reader),
+      State::Done => None,
+    }
+  }
+}
+
+/// Lines of a file that isn't a single JSON array. A line that opens an object or array without
+/// closing it is joined with the lines after it until the document parses. Invalid UTF-8 is
+/// replaced rather than failing the file, as text logs aren't always clean
+struct Lines {
+  reader: LogReader,
+  /// Lines read ahead for a document that never closed, to be read again as records of their own
+  pending: VecDeque<String>,
+}
+
+impl Lines {
+  fn new(reader: LogReader) -> Self {
+    Self { reader, pending: VecDeque::new() }
+  }
+
+  fn next_line(&mut self) -> Option<io::Result<String>> {
+    if let Some(line) = self.pending.pop_front() {
+      return Some(Ok(line));
+    }
+
+    let mut line = Vec::new();
+    match self.reader.read_until(b'\n', &mut line) {
+      Ok(0) => None,
+      Ok(_) => {
+        if line.ends_with(b"\n") {
+          line.pop();
+          if line.ends_with(b"\r") {
+            line.pop();
+          }
+        }
+        Some(Ok(String::from_utf8_lossy(&line).into_owned()))
+      }
+      Err(e) => Some(Err(e)),
+    }
+  }
+
+  fn next_record(&mut self) -> Option<Result<Record, OutputError>> {
+    loop {
+      let line = match self.next_line()? {
+        Ok(line) => line,
+        Err(e) => return Some(Err(e.into())),
+      };
+      let trimmed = line.trim();
+      if trimmed.is_empty() {
+        continue;
+      }
+      if trimmed.starts_with(['{', '[']) {
+        return Some(self.document(line));
+      }
+      // only whole objects and arrays are records, a line that's a bare number or string is text
+      return Some(Ok(Record::Text(line)));
+    }
+  }
+
+  /// Parse a line opening an object or array, joining the lines after it while the document is
+  /// incomplete. If it never parses, the first line is text and the rest are read again
+  fn document(&mut self, first: String) -> Result<Record, OutputError> {
+    let mut document = first.clone();
+    let mut joined = Vec::new();
+    loop {
+      match serde_json::from_str::<Value>(&document) {
+        Ok(value) => return Ok(Record::Json(value)),
+        Err(e) if e.is_eof() && document.len() < MAX_DOCUMENT_BYTES => match self.next_line() {
+          Some(Ok(line)) => {
+            document.push('\n');
+            document.push_str(&line);
+            joined.push(line);
+          }
+          Some(Err(e)) => return Err(e.into()),
+          None => break,
+        },
+        Err(_) => break,
+      }
+    }
+
+    for line in joined.into_iter().rev() {
+      self.pending.push_front(line);
+    }
+
+    Ok(Record::Text(first))
+  }
+}
+
+/// Consume leading whitespace, returning the next byte without consuming it
+fn first_non_whitespace_byte<R: BufRead>(reader: &mut R) -> io::Result<Option<u8>> {
+  loop {
+    let buf = reader.fill_buf()?;
+    if buf.is_empty() {
+      return Ok(None);
+    }
+
+    match buf.iter().position(|byte| !byte.is_ascii_whitespace()) {
+      Some(position) => {
+        let byte = buf[position];
+        reader.consume(position);
+        return Ok(Some(byte));
+      }
+      None => {
+        let len = buf.len();
+        reader.consume(len);
+      }
+    }
+  }
+}
+
+/// True when an array element is followed by `,` or `]`. Parsing a number swallows the byte after
+/// it, which is the last one the recorder consumed
+fn element_ends<R: BufRead>(value: &Value, recorder: &mut Recorder<R>) -> io::Result<bool> {
+  let swallowed = match value {
+    Value::Number(_) => recorder.last,
+    _ => None,
+  };
+  let next = match swallowed {
+    Some(byte) if !byte.is_ascii_whitespace() => Some(byte),
+    _ => first_non_whitespace_byte(recorder)?,
+  };
+
+  Ok(matches!(next, Some(b',' | b']')))
+}
+
+/// True when parsing a number element swallowed the `]` closing its array
+fn closes_array<R>(value: &Value, reader: &Recorder<R>) -> bool {
+  value.is_number() && reader.last == Some(b']')
+}
+
+/// Skip to the next array element, returning false once the array's `]` is consumed
+fn read_array_separator<R: BufRead>(reader: &mut R) -> io::Result<bool> {
+  loop {
+    match first_non_whitespace_byte(reader)? {
+      Some(b',') => reader.consume(1),
+      Some(b']') => {
+        reader.consume(1);
+        return Ok(false);
+      }
+      None => return Ok(false),
+      Some(_) => return Ok(true),
+    }
+  }
+}
+
+/// Keeps a copy of everything consumed until it grows past `MAX_ARRAY_PROBE_BYTES`
+struct Recorder<R> {
+  inner: R,
+  recorded: Vec<u8>,
+  recording: bool,
+  /// Last byte consumed
+  last: Option<u8>,
+}
+
+impl<R: BufRead> Read for Recorder<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let available = self.fill_buf()?;
+    let read = available.len().min(buf.len());
+    buf[..read].copy_from_slice(&available[..read]);
+    self.consume(read);
+
+    Ok(read)
+  }
+}
+
+impl<R: BufRead> BufRead for Recorder<R> {
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    self.inner.fill_buf()
+  }
+
+  fn consume(&mut self, amt: usize) {
+    if self.recording && self.recorded.len() + amt > MAX_ARRAY_PROBE_BYTES {
+      self.recording = false;
+      self.recorded = Vec::new();
+    }
+    // already buffered by the last fill_buf, so this doesn't read
+    if let Ok(buf) = self.inner.fill_buf() {
+      let consumed = &buf[..amt.min(buf.len())];
+      if self.recording {
+        self.recorded.extend_from_slice(consumed);
+      }
+      if let Some(&last) = consumed.last() {
+        self.last = Some(last);
+      }
+    }
+    self.inner.consume(amt);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  fn records(contents: &str) -> Vec<Record> {
+    let reader: LogReader = Box::new(Cursor::new(contents.as_bytes().to_vec()));
+    Records::new(reader).collect::<Result<_, _>>().unwrap()
+  }
+
+  fn text(line: &str) -> Record {
+    Record::Text(line.to_string())
+  }
+
+  #[test]
+  fn reads_ndjson_line_by_line() {
+    let records = records("{\"n\":1}\n\n{\"n\":2}\n");
+
+    assert_eq!(records, [Record::Json(json!({"n": 1})), Record::Json(json!({"n": 2}))]);
+  }
+
+  #[test]
+  fn reads_a_json_array_element_by_element() {
+    let records = records("[\n  {\"n\": 1},\n  {\"n\": 2}\n]\n");
+
+    assert_eq!(records, [Record::Json(json!({"n": 1})), Record::Json(json!({"n": 2}))]);
+  }
+
+  #[test]
+  fn reads_a_pretty_printed_object() {
+    let records = records("{\n  \"level\": \"info\",\n  \"nested\": {\n    \"n\": 1\n  }\n}\n");
+
+    assert_eq!(records, [Record::Json(json!({"level": "info", "nested": {"n": 1}}))]);
+  }
+
+  #[test]
+  fn reads_text_json_and_pretty_printed_documents_mixed() {
+    let records = records("starting up\n{\"n\":1}\n{\n  \"n\": 2\n}\nshutting down\n");
+
+    assert_eq!(records, [text("starting up"), Record::Json(json!({"n": 1})), Record::Json(json!({"n": 2})), text("shutting down")]);
+  }
+
+  #[test]
+  fn reads_lines_again_after_a_document_that_never_closes() {
+    let records = records("{ not json\nplain line\n{\"n\":1}\n");
+
+    assert_eq!(records, [text("{ not json"), text("plain line"), Record::Json(json!({"n": 1}))]);
+  }
+
+  #[test]
+  fn reads_text_starting_with_a_bracketed_timestamp_as_lines() {
+    let read = records("[2024-10-01 12:00:00] ERROR disk full\n[2024-10-01 12:00:01] INFO retrying\n");
+
+    assert_eq!(read, [text("[2024-10-01 12:00:00] ERROR disk full"), text("[2024-10-01 12:00:01] INFO retrying")]);
+    assert_eq!(records("{\"n\":1}\n[2024-10-01 12:00:00] ERROR disk full\n"), [Record::Json(json!({"n": 1})), text("[2024-10-01 12:00:00] ERROR disk full")]);
+  }
+
+  #[test]
+  fn reads_arrays_of_numbers() {
+    assert_eq!(records("[1, 2 ,3]"), [Record::Json(json!(1)), Record::Json(json!(2)), Record::Json(json!(3))]);
+    assert_eq!(records("[2024]"), [Record::Json(json!(2024))]);
+  }
+
+  #[test]
+  fn reads_arrays_one_after_another() {
+    assert_eq!(records("[{\"n\":1}]\n[{\"n\":2}]"), [Record::Json(json!({"n": 1})), Record::Json(json!({"n": 2}))]);
+    assert_eq!(records("[1,2]\n[3,4]"), [1, 2, 3, 4].map(|n| Record::Json(json!(n))));
+    assert_eq!(records("[1]\n[2 ]"), [Record::Json(json!(1)), Record::Json(json!(2))]);
+  }
+
+  #[test]
+  fn reads_lines_after_an_array() {
+    let records = records("[{\"n\":1}]\n{\"n\":2}\nplain");
+
+    assert_eq!(records, [Record::Json(json!({"n": 1})), Record::Json(json!({"n": 2})), text("plain")]);
+  }
+
+  #[test]
+  fn reads_lines_of_bare_values_as_text() {
+    assert_eq!(records("42\n\"quoted\"\n"), [text("42"), text("\"quoted\"")]);
+  }
+
+  #[test]
+  fn replaces_invalid_utf8_in_text_lines() {
+    let reader: LogReader = Box::new(Cursor::new(b"caf\xe9\r\nok\n".to_vec()));
+    let records: Vec<Record> = Records::new(reader).collect::<Result<_, _>>().unwrap();
+
+    assert_eq!(records, [text("caf\u{fffd}"), text("ok")]);
+  }
+
+  #[test]
+  fn reads_bracketed_text_as_lines() {
+    let records = records("[INFO] starting\n[WARN] slow\n");
+
+    assert_eq!(records, [text("[INFO] starting"), text("[WARN] slow")]);
+  }
+}
@@ -0,0 +1,206 @@
+use std::{cmp::{Ordering, Reverse}, collections::BinaryHeap, io, path::{Path, PathBuf}};
+
+use chrono::{DateTime, NaiveDateTime};
+use log::{debug, warn};
+use serde_json::Value;
+
+use super::{decoders::DecoderRegistry, errors::OutputError, filter::FieldPath, reader::{Record, Records}};
+
+/// Records read from the start of each file to find its first timestamp
+const PROBE_RECORDS: usize = 1000;
+const NAIVE_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"];
+
+/// Write the records of every file in timestamp order using a k-way merge.
+///
+/// Each file is assumed to be roughly in order already, as log shippers write them. Files are only
+/// opened once the merge reaches their first timestamp, so only files that overlap in time are held
+/// open together.
+///
+/// Records without a parseable timestamp are ordered as follows:
+/// - a record takes the timestamp of the record before it in the same file, keeping things like
+///   multi line stack traces next to the line that logged them
+/// - records before the first timestamp in a file take that first timestamp
+/// - files without any timestamps are written last
+///
+/// Ties are broken by file path, then by position within the file.
+pub fn merge_sorted<F>(files: &[PathBuf], field: &FieldPath, mut emit: F) -> Result<(), OutputError>
+where
+  F: FnMut(Record) -> io::Result<()>,
+{
+  let registry = DecoderRegistry::default();
+
+  let mut paths = files.to_vec();
+  paths.sort();
+
+  let mut pending: Vec<(i64, usize)> = Vec::new();
+  for (index, path) in paths.iter().enumerate() {
+    match first_timestamp(&registry, path, field) {
+      Ok(Some(first)) => pending.push((first, index)),
+      Ok(None) => debug!("Skipping {}: unsupported file format", path.display()),
+      Err(e) => warn!("Skipping {}: {}", path.display(), e),
+    }
+  }
+  // reversed so the earliest file can be popped off the end
+  pending.sort_by(|a, b| b.cmp(a));
+
+  let mut cursors: Vec<Option<Cursor>> = (0..paths.len()).map(|_| None).collect();
+  let mut heap: BinaryHeap<Reverse<Entry>> = BinaryHeap::new();
+
+  loop {
+    // open every file that starts before the next record to be written
+    while let Some(&(first, index)) = pending.last() {
+      let next_key = heap.peek().map(|Reverse(entry)| entry.timestamp);
+      if next_key.is_some_and(|next| first > next) {
+        break;
+      }
+      pending.pop();
+
+      let reader = match registry.open(&paths[index])? {
+        Some(reader) => reader,
+        None => continue,
+      };
+      let mut cursor = Cursor { records: Records::new(reader), last_timestamp: first, position: 0 };
+      if let Some(entry) = cursor.next_entry(index, field)? {
+        heap.push(Reverse(entry));
+      }
+      cursors[index] = Some(cursor);
+    }
+
+    let Reverse(entry) = match heap.pop() {
+      Some(entry) => entry,
+      None => break,
+    };
+
+    let file = entry.file;
+    emit(entry.record)?;
+
+    if let Some(cursor) = cursors[file].as_mut() {
+      match cursor.next_entry(file, field)? {
+        Some(next) => heap.push(Reverse(next)),
+        None => cursors[file] = None,
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Timestamp of a record in nanoseconds since the epoch.
+///
+/// Accepts RFC 3339 strings, `YYYY-MM-DD HH:MM:SS` style strings (as UTC) and epoch numbers in
+/// seconds, milliseconds, microseconds or nanoseconds.
+pub fn parse_timestamp(value: &Value) -> Option<i64> {
+  match value {
+    Value::Number(number) => number.as_f64().and_then(epoch_to_nanos),
+    Value::String(text) => {
+      if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
+        return datetime.timestamp_nanos_opt();
+      }
+      for format in NAIVE_FORMATS {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(text, format) {
+          return datetime.and_utc().timestamp_nanos_opt();
+        }
+      }
+      text.parse::<f64>().ok().and_then(epoch_to_nanos)
+    }
+    _ => None,
+  }
+}
+
+/// Guess the unit of an epoch timestamp from its magnitude
+fn epoch_to_nanos(epoch: f64) -> Option<i64> {
+  let magnitude = epoch.abs();
+  let nanos = if magnitude < 1e11 {
+    epoch * 1e9
+  } else if magnitude < 1e14 {
+    epoch * 1e6
+  } else if magnitude < 1e17 {
+    epoch * 1e3
+  } else {
+    epoch
+  };
+
+  if nanos.is_finite() && nanos.abs() < i64::MAX as f64 {
+    Some(nanos as i64)
+  } else {
+    None
+  }
+}
+
+fn record_timestamp(record: &Record, field: &FieldPath) -> Option<i64> {
+  match record {
+    Record::Json(value) => field.lookup(value).and_then(parse_timestamp),
+    Record::Text(_) => None,
+  }
+}
+
+/// First timestamp in a file, `i64::MAX` when there isn't one, or `None` when it can't be decoded
+fn first_timestamp(registry: &DecoderRegistry, path: &Path, field: &FieldPath) -> Result<Option<i64>, OutputError> {
+  let reader = match registry.open(path)? {
+    Some(reader) => reader,
+    None => return Ok(None),
+  };
+
+  for record in Records::new(reader).take(PROBE_RECORDS) {
+    if let Some(timestamp) = record_timestamp(&record?, field) {
+      return Ok(Some(timestamp));
+    }
+  }
+
+  Ok(Some(i64::MAX))
+}
+
+struct Cursor {
+  records: Records,
+  last_timestamp: i64,
+  position: u64,
+}
+
+impl Cursor {
+  fn next_entry(&mut self, file: usize, field: &FieldPath) -> Result<Option<Entry>, OutputError> {
+    let record = match self.records.next() {
+      Some(record) => record?,
+      None => return Ok(None),
+    };
+
+    if let Some(timestamp) = record_timestamp(&record, field) {
+      self.last_timestamp = timestamp;
+    }
+    self.position += 1;
+
+    Ok(Some(Entry { timestamp: self.last_timestamp, file, position: self.position, record }))
+  }
+}
+
+struct Entry {
+  timestamp: i64,
+  file: usize,
+  position: u64,
+  record: Record,
+}
+
+impl Entry {
+  fn key(&self) -> (i64, usize, u64) {
+    (self.timestamp, self.file, self.position)
+  }
+}
+
+impl PartialEq for Entry {
+  fn eq(&self, other: &Self) -> bool {
+    self.key() == other.key()
+  }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Entry {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self.key().cmp(&other.key())
+  }
+}
@@ -1,49 +1,132 @@
-use std::{io::{self, Read, Write}, path::Path};
+use std::{io::{self, BufRead, BufReader, ErrorKind, Read, Write}, path::{Path, PathBuf}};
 use file_format::FileFormat;
-use flate2::read::GzDecoder;
-use serde_json::{Result, Value};
+use log::warn;
 
-pub fn output_logfile (path: &Path) {
-  if is_gzipped(path) {
-      let val = parse_gzipped_logfile(path).unwrap();
-      pipe_logs_to_stdout(val);
+use super::{decoders::DecoderRegistry, errors::OutputError, filter::FieldPath, format, reader::{for_each_record, Record}, sort::merge_sorted, OutputOptions};
+
+/// Bytes of a streamed log read before detecting its format
+const FORMAT_SAMPLE_BYTES: u64 = 8 * 1024;
+
+pub fn output_logfile (path: &Path, options: &OutputOptions) -> Result<(), OutputError> {
+  let registry = DecoderRegistry::default();
+
+  match registry.open(path)? {
+    Some(reader) => for_each_record(reader, |record| write_record(record, options)).map_err(stdout_closed),
+    None => {
+      warn!("Skipping {}: unsupported file format", path.display());
+      Ok(())
+    }
+  }
+}
+
+/// Write the records of a log that's read as it arrives, detecting its format from a sample of its first bytes
+pub fn output_stream (name: &str, reader: impl Read + Send + 'static, options: &OutputOptions) -> Result<(), OutputError> {
+  let registry = DecoderRegistry::default();
+  let (format, reader) = sample_format(reader)?;
+
+  match registry.decode(format, Box::new(reader))? {
+    Some(reader) => for_each_record(reader, |record| write_record(record, options)).map_err(stdout_closed),
+    None => {
+      warn!("Skipping {}: unsupported file format", name);
+      Ok(())
+    }
   }
 }
 
-fn is_gzipped (path: &Path) -> bool {
-  let fmt = FileFormat::from_file(path).unwrap();
+/// Detect the format from up to `FORMAT_SAMPLE_BYTES`, as a body can arrive a few bytes at a time.
+/// The returned reader starts with the sample
+fn sample_format (mut reader: impl Read + Send + 'static) -> io::Result<(FileFormat, impl BufRead + Send + 'static)> {
+  let mut sample = Vec::new();
+  reader.by_ref().take(FORMAT_SAMPLE_BYTES).read_to_end(&mut sample)?;
+  let format = FileFormat::from_bytes(&sample);
 
-  matches!(fmt, FileFormat::Gzip)
+  Ok((format, BufReader::new(io::Cursor::new(sample).chain(reader))))
 }
 
-fn parse_gzipped_logfile (path: &Path) -> Result<Value> {
-  let bytes = std::fs::read(path).unwrap();
-  let mut d = GzDecoder::new(&bytes[..]);
+/// Write the records of every file merged into timestamp order, see [`merge_sorted`]
+pub fn output_sorted (files: &[PathBuf], timestamp_field: &FieldPath, options: &OutputOptions) -> Result<(), OutputError> {
+  merge_sorted(files, timestamp_field, |record| write_record(record, options)).map_err(stdout_closed)
+}
 
-  let mut s = String::new();
-  d.read_to_string(&mut s).unwrap();
+/// True when writing failed because stdout was closed, which stops the output rather than failing it
+pub fn is_stdout_closed(e: &io::Error) -> bool {
+  e.kind() == ErrorKind::BrokenPipe
+}
 
-  serde_json::from_str(&s)
+/// Tell a closed stdout apart from failing to read, as every write after it fails the same way
+fn stdout_closed(e: OutputError) -> OutputError {
+  match e {
+    OutputError::ReadError(e) if is_stdout_closed(&e) => OutputError::StdoutClosed,
+    e => e,
+  }
 }
 
-fn pipe_logs_to_stdout (val: Value) {
-  if val.is_array() {
-      val.as_array().unwrap().iter().for_each(|obj| {
-          let _ = pipe_json_obj_to_stdout(obj.clone());
-      });
+fn write_record (record: Record, options: &OutputOptions) -> io::Result<()> {
+  match &options.filter {
+    Some(filter) if !filter.matches(&record) => Ok(()),
+    _ => pipe_record_to_stdout(record, options),
   }
+}
 
-  if val.is_object() {
-      let _ = pipe_json_obj_to_stdout(val);
+/// Write the format's header row, if it has one. Call once before writing any records
+pub fn output_header (options: &OutputOptions) -> io::Result<()> {
+  match format::header(options) {
+    Some(header) => io::stdout().lock().write_all(header.as_bytes()),
+    None => Ok(()),
   }
 }
 
-fn pipe_json_obj_to_stdout (val: Value) -> Result<()>{
-  let mut stdout = io::stdout();
-  let mut bytes: Vec<u8> = Vec::new();
-  serde_json::to_writer(&mut bytes, &val).unwrap();
+/// Each record goes out in a single write so concurrent files never interleave mid line
+fn pipe_record_to_stdout (record: Record, options: &OutputOptions) -> io::Result<()> {
+  let line = format::format_record(&record, options);
+
+  io::stdout().lock().write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+  use flate2::{write::GzEncoder, Compression};
 
-  let _ = stdout.write_all(&bytes);
-  
-  Ok(())
-}
\ No newline at end of file
+  use super::*;
+
+  /// Hands out at most one byte per read, like a body arriving in tiny chunks
+  struct Trickle(io::Cursor<Vec<u8>>);
+
+  impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      let len = buf.len().min(1);
+      self.0.read(&mut buf[..len])
+    }
+  }
+
+  #[test]
+  fn detects_the_format_of_a_log_arriving_a_byte_at_a_time() {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b"{\"level\":\"info\"}\n").unwrap();
+    let gzip = encoder.finish().unwrap();
+
+    let (format, mut reader) = sample_format(Trickle(io::Cursor::new(gzip.clone()))).unwrap();
+
+    assert_eq!(format, FileFormat::Gzip);
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).unwrap();
+    assert_eq!(read, gzip, "the sample is read again");
+  }
+
+  #[test]
+  fn tells_a_closed_stdout_from_other_errors() {
+    assert!(matches!(stdout_closed(OutputError::ReadError(ErrorKind::BrokenPipe.into())), OutputError::StdoutClosed));
+    assert!(matches!(stdout_closed(OutputError::ReadError(ErrorKind::NotFound.into())), OutputError::ReadError(_)));
+  }
+
+  #[test]
+  fn keeps_the_rest_of_a_log_longer_than_the_sample() {
+    let log = "info\n".repeat(10_000);
+
+    let (_, mut reader) = sample_format(io::Cursor::new(log.clone().into_bytes())).unwrap();
+
+    let mut read = String::new();
+    reader.read_to_string(&mut read).unwrap();
+    assert_eq!(read, log);
+  }
+}
@@ -0,0 +1,81 @@
+use std::{fs, io::ErrorKind, path::{Path, PathBuf}, time::Duration};
+
+use aws::s3::checksum::to_hex;
+use chrono::Utc;
+use md5::{Digest, Md5};
+use log::debug;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::errors::CacheError;
+
+/// Values saved as JSON files in a directory, each with its own expiry
+#[derive(Debug, Clone)]
+pub struct FileCache {
+  dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<T> {
+  key: String,
+  /// Unix time after which the value is stale, `None` to keep it forever
+  expires_at: Option<i64>,
+  value: T,
+}
+
+impl FileCache {
+  pub fn new(dir: &Path) -> Self {
+    Self { dir: dir.to_path_buf() }
+  }
+
+  /// The value saved under `key`, or `None` when there isn't one, it has expired or it can't be read
+  pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+    let contents = fs::read(self.path(key)).ok()?;
+    let entry: CacheEntry<T> = match serde_json::from_slice(&contents) {
+      Ok(entry) => entry,
+      Err(e) => {
+        debug!("Ignoring unreadable cache entry {}: {}", key, e);
+        return None;
+      }
+    };
+    if entry.key != key || entry.expires_at.is_some_and(|expires_at| expires_at <= Utc::now().timestamp()) {
+      return None;
+    }
+
+    Some(entry.value)
+  }
+
+  /// Save a value under `key` for `ttl`, or until it's removed without one. The file is written
+  /// aside and renamed into place so a concurrent read never sees half of it
+  pub fn put<T: Serialize>(&self, key: &str, value: &T, ttl: Option<Duration>) -> Result<(), CacheError> {
+    let expires_at = ttl.map(|ttl| Utc::now().timestamp() + ttl.as_secs() as i64);
+    let contents = serde_json::to_vec(&CacheEntry { key: key.to_string(), expires_at, value })?;
+
+    fs::create_dir_all(&self.dir).map_err(CacheError::WriteError)?;
+    let path = self.path(key);
+    let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&temp_path, contents).map_err(CacheError::WriteError)?;
+    fs::rename(&temp_path, &path).map_err(CacheError::WriteError)?;
+
+    Ok(())
+  }
+
+  pub fn remove(&self, key: &str) -> Result<(), CacheError> {
+    match fs::remove_file(self.path(key)) {
+      Err(e) if e.kind() != ErrorKind::NotFound => Err(CacheError::WriteError(e)),
+      _ => Ok(()),
+    }
+  }
+
+  /// Remove every value, expired or not
+  pub fn clear(&self) -> Result<(), CacheError> {
+    match fs::remove_dir_all(&self.dir) {
+      Err(e) if e.kind() != ErrorKind::NotFound => Err(CacheError::WriteError(e)),
+      _ => Ok(()),
+    }
+  }
+
+  /// Keys can be anything, so files are named after their MD5, which stays the same across builds
+  fn path(&self, key: &str) -> PathBuf {
+    self.dir.join(format!("{}.json", to_hex(&Md5::digest(key.as_bytes()))))
+  }
+}
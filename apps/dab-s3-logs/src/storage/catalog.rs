@@ -0,0 +1,98 @@
+use std::{path::{Path, PathBuf}, sync::{Arc, Mutex}};
+
+use aws::{client::ClientOptions, s3::{buckets::{buckets_file_path, dto_to_bucket, get_buckets, load_buckets_file, save_buckets_to_file, BucketsContainer}, store::ObjectStore}};
+use aws_sdk_s3::types::Bucket;
+use log::debug;
+
+use super::errors::CatalogError;
+
+/// Catalog name of the credentials used without a profile
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Buckets visible to a profile, saved under the data directory so picking or completing a bucket
+/// doesn't need a ListBuckets call every time. A profile has a separate catalog per endpoint, as an
+/// S3 compatible store holds different buckets from AWS
+#[derive(Debug, Clone)]
+pub struct BucketCatalog {
+  data_directory: PathBuf,
+  profile: String,
+  /// Name the catalog is saved under, the profile followed by the endpoint when there is one
+  name: String,
+  /// Shared with [`crate::app::App::buckets`], filled once the catalog is read
+  buckets: Arc<Mutex<Vec<Bucket>>>,
+}
+
+impl BucketCatalog {
+  pub fn new(data_directory: &Path, options: &ClientOptions, buckets: Arc<Mutex<Vec<Bucket>>>) -> Self {
+    let profile = options.profile.as_deref().unwrap_or(DEFAULT_PROFILE).to_string();
+    let name = match &options.endpoint_url {
+      Some(endpoint_url) => format!("{}@{}", profile, file_name_safe(endpoint_url)),
+      None => profile.clone(),
+    };
+
+    Self { data_directory: data_directory.to_path_buf(), profile, name, buckets }
+  }
+
+  pub fn profile(&self) -> &str {
+    &self.profile
+  }
+
+  /// The saved catalog, `None` when the profile's buckets were never listed
+  pub fn load(&self) -> Result<Option<BucketsContainer>, CatalogError> {
+    if !buckets_file_path(&self.name, &self.data_directory).exists() {
+      return Ok(None);
+    }
+
+    let container = load_buckets_file(&self.name, &self.data_directory)?;
+    self.remember(container.buckets.iter().map(dto_to_bucket).collect());
+
+    Ok(Some(container))
+  }
+
+  /// List the profile's buckets from S3 and save them over the catalog
+  pub async fn refresh<C: ObjectStore>(&self, client: &C) -> Result<Vec<Bucket>, CatalogError> {
+    let buckets = get_buckets(client).await?;
+    save_buckets_to_file(&buckets, &self.name, self.data_directory.clone())?;
+    debug!("Saved {} buckets for profile {}", buckets.len(), self.profile);
+    self.remember(buckets.clone());
+
+    Ok(buckets)
+  }
+
+  /// Bucket names from memory, then the saved catalog, listing them from S3 only when neither has them
+  pub async fn names<C: ObjectStore>(&self, client: &C) -> Result<Vec<String>, CatalogError> {
+    let mut names = self.cached_names();
+    if names.is_empty() {
+      names = match self.load()? {
+        Some(container) => container.buckets.into_iter().map(|bucket| bucket.name).collect(),
+        None => self.refresh(client).await?.into_iter().filter_map(|bucket| bucket.name).collect(),
+      };
+    }
+
+    Ok(names)
+  }
+
+  /// Bucket names from the saved catalog alone, empty when there isn't one or it can't be read
+  pub fn saved_names(&self) -> Vec<String> {
+    match self.load() {
+      Ok(Some(container)) => container.buckets.into_iter().map(|bucket| bucket.name).collect(),
+      _ => Vec::new(),
+    }
+  }
+
+  fn cached_names(&self) -> Vec<String> {
+    self.buckets.lock().unwrap().iter().filter_map(|bucket| bucket.name.clone()).collect()
+  }
+
+  fn remember(&self, buckets: Vec<Bucket>) {
+    *self.buckets.lock().unwrap() = buckets;
+  }
+}
+
+/// An endpoint URL as part of a file name, e.g. `http-localhost-9000` for `http://localhost:9000/`
+fn file_name_safe(endpoint_url: &str) -> String {
+  endpoint_url.split(|c: char| !c.is_ascii_alphanumeric() && !matches!(c, '-' | '.'))
+    .filter(|part| !part.is_empty())
+    .collect::<Vec<_>>()
+    .join("-")
+}
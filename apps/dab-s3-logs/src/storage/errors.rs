@@ -0,0 +1,67 @@
+use std::{io::Error as IoError, path::PathBuf};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum IndexError {
+  #[error("Failed to read download index: {0}")]
+  ReadError(IoError),
+  #[error("Failed to write download index: {0}")]
+  WriteError(IoError),
+  #[error("Failed to lock download index: {0}")]
+  LockError(IoError),
+  #[error("Failed to serialize download index entry: {0}")]
+  SerializeError(#[from] serde_json::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum EvictionError {
+  #[error("Failed to read download directory: {0}")]
+  ReadError(#[from] walkdir::Error),
+  #[error("Failed to read file metadata: {0}")]
+  MetadataError(IoError),
+  #[error("Failed to delete {0}: {1}")]
+  DeleteError(PathBuf, IoError),
+  #[error(transparent)]
+  WorkspaceError(#[from] WorkspaceError),
+  #[error(transparent)]
+  IndexError(#[from] IndexError),
+}
+
+#[derive(Error, Debug)]
+pub enum WorkspaceError {
+  #[error("Invalid workspace name `{0}`")]
+  InvalidName(String),
+  #[error("Failed to read workspace: {0}")]
+  ReadError(IoError),
+  #[error("Failed to write workspace: {0}")]
+  WriteError(IoError),
+  #[error("Failed to parse workspace manifest: {0}")]
+  ParseError(#[from] serde_json::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum IntegrityError {
+  #[error("Failed to read download directory: {0}")]
+  ReadError(#[from] walkdir::Error),
+  #[error("Failed to check {0}: {1}")]
+  CheckError(PathBuf, IoError),
+  #[error("Failed to delete {0}: {1}")]
+  DeleteError(PathBuf, IoError),
+  #[error(transparent)]
+  IndexError(#[from] IndexError),
+}
+
+#[derive(Error, Debug)]
+pub enum CatalogError {
+  #[error(transparent)]
+  BucketsError(#[from] aws::s3::errors::BucketsError),
+}
+
+#[derive(Error, Debug)]
+pub enum CacheError {
+  #[error("Failed to write cache: {0}")]
+  WriteError(IoError),
+  #[error("Failed to serialize cache entry: {0}")]
+  SerializeError(#[from] serde_json::Error),
+}
@@ -0,0 +1,489 @@
+use std::{collections::{HashMap, HashSet}, fs::{self, File, OpenOptions}, io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}};
+
+use aws::s3::Query;
+use chrono::Utc;
+use aws_sdk_s3::types::Object;
+use log::{debug, info};
+use serde_derive::{Deserialize, Serialize};
+
+use super::errors::IndexError;
+
+const INDEX_FILE_NAME: &str = "download-index.ndjson";
+/// Held while the index file is appended to or rewritten, as fetches into other workspaces share it
+const LOCK_FILE_NAME: &str = "download-index.lock";
+
+/// Record of an S3 object that has been fully downloaded
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct IndexEntry {
+  /// Workspace the object was downloaded into, `None` for the download directory itself
+  pub workspace: Option<String>,
+  pub bucket: String,
+  pub key: String,
+  pub size: u64,
+  pub e_tag: Option<String>,
+  pub last_modified: Option<String>,
+  /// Unix time the object was downloaded
+  pub fetched_at: Option<i64>,
+  /// Unix time the file was last written to stdout
+  pub accessed_at: Option<i64>,
+  /// Hex MD5 of the file as downloaded, missing for files downloaded before it was recorded
+  #[serde(default)]
+  pub md5: Option<String>,
+}
+
+impl IndexEntry {
+  pub fn from_object(workspace: &str, bucket: &str, object: &Object) -> Self {
+    Self {
+      workspace: Some(workspace.to_string()),
+      bucket: bucket.to_string(),
+      key: object.key.clone().unwrap_or_default(),
+      size: object_size(object),
+      e_tag: object.e_tag.clone(),
+      last_modified: object.last_modified.map(|date| date.to_string()),
+      fetched_at: Some(Utc::now().timestamp()),
+      accessed_at: None,
+      md5: None,
+    }
+  }
+
+  /// Where the object is stored below the download directory
+  pub fn local_path(&self, download_dir: &Path) -> PathBuf {
+    match &self.workspace {
+      Some(workspace) => download_dir.join(workspace).join(&self.key),
+      None => download_dir.join(&self.key),
+    }
+  }
+
+  /// Unix time the file was last fetched or read, whichever is later
+  pub fn last_used(&self) -> Option<i64> {
+    self.fetched_at.max(self.accessed_at)
+  }
+
+  /// True when the object in S3 is unchanged since this entry was recorded
+  pub fn matches(&self, object: &Object) -> bool {
+    self.size == object_size(object)
+      && self.e_tag == object.e_tag
+      && self.last_modified == object.last_modified.map(|date| date.to_string())
+  }
+}
+
+/// Append-only index of downloaded objects.
+///
+/// Every completed download is appended as one JSON line, so an interrupted fetch
+/// keeps a record of everything that finished before it stopped. Entries changed or removed
+/// since loading are tracked so `compact` can merge them with what other processes wrote.
+#[derive(Debug)]
+pub struct DownloadIndex {
+  path: PathBuf,
+  entries: HashMap<String, IndexEntry>,
+  changed: HashSet<String>,
+  removed: HashSet<String>,
+}
+
+impl DownloadIndex {
+  /// Load the index from the data directory, starting empty if it doesn't exist yet
+  pub fn load(data_directory: &Path) -> Result<Self, IndexError> {
+    let path = data_directory.join(INDEX_FILE_NAME);
+    let entries = read_entries(&path)?;
+
+    debug!("Loaded {} entries from download index {:?}", entries.len(), path);
+
+    Ok(Self { path, entries, changed: HashSet::new(), removed: HashSet::new() })
+  }
+
+  pub fn get(&self, workspace: Option<&str>, bucket: &str, key: &str) -> Option<&IndexEntry> {
+    self.entries.get(&entry_id(workspace, bucket, key))
+  }
+
+  pub fn entries(&self) -> impl Iterator<Item = &IndexEntry> {
+    self.entries.values()
+  }
+
+  /// Forget an object, e.g. once its file has been evicted
+  pub fn remove(&mut self, workspace: Option<&str>, bucket: &str, key: &str) -> Option<IndexEntry> {
+    let id = entry_id(workspace, bucket, key);
+    self.changed.remove(&id);
+    self.removed.insert(id.clone());
+    self.entries.remove(&id)
+  }
+
+  /// Mark files in the download directory as read now. Call `compact` afterwards to save
+  pub fn record_access(&mut self, download_dir: &Path, paths: &[PathBuf]) {
+    let now = Utc::now().timestamp();
+    let paths: HashSet<&Path> = paths.iter().map(PathBuf::as_path).collect();
+
+    for (id, entry) in self.entries.iter_mut() {
+      if paths.contains(entry.local_path(download_dir).as_path()) {
+        entry.accessed_at = Some(now);
+        self.changed.insert(id.clone());
+      }
+    }
+  }
+
+  /// True when the object has already been downloaded into the workspace, is unchanged in S3 and is intact on disk
+  pub fn is_current(&self, workspace: &str, bucket: &str, object: &Object, download_dir: &Path) -> bool {
+    let key = match &object.key {
+      Some(key) => key,
+      None => return false,
+    };
+
+    match self.get(Some(workspace), bucket, key) {
+      Some(entry) if entry.matches(object) => {
+        match fs::metadata(entry.local_path(download_dir)) {
+          Ok(metadata) => metadata.len() == entry.size,
+          Err(_) => false,
+        }
+      }
+      _ => false,
+    }
+  }
+
+  /// Reduce a query down to the objects that still need downloading into the workspace
+  pub fn pending(&self, query: Query, workspace: &str, download_dir: &Path) -> Query {
+    let total = query.objects.len();
+    let objects: HashMap<String, Object> = query.objects.into_iter()
+      .filter(|(_, object)| !self.is_current(workspace, &query.bucket, object, download_dir))
+      .collect();
+    let size = objects.values().map(object_size).sum();
+
+    info!("Skipping {} objects already downloaded", total - objects.len());
+
+    Query {
+      objects,
+      prefix: query.prefix,
+      bucket: query.bucket,
+      size,
+    }
+  }
+
+  /// Move objects of the query downloaded before workspaces existed, which sit in the download
+  /// directory itself, into the workspace so they aren't fetched again. Returns how many moved
+  pub fn adopt_legacy(&mut self, query: &Query, workspace: &str, download_dir: &Path) -> Result<usize, IndexError> {
+    let mut adopted = 0;
+    for object in query.objects.values() {
+      let key = match &object.key {
+        Some(key) => key,
+        None => continue,
+      };
+      let mut entry = match self.get(None, &query.bucket, key) {
+        Some(entry) if entry.matches(object) => entry.clone(),
+        _ => continue,
+      };
+      let from = entry.local_path(download_dir);
+      if !fs::metadata(&from).is_ok_and(|metadata| metadata.len() == entry.size) {
+        continue;
+      }
+
+      entry.workspace = Some(workspace.to_string());
+      let to = entry.local_path(download_dir);
+      if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(IndexError::WriteError)?;
+      }
+      fs::rename(&from, &to).map_err(IndexError::WriteError)?;
+      remove_empty_parents(&from, download_dir);
+
+      self.remove(None, &query.bucket, key);
+      self.insert(entry);
+      adopted += 1;
+    }
+
+    if adopted > 0 {
+      self.compact()?;
+    }
+
+    Ok(adopted)
+  }
+
+  /// Record a completed download, appending it to the index file straight away. A line torn by
+  /// an interrupted fetch is ended first so it doesn't swallow this one
+  pub fn record(&mut self, entry: IndexEntry) -> Result<(), IndexError> {
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+
+    let _lock = self.lock()?;
+    let mut file = OpenOptions::new().create(true).read(true).append(true).open(&self.path).map_err(IndexError::WriteError)?;
+    if !ends_with_newline(&mut file).map_err(IndexError::WriteError)? {
+      line.insert(0, '\n');
+    }
+    file.write_all(line.as_bytes()).map_err(IndexError::WriteError)?;
+
+    self.insert(entry);
+
+    Ok(())
+  }
+
+  /// Rewrite the index file with one line per entry, dropping superseded records.
+  ///
+  /// The file is read again first, so entries other processes recorded since this index was
+  /// loaded are kept, apart from those this one has since changed or removed.
+  pub fn compact(&mut self) -> Result<(), IndexError> {
+    let _lock = self.lock()?;
+
+    let mut entries = read_entries(&self.path)?;
+    for id in self.removed.drain() {
+      entries.remove(&id);
+    }
+    for id in self.changed.drain() {
+      if let Some(entry) = self.entries.get(&id) {
+        entries.insert(id, entry.clone());
+      }
+    }
+    self.entries = entries;
+
+    let tmp_path = self.path.with_extension("ndjson.tmp");
+    let file = File::create(&tmp_path).map_err(IndexError::WriteError)?;
+    let mut buf_writer = BufWriter::new(file);
+    for entry in self.entries.values() {
+      serde_json::to_writer(&mut buf_writer, entry)?;
+      buf_writer.write_all(b"\n").map_err(IndexError::WriteError)?;
+    }
+    buf_writer.flush().map_err(IndexError::WriteError)?;
+    drop(buf_writer);
+
+    fs::rename(&tmp_path, &self.path).map_err(IndexError::WriteError)?;
+
+    Ok(())
+  }
+
+  fn insert(&mut self, entry: IndexEntry) {
+    let id = entry_id(entry.workspace.as_deref(), &entry.bucket, &entry.key);
+    self.removed.remove(&id);
+    self.changed.insert(id.clone());
+    self.entries.insert(id, entry);
+  }
+
+  /// Wait for other processes to finish with the index file, holding it until the lock is dropped
+  fn lock(&self) -> Result<File, IndexError> {
+    if let Some(parent) = self.path.parent() {
+      fs::create_dir_all(parent).map_err(IndexError::WriteError)?;
+    }
+    let lock_path = self.path.with_file_name(LOCK_FILE_NAME);
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(lock_path).map_err(IndexError::LockError)?;
+    file.lock().map_err(IndexError::LockError)?;
+
+    Ok(file)
+  }
+}
+
+/// Every readable line of the index file, nothing if it doesn't exist yet
+fn read_entries(path: &Path) -> Result<HashMap<String, IndexEntry>, IndexError> {
+  let mut entries = HashMap::new();
+  if !path.exists() {
+    return Ok(entries);
+  }
+
+  let file = File::open(path).map_err(IndexError::ReadError)?;
+  for line in BufReader::new(file).lines() {
+    let line = line.map_err(IndexError::ReadError)?;
+    match serde_json::from_str::<IndexEntry>(&line) {
+      Ok(entry) => {
+        entries.insert(entry_id(entry.workspace.as_deref(), &entry.bucket, &entry.key), entry);
+      }
+      Err(e) => {
+        // a partially written line is expected if the last fetch was interrupted
+        debug!("Skipping unreadable download index line: {}", e);
+      }
+    }
+  }
+
+  Ok(entries)
+}
+
+/// True for an empty file too, as there's no line to end
+fn ends_with_newline(file: &mut File) -> io::Result<bool> {
+  if file.metadata()?.len() == 0 {
+    return Ok(true);
+  }
+  let mut last = [0; 1];
+  file.seek(SeekFrom::End(-1))?;
+  file.read_exact(&mut last)?;
+
+  Ok(last[0] == b'\n')
+}
+
+fn entry_id(workspace: Option<&str>, bucket: &str, key: &str) -> String {
+  format!("{}:{}/{}", workspace.unwrap_or_default(), bucket, key)
+}
+
+/// Remove the folders left empty above a moved file, stopping at the download directory
+fn remove_empty_parents(path: &Path, download_dir: &Path) {
+  for dir in path.ancestors().skip(1).take_while(|dir| *dir != download_dir) {
+    if fs::remove_dir(dir).is_err() {
+      break;
+    }
+  }
+}
+
+fn object_size(object: &Object) -> u64 {
+  object.size.and_then(|size| u64::try_from(size).ok()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use aws_sdk_s3::primitives::DateTime;
+  use tempfile::TempDir;
+
+  use super::*;
+
+  fn object(key: &str, size: i64, e_tag: &str) -> Object {
+    Object::builder().key(key).size(size).e_tag(e_tag).last_modified(DateTime::from_secs(1_714_564_800)).build()
+  }
+
+  fn write_file(download_dir: &Path, workspace: &str, key: &str, size: usize) {
+    let path = download_dir.join(workspace).join(key);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, vec![b'x'; size]).unwrap();
+  }
+
+  #[test]
+  fn reloads_recorded_entries_and_skips_a_torn_line() {
+    let dir = TempDir::new().unwrap();
+    let mut index = DownloadIndex::load(dir.path()).unwrap();
+    index.record(IndexEntry::from_object("ws", "logs", &object("a.log", 3, "\"a\""))).unwrap();
+    drop(index);
+    let mut file = OpenOptions::new().append(true).open(dir.path().join(INDEX_FILE_NAME)).unwrap();
+    file.write_all(b"{\"workspace\":\"ws\",\"buck").unwrap();
+
+    let index = DownloadIndex::load(dir.path()).unwrap();
+
+    assert_eq!(index.entries().count(), 1);
+    assert_eq!(index.get(Some("ws"), "logs", "a.log").map(|entry| entry.size), Some(3));
+    assert!(index.get(Some("other"), "logs", "a.log").is_none());
+  }
+
+  #[test]
+  fn keeps_entries_recorded_after_a_torn_line() {
+    let dir = TempDir::new().unwrap();
+    let mut index = DownloadIndex::load(dir.path()).unwrap();
+    index.record(IndexEntry::from_object("ws", "logs", &object("a.log", 3, "\"a\""))).unwrap();
+    let mut file = OpenOptions::new().append(true).open(dir.path().join(INDEX_FILE_NAME)).unwrap();
+    file.write_all(b"{\"workspace\":\"ws\",\"buck").unwrap();
+
+    let mut index = DownloadIndex::load(dir.path()).unwrap();
+    index.record(IndexEntry::from_object("ws", "logs", &object("b.log", 4, "\"b\""))).unwrap();
+
+    let index = DownloadIndex::load(dir.path()).unwrap();
+    assert_eq!(index.get(Some("ws"), "logs", "b.log").map(|entry| entry.size), Some(4));
+    assert_eq!(index.entries().count(), 2);
+  }
+
+  #[test]
+  fn compact_keeps_what_other_processes_recorded() {
+    let dir = TempDir::new().unwrap();
+    let mut first = DownloadIndex::load(dir.path()).unwrap();
+    first.record(IndexEntry::from_object("one", "logs", &object("a.log", 3, "\"a\""))).unwrap();
+    first.record(IndexEntry::from_object("one", "logs", &object("b.log", 3, "\"b\""))).unwrap();
+    let mut second = DownloadIndex::load(dir.path()).unwrap();
+    second.record(IndexEntry::from_object("two", "logs", &object("a.log", 3, "\"a\""))).unwrap();
+
+    first.remove(Some("one"), "logs", "b.log");
+    first.compact().unwrap();
+    second.compact().unwrap();
+
+    let index = DownloadIndex::load(dir.path()).unwrap();
+    assert!(index.get(Some("one"), "logs", "a.log").is_some());
+    assert!(index.get(Some("two"), "logs", "a.log").is_some());
+    assert!(index.get(Some("one"), "logs", "b.log").is_none(), "removed entries aren't brought back");
+    assert_eq!(index.entries().count(), 2);
+  }
+
+  #[test]
+  fn is_current_only_when_unchanged_and_intact() {
+    let dir = TempDir::new().unwrap();
+    let download_dir = dir.path().join("downloads");
+    let mut index = DownloadIndex::load(dir.path()).unwrap();
+    let downloaded = object("a.log", 3, "\"a\"");
+    index.record(IndexEntry::from_object("ws", "logs", &downloaded)).unwrap();
+
+    assert!(!index.is_current("ws", "logs", &downloaded, &download_dir), "file is missing");
+    write_file(&download_dir, "ws", "a.log", 3);
+    assert!(index.is_current("ws", "logs", &downloaded, &download_dir));
+    assert!(!index.is_current("ws", "logs", &object("a.log", 3, "\"changed\""), &download_dir));
+    assert!(!index.is_current("other", "logs", &downloaded, &download_dir));
+
+    write_file(&download_dir, "ws", "a.log", 2);
+    assert!(!index.is_current("ws", "logs", &downloaded, &download_dir), "file is truncated");
+  }
+
+  #[test]
+  fn pending_leaves_only_objects_to_download() {
+    let dir = TempDir::new().unwrap();
+    let download_dir = dir.path().join("downloads");
+    let mut index = DownloadIndex::load(dir.path()).unwrap();
+    let done = object("a.log", 3, "\"a\"");
+    index.record(IndexEntry::from_object("ws", "logs", &done)).unwrap();
+    write_file(&download_dir, "ws", "a.log", 3);
+
+    let objects = HashMap::from([
+      ("a.log".to_string(), done),
+      ("b.log".to_string(), object("b.log", 5, "\"b\"")),
+    ]);
+    let query = Query { objects, prefix: String::new(), bucket: "logs".to_string(), size: 8 };
+    let pending = index.pending(query, "ws", &download_dir);
+
+    assert_eq!(pending.objects.keys().collect::<Vec<_>>(), ["b.log"]);
+    assert_eq!(pending.size, 5);
+  }
+
+  #[test]
+  fn compact_keeps_only_the_latest_entry_per_object() {
+    let dir = TempDir::new().unwrap();
+    let mut index = DownloadIndex::load(dir.path()).unwrap();
+    index.record(IndexEntry::from_object("ws", "logs", &object("a.log", 3, "\"a\""))).unwrap();
+    index.record(IndexEntry::from_object("ws", "logs", &object("a.log", 4, "\"a2\""))).unwrap();
+    index.record(IndexEntry::from_object("ws", "logs", &object("b.log", 5, "\"b\""))).unwrap();
+    index.remove(Some("ws"), "logs", "b.log");
+
+    index.compact().unwrap();
+
+    let contents = fs::read_to_string(dir.path().join(INDEX_FILE_NAME)).unwrap();
+    assert_eq!(contents.lines().count(), 1);
+    let index = DownloadIndex::load(dir.path()).unwrap();
+    assert_eq!(index.get(Some("ws"), "logs", "a.log").map(|entry| entry.size), Some(4));
+  }
+
+  #[test]
+  fn records_when_files_are_read() {
+    let dir = TempDir::new().unwrap();
+    let download_dir = dir.path().join("downloads");
+    let mut index = DownloadIndex::load(dir.path()).unwrap();
+    index.record(IndexEntry::from_object("ws", "logs", &object("a.log", 3, "\"a\""))).unwrap();
+    index.record(IndexEntry::from_object("ws", "logs", &object("b.log", 3, "\"b\""))).unwrap();
+
+    index.record_access(&download_dir, &[download_dir.join("ws/a.log")]);
+
+    assert!(index.get(Some("ws"), "logs", "a.log").unwrap().accessed_at.is_some());
+    assert!(index.get(Some("ws"), "logs", "b.log").unwrap().accessed_at.is_none());
+  }
+
+  #[test]
+  fn adopts_unchanged_legacy_downloads_into_the_workspace() {
+    let dir = TempDir::new().unwrap();
+    let download_dir = dir.path().join("downloads");
+    let mut index = DownloadIndex::load(dir.path()).unwrap();
+    let unchanged = object("api/a.log", 3, "\"a\"");
+    let changed = object("api/b.log", 3, "\"b\"");
+    for object in [&unchanged, &changed] {
+      index.record(IndexEntry { workspace: None, ..IndexEntry::from_object("", "logs", object) }).unwrap();
+      write_file(&download_dir, "", object.key().unwrap(), 3);
+    }
+
+    let objects = HashMap::from([
+      ("api/a.log".to_string(), unchanged.clone()),
+      ("api/b.log".to_string(), object("api/b.log", 3, "\"b2\"")),
+    ]);
+    let query = Query { objects, prefix: String::new(), bucket: "logs".to_string(), size: 6 };
+
+    assert_eq!(index.adopt_legacy(&query, "ws", &download_dir).unwrap(), 1);
+    assert!(index.is_current("ws", "logs", &unchanged, &download_dir));
+    assert!(!download_dir.join("api/a.log").exists());
+    assert!(download_dir.join("api/b.log").exists(), "a changed object is left to be fetched again");
+
+    let index = DownloadIndex::load(dir.path()).unwrap();
+    assert!(index.get(None, "logs", "api/a.log").is_none());
+    assert!(index.get(Some("ws"), "logs", "api/a.log").is_some());
+    assert!(index.get(None, "logs", "api/b.log").is_some());
+  }
+}
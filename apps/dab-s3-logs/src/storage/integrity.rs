@@ -0,0 +1,165 @@
+use std::{fmt, fs, io::ErrorKind, path::{Path, PathBuf}};
+
+use aws::s3::{checksum::file_md5, download::is_temp_file};
+use glob::Pattern;
+use log::info;
+use walkdir::WalkDir;
+
+use super::{errors::IntegrityError, index::{DownloadIndex, IndexEntry}};
+
+/// Why a downloaded file failed verification
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+  /// In the download index but gone from disk
+  Missing,
+  /// Left behind by an interrupted download
+  Incomplete,
+  SizeMismatch { expected: u64, actual: u64 },
+  ChecksumMismatch { expected: String, actual: String },
+}
+
+impl fmt::Display for Problem {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Problem::Missing => write!(f, "missing"),
+      Problem::Incomplete => write!(f, "incomplete download"),
+      Problem::SizeMismatch { expected, actual } => write!(f, "{} bytes, expected {}", actual, expected),
+      Problem::ChecksumMismatch { expected, actual } => write!(f, "MD5 {}, expected {}", actual, expected),
+    }
+  }
+}
+
+/// A file that failed verification
+#[derive(Debug, Clone)]
+pub struct Finding {
+  pub path: PathBuf,
+  pub problem: Problem,
+  /// Index entry of the file, `None` for incomplete downloads which were never recorded
+  pub entry: Option<IndexEntry>,
+}
+
+/// Outcome of verifying the download directory
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+  /// Files whose size and MD5 match the index
+  pub verified: usize,
+  /// Files whose size matches, downloaded before their MD5 was recorded
+  pub size_only: usize,
+  pub findings: Vec<Finding>,
+}
+
+/// Checks downloaded files against what the download index recorded for them
+pub struct IntegrityChecker {
+  download_dir: PathBuf,
+}
+
+impl IntegrityChecker {
+  pub fn new(download_dir: &Path) -> Self {
+    Self { download_dir: download_dir.to_path_buf() }
+  }
+
+  /// Check every downloaded file in workspaces matching the selector, or in the whole download
+  /// directory without one, calling `on_checked` after each
+  pub fn verify<F: FnMut(&IndexEntry)>(&self, index: &DownloadIndex, selector: Option<&Pattern>, mut on_checked: F) -> Result<IntegrityReport, IntegrityError> {
+    let mut report = IntegrityReport::default();
+    for entry in self.entries(index, selector) {
+      match self.check(entry)? {
+        Some(problem) => report.findings.push(Finding { path: entry.local_path(&self.download_dir), problem, entry: Some(entry.clone()) }),
+        None if entry.md5.is_some() => report.verified += 1,
+        None => report.size_only += 1,
+      }
+      on_checked(entry);
+    }
+
+    for path in self.incomplete(selector)? {
+      report.findings.push(Finding { path, problem: Problem::Incomplete, entry: None });
+    }
+
+    Ok(report)
+  }
+
+  /// Index entries in workspaces matching the selector, or every entry without one
+  pub fn entries<'a>(&self, index: &'a DownloadIndex, selector: Option<&Pattern>) -> Vec<&'a IndexEntry> {
+    let mut entries: Vec<&IndexEntry> = index.entries()
+      .filter(|entry| match selector {
+        Some(selector) => entry.workspace.as_ref().is_some_and(|workspace| selector.matches(workspace)),
+        None => true,
+      })
+      .collect();
+    entries.sort_by_key(|entry| entry.local_path(&self.download_dir));
+
+    entries
+  }
+
+  /// Check a file's size, then its MD5 when one was recorded
+  fn check(&self, entry: &IndexEntry) -> Result<Option<Problem>, IntegrityError> {
+    let path = entry.local_path(&self.download_dir);
+    let metadata = match fs::metadata(&path) {
+      Ok(metadata) => metadata,
+      Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Some(Problem::Missing)),
+      Err(e) => return Err(IntegrityError::CheckError(path, e)),
+    };
+    if metadata.len() != entry.size {
+      return Ok(Some(Problem::SizeMismatch { expected: entry.size, actual: metadata.len() }));
+    }
+
+    let expected = match &entry.md5 {
+      Some(expected) => expected,
+      None => return Ok(None),
+    };
+    let actual = file_md5(&path).map_err(|e| IntegrityError::CheckError(path.clone(), e))?;
+    if &actual != expected {
+      return Ok(Some(Problem::ChecksumMismatch { expected: expected.clone(), actual }));
+    }
+
+    Ok(None)
+  }
+
+  /// Temporary files of interrupted downloads in workspaces matching the selector
+  pub fn incomplete(&self, selector: Option<&Pattern>) -> Result<Vec<PathBuf>, IntegrityError> {
+    if !self.download_dir.exists() {
+      return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&self.download_dir) {
+      let entry = entry?;
+      if !entry.file_type().is_file() || !is_temp_file(entry.path()) {
+        continue;
+      }
+      if let Some(selector) = selector {
+        if !self.workspace_of(entry.path()).is_some_and(|workspace| selector.matches(&workspace)) {
+          continue;
+        }
+      }
+      files.push(entry.into_path());
+    }
+    files.sort();
+
+    Ok(files)
+  }
+
+  /// Name of the workspace folder a downloaded file is in
+  pub fn workspace_of(&self, path: &Path) -> Option<String> {
+    path.strip_prefix(&self.download_dir).ok()
+      .and_then(|relative| relative.components().next())
+      .map(|workspace| workspace.as_os_str().to_string_lossy().to_string())
+  }
+
+  /// Delete every file that failed verification and drop it from the index, so the next fetch downloads it again
+  pub fn remove(&self, findings: &[Finding], index: &mut DownloadIndex) -> Result<(), IntegrityError> {
+    for finding in findings {
+      if finding.problem != Problem::Missing {
+        fs::remove_file(&finding.path).map_err(|e| IntegrityError::DeleteError(finding.path.clone(), e))?;
+      }
+      if let Some(entry) = &finding.entry {
+        index.remove(entry.workspace.as_deref(), &entry.bucket, &entry.key);
+      }
+    }
+    index.compact()?;
+
+    info!("Removed {} files that failed verification", findings.len());
+
+    Ok(())
+  }
+}
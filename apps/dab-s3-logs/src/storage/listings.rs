@@ -0,0 +1,95 @@
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use aws::{client::ClientOptions, s3::{list_keys, store::ObjectStore, Query}};
+use aws_sdk_s3::{primitives::DateTime, types::Object};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use super::{cache::FileCache, catalog::DEFAULT_PROFILE};
+
+/// Directory beneath the cache directory holding the object listings of preview, fetch and stream
+pub const LISTING_CACHE_DIRECTORY: &str = "listings";
+
+/// Object listings of prefixes, saved in the cache directory so a preview followed by a fetch, or
+/// the same fetch run again, only lists S3 once
+#[derive(Debug, Clone)]
+pub struct ListingCache {
+  cache: FileCache,
+  /// Profile and endpoint the listings were made with, as the same bucket name can mean another bucket
+  scope: String,
+  ttl: Duration,
+  /// List from S3 even when there's a fresh listing, saving the new one
+  refresh: bool,
+}
+
+/// The parts of a listed object that fetching and previewing use
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedObject {
+  key: String,
+  size: i64,
+  e_tag: Option<String>,
+  last_modified: Option<i64>,
+}
+
+impl ListingCache {
+  pub fn new(dir: &Path, options: &ClientOptions, ttl: Duration) -> Self {
+    let profile = options.profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+    let scope = format!("{}\n{}", profile, options.endpoint_url.as_deref().unwrap_or_default());
+
+    Self { cache: FileCache::new(dir), scope, ttl, refresh: false }
+  }
+
+  pub fn refresh(mut self, refresh: bool) -> Self {
+    self.refresh = refresh;
+    self
+  }
+
+  /// Every object under a prefix, from the cache while the listing is fresh. Listings of
+  /// immutable prefixes, such as the date folder of a day that's over, never go stale
+  pub async fn list<C: ObjectStore>(&self, client: &C, bucket: &str, prefix: &str, immutable: bool) -> anyhow::Result<Query> {
+    let key = format!("listing\n{}\n{}\n{}", self.scope, bucket, prefix);
+
+    if !self.refresh {
+      if let Some(objects) = self.cache.get::<Vec<CachedObject>>(&key) {
+        debug!("Using cached listing of {} objects under {}", objects.len(), prefix);
+        return Ok(to_query(bucket, prefix, objects));
+      }
+    }
+
+    let query = list_keys(client, bucket, prefix).await?;
+    let objects: Vec<CachedObject> = query.objects.values().map(from_object).collect();
+    let ttl = if immutable { None } else { Some(self.ttl) };
+    // a listing that can't be cached is still a listing
+    if let Err(e) = self.cache.put(&key, &objects, ttl) {
+      warn!("Failed to cache listing of {}: {}", prefix, e);
+    }
+
+    Ok(query)
+  }
+}
+
+fn from_object(object: &Object) -> CachedObject {
+  CachedObject {
+    key: object.key.clone().unwrap_or_default(),
+    size: object.size.unwrap_or(0),
+    e_tag: object.e_tag.clone(),
+    last_modified: object.last_modified.and_then(|time| time.to_millis().ok()),
+  }
+}
+
+fn to_query(bucket: &str, prefix: &str, objects: Vec<CachedObject>) -> Query {
+  let objects: HashMap<String, Object> = objects.into_iter()
+    .map(|object| {
+      let listed = Object::builder()
+        .key(&object.key)
+        .size(object.size)
+        .set_e_tag(object.e_tag)
+        .set_last_modified(object.last_modified.map(DateTime::from_millis))
+        .build();
+      (object.key, listed)
+    })
+    .collect();
+  let size = objects.values().filter_map(|object| object.size).filter_map(|size| u64::try_from(size).ok()).sum();
+
+  Query { objects, prefix: prefix.to_string(), bucket: bucket.to_string(), size }
+}
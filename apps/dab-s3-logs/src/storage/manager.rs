@@ -0,0 +1,324 @@
+use std::{collections::{HashMap, HashSet}, fs, path::{Path, PathBuf}, time::UNIX_EPOCH};
+
+use glob::{Pattern, PatternError};
+use log::{debug, info};
+
+use super::{errors::EvictionError, index::{DownloadIndex, IndexEntry}, list_files, workspace::{Workspace, MANIFEST_FILE_NAME}};
+
+/// A downloaded file that can be evicted to make room
+#[derive(Debug, Clone)]
+pub struct EvictionCandidate {
+  pub path: PathBuf,
+  /// Workspace holding the file, if any
+  pub workspace: Option<String>,
+  /// Path relative to the workspace, the same as the object key
+  pub key: String,
+  /// Bucket the file was downloaded from, if it's in the download index
+  pub bucket: Option<String>,
+  pub size: u64,
+  /// Unix time the file was last fetched or read, if known
+  pub last_used: Option<i64>,
+}
+
+/// Files to evict, least recently used first
+#[derive(Debug, Clone, Default)]
+pub struct EvictionPlan {
+  pub files: Vec<EvictionCandidate>,
+  /// Bytes freed by evicting every file in the plan
+  pub size: u64,
+}
+
+/// Which downloaded files a reset removes. Everything when nothing is set
+#[derive(Debug, Clone, Default)]
+pub struct StorageScope {
+  /// Glob matched against workspace names, e.g. `prod-*`
+  pub workspace: Option<Pattern>,
+  pub bucket: Option<String>,
+  /// Glob matched against the start of each key, e.g. `logs/*/2024-05-`
+  pub prefix: Option<Pattern>,
+  /// Only files last fetched or read before this unix time
+  pub older_than: Option<i64>,
+}
+
+impl StorageScope {
+  /// A trailing `*` is added to the prefix so it also matches everything below it
+  pub fn prefix_pattern(prefix: &str) -> Result<Pattern, PatternError> {
+    let prefix = if prefix.ends_with('*') { prefix.to_string() } else { format!("{}*", prefix) };
+    Pattern::new(&prefix)
+  }
+
+  fn matches(&self, candidate: &EvictionCandidate) -> bool {
+    let workspace_matches = match &self.workspace {
+      Some(workspace) => candidate.workspace.as_ref().is_some_and(|name| workspace.matches(name)),
+      None => true,
+    };
+    let bucket_matches = match &self.bucket {
+      Some(bucket) => candidate.bucket.as_ref() == Some(bucket),
+      None => true,
+    };
+    let prefix_matches = match &self.prefix {
+      Some(prefix) => prefix.matches(&candidate.key),
+      None => true,
+    };
+    let age_matches = match self.older_than {
+      Some(cutoff) => candidate.last_used.is_some_and(|last_used| last_used < cutoff),
+      None => true,
+    };
+
+    workspace_matches && bucket_matches && prefix_matches && age_matches
+  }
+}
+
+/// Keeps the download directory under `max_storage` by evicting the least recently used files
+pub struct StorageManager {
+  download_dir: PathBuf,
+  max_storage: u64,
+}
+
+impl StorageManager {
+  pub fn new(download_dir: &Path, max_storage: u64) -> Self {
+    Self { download_dir: download_dir.to_path_buf(), max_storage }
+  }
+
+  /// Bytes still free under `max_storage`
+  pub fn available(&self, used: u64) -> u64 {
+    self.max_storage.saturating_sub(used)
+  }
+
+  /// Pick the least recently used files to evict so `required` more bytes fit under `max_storage`.
+  ///
+  /// Files in `keep` are never picked. Files the index doesn't know about are aged by their
+  /// modified time. The plan may free less than needed if there isn't enough to evict.
+  pub fn plan_eviction(&self, index: &DownloadIndex, used: u64, required: u64, keep: &HashSet<PathBuf>) -> Result<EvictionPlan, EvictionError> {
+    let to_free = required.saturating_sub(self.available(used));
+    if to_free == 0 {
+      return Ok(EvictionPlan::default());
+    }
+
+    let mut candidates = self.candidates(index, keep)?;
+    candidates.sort_by_key(|candidate| candidate.last_used.unwrap_or(i64::MIN));
+
+    let mut plan = EvictionPlan::default();
+    for candidate in candidates {
+      if plan.size >= to_free {
+        break;
+      }
+      plan.size += candidate.size;
+      plan.files.push(candidate);
+    }
+
+    debug!("Eviction plan frees {} of {} bytes needed over {} files", plan.size, to_free, plan.files.len());
+
+    Ok(plan)
+  }
+
+  /// Every downloaded file in the scope, least recently used first
+  pub fn plan_scoped(&self, index: &DownloadIndex, scope: &StorageScope) -> Result<EvictionPlan, EvictionError> {
+    let mut candidates = self.candidates(index, &HashSet::new())?;
+    candidates.retain(|candidate| scope.matches(candidate));
+    candidates.sort_by_key(|candidate| candidate.last_used.unwrap_or(i64::MIN));
+
+    let size = candidates.iter().map(|candidate| candidate.size).sum();
+
+    Ok(EvictionPlan { files: candidates, size })
+  }
+
+  /// Every one of the given files, e.g. temporary files left by interrupted downloads, which
+  /// `plan_scoped` never picks since they're hidden
+  pub fn plan_files(&self, paths: Vec<PathBuf>) -> Result<EvictionPlan, EvictionError> {
+    let workspaces: HashSet<String> = Workspace::list(&self.download_dir)?.into_iter()
+      .map(|workspace| workspace.name)
+      .collect();
+
+    let mut plan = EvictionPlan::default();
+    for path in paths {
+      let metadata = fs::metadata(&path).map_err(EvictionError::MetadataError)?;
+      let modified = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_secs() as i64);
+
+      plan.size += metadata.len();
+      plan.files.push(self.unindexed(path, metadata.len(), modified, &workspaces));
+    }
+
+    Ok(plan)
+  }
+
+  /// Delete every file in the plan and drop it from the index, returning the bytes freed
+  pub fn evict(&self, plan: &EvictionPlan, index: &mut DownloadIndex) -> Result<u64, EvictionError> {
+    let mut freed = 0;
+    for candidate in &plan.files {
+      fs::remove_file(&candidate.path).map_err(|e| EvictionError::DeleteError(candidate.path.clone(), e))?;
+      if let Some(bucket) = &candidate.bucket {
+        index.remove(candidate.workspace.as_deref(), bucket, &candidate.key);
+      }
+      self.remove_empty_parents(&candidate.path);
+
+      freed += candidate.size;
+    }
+    index.compact()?;
+
+    info!("Evicted {} files", plan.files.len());
+
+    Ok(freed)
+  }
+
+  fn candidates(&self, index: &DownloadIndex, keep: &HashSet<PathBuf>) -> Result<Vec<EvictionCandidate>, EvictionError> {
+    let entries_by_path: HashMap<PathBuf, &IndexEntry> = index.entries()
+      .map(|entry| (entry.local_path(&self.download_dir), entry))
+      .collect();
+    let workspaces: HashSet<String> = Workspace::list(&self.download_dir)?.into_iter()
+      .map(|workspace| workspace.name)
+      .collect();
+
+    let mut candidates = Vec::new();
+    for file in list_files(&self.download_dir)? {
+      let path = PathBuf::from(file);
+      if keep.contains(&path) {
+        continue;
+      }
+
+      let metadata = fs::metadata(&path).map_err(EvictionError::MetadataError)?;
+      let modified = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_secs() as i64);
+
+      let candidate = match entries_by_path.get(&path) {
+        Some(entry) => EvictionCandidate {
+          workspace: entry.workspace.clone(),
+          key: entry.key.clone(),
+          bucket: Some(entry.bucket.clone()),
+          size: metadata.len(),
+          last_used: entry.last_used().or(modified),
+          path,
+        },
+        None => self.unindexed(path, metadata.len(), modified, &workspaces),
+      };
+      candidates.push(candidate);
+    }
+
+    Ok(candidates)
+  }
+
+  /// A file the index doesn't know about, keyed by its path below its workspace if it's in one
+  fn unindexed(&self, path: PathBuf, size: u64, modified: Option<i64>, workspaces: &HashSet<String>) -> EvictionCandidate {
+    let relative = path.strip_prefix(&self.download_dir).unwrap_or(&path).to_string_lossy().to_string();
+    let (workspace, key) = match relative.split_once('/') {
+      Some((workspace, key)) if workspaces.contains(workspace) => (Some(workspace.to_string()), key.to_string()),
+      _ => (None, relative),
+    };
+
+    EvictionCandidate { workspace, key, bucket: None, size, last_used: modified, path }
+  }
+
+  /// Tidy up folders left empty by an eviction, stopping at the download directory. A workspace
+  /// left with nothing but its manifest is removed too
+  fn remove_empty_parents(&self, path: &Path) {
+    let mut current = path.parent();
+    while let Some(dir) = current {
+      if dir == self.download_dir || !dir.starts_with(&self.download_dir) {
+        break;
+      }
+      if dir.parent() == Some(self.download_dir.as_path()) && only_contains_manifest(dir) {
+        let _ = fs::remove_file(dir.join(MANIFEST_FILE_NAME));
+      }
+      if fs::remove_dir(dir).is_err() {
+        break;
+      }
+      current = dir.parent();
+    }
+  }
+}
+
+fn only_contains_manifest(dir: &Path) -> bool {
+  match fs::read_dir(dir) {
+    Ok(entries) => entries
+      .filter_map(|entry| entry.ok())
+      .all(|entry| entry.file_name() == MANIFEST_FILE_NAME),
+    Err(_) => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use tempfile::TempDir;
+
+  use super::*;
+
+  /// A download directory holding `ws/<key>` files of the given size and last use, all indexed
+  fn downloads(files: &[(&str, usize, i64)]) -> (TempDir, DownloadIndex) {
+    let dir = TempDir::new().unwrap();
+    let mut index = DownloadIndex::load(dir.path()).unwrap();
+    for (key, size, last_used) in files {
+      let path = dir.path().join("downloads/ws").join(key);
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(&path, vec![b'x'; *size]).unwrap();
+      index.record(IndexEntry {
+        workspace: Some("ws".to_string()),
+        bucket: "logs".to_string(),
+        key: key.to_string(),
+        size: *size as u64,
+        e_tag: None,
+        last_modified: None,
+        fetched_at: Some(*last_used),
+        accessed_at: None,
+        md5: None,
+      }).unwrap();
+    }
+
+    (dir, index)
+  }
+
+  fn keys(plan: &EvictionPlan) -> Vec<&str> {
+    plan.files.iter().map(|file| file.key.as_str()).collect()
+  }
+
+  #[test]
+  fn plans_nothing_when_there_is_room() {
+    let (dir, index) = downloads(&[("a.log", 10, 1)]);
+    let manager = StorageManager::new(&dir.path().join("downloads"), 100);
+
+    let plan = manager.plan_eviction(&index, 10, 90, &HashSet::new()).unwrap();
+
+    assert!(plan.files.is_empty());
+  }
+
+  #[test]
+  fn evicts_least_recently_used_first_until_enough_is_free() {
+    let (dir, index) = downloads(&[("new.log", 10, 300), ("old.log", 10, 100), ("mid.log", 10, 200)]);
+    let manager = StorageManager::new(&dir.path().join("downloads"), 30);
+
+    let plan = manager.plan_eviction(&index, 30, 15, &HashSet::new()).unwrap();
+
+    assert_eq!(keys(&plan), ["old.log", "mid.log"]);
+    assert_eq!(plan.size, 20);
+  }
+
+  #[test]
+  fn never_evicts_files_to_keep() {
+    let (dir, index) = downloads(&[("old.log", 10, 100), ("new.log", 10, 200)]);
+    let download_dir = dir.path().join("downloads");
+    let manager = StorageManager::new(&download_dir, 20);
+    let keep = HashSet::from([download_dir.join("ws/old.log")]);
+
+    let plan = manager.plan_eviction(&index, 20, 30, &keep).unwrap();
+
+    assert_eq!(keys(&plan), ["new.log"]);
+    assert!(plan.size < 30, "the plan can fall short of what's needed");
+  }
+
+  #[test]
+  fn plans_every_file_in_scope() {
+    let (dir, index) = downloads(&[("api/2024-05-01/a.log", 10, 100), ("web/2024-05-01/b.log", 10, 200), ("api/2024-05-02/c.log", 10, 300)]);
+    let manager = StorageManager::new(&dir.path().join("downloads"), 100);
+
+    let by_prefix = StorageScope { prefix: Some(StorageScope::prefix_pattern("api/").unwrap()), ..Default::default() };
+    assert_eq!(keys(&manager.plan_scoped(&index, &by_prefix).unwrap()), ["api/2024-05-01/a.log", "api/2024-05-02/c.log"]);
+
+    let by_age = StorageScope { older_than: Some(250), ..Default::default() };
+    assert_eq!(keys(&manager.plan_scoped(&index, &by_age).unwrap()), ["api/2024-05-01/a.log", "web/2024-05-01/b.log"]);
+
+    let other_bucket = StorageScope { bucket: Some("other".to_string()), ..Default::default() };
+    assert!(manager.plan_scoped(&index, &other_bucket).unwrap().files.is_empty());
+  }
+}
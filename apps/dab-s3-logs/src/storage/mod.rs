@@ -1,9 +1,20 @@
+use std::path::Path;
+
 use anyhow::Result;
 use fs_extra::dir::get_size;
 use walkdir::{DirEntry, WalkDir};
 
 use crate::app::App;
 
+pub mod cache;
+pub mod catalog;
+pub mod errors;
+pub mod index;
+pub mod integrity;
+pub mod listings;
+pub mod manager;
+pub mod workspace;
+
 pub fn get_used_storage (app: &App) -> Result<u64> {
   let download_dir = {
     let cfg = app.config.lock().unwrap().clone().unwrap();
@@ -19,11 +30,19 @@ pub fn get_all_files (app: &App) -> Result<Vec<String>> {
     let cfg = app.config.lock().unwrap().clone().unwrap();
     cfg.download_directory
   };
-  
+
+  Ok(list_files(&download_dir)?)
+}
+
+/// Every log file below a directory, skipping hidden files and folders such as workspace manifests
+pub fn list_files (dir: &Path) -> Result<Vec<String>, walkdir::Error> {
   let mut files = Vec::new();
-  let walker = WalkDir::new(download_dir).into_iter();
-  for entry in walker.filter_entry(|e| !is_hidden(e) || !e.file_type().is_dir()) {
-      files.push(entry?.path().to_str().unwrap().to_string());
+  let walker = WalkDir::new(dir).into_iter();
+  for entry in walker.filter_entry(|e| e.depth() == 0 || !is_hidden(e)) {
+      let entry = entry?;
+      if entry.file_type().is_file() {
+          files.push(entry.path().to_str().unwrap().to_string());
+      }
   }
 
   Ok(files)
@@ -0,0 +1,270 @@
+use std::{collections::BTreeMap, fs::{self, File}, io::{BufReader, BufWriter, Write}, path::{Path, PathBuf}};
+
+use aws::s3::Query;
+use chrono::{DateTime, Utc};
+use glob::Pattern;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::app::time_range::TimeRange;
+
+use super::{errors::WorkspaceError, list_files};
+
+/// Hidden so it's never mistaken for a log file
+pub const MANIFEST_FILE_NAME: &str = ".manifest.json";
+
+/// An object downloaded into a workspace
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ManifestObject {
+  pub key: String,
+  pub e_tag: Option<String>,
+  pub size: u64,
+}
+
+/// What was fetched into a workspace
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Manifest {
+  pub name: String,
+  pub bucket: String,
+  pub prefix: String,
+  pub profile: Option<String>,
+  /// RFC 3339 bounds of a time range covering every fetch, if they all had one
+  pub since: Option<String>,
+  pub until: Option<String>,
+  /// Unix times the workspace was created and last fetched into
+  pub created_at: i64,
+  pub updated_at: i64,
+  pub objects: Vec<ManifestObject>,
+}
+
+impl Manifest {
+  pub fn new(name: &str, query: &Query, profile: Option<String>, time_range: Option<&TimeRange>) -> Self {
+    let now = Utc::now().timestamp();
+    let mut manifest = Self {
+      name: name.to_string(),
+      bucket: query.bucket.clone(),
+      prefix: query.prefix.clone(),
+      profile,
+      since: time_range.map(|time_range| time_range.start.to_rfc3339()),
+      until: time_range.map(|time_range| time_range.end.to_rfc3339()),
+      created_at: now,
+      updated_at: now,
+      objects: Vec::new(),
+    };
+    manifest.add_objects(query);
+
+    manifest
+  }
+
+  /// Fold a later fetch into the manifest, keeping objects from earlier fetches and widening the
+  /// time range to cover both
+  pub fn update(&mut self, later: Manifest) {
+    self.bucket = later.bucket;
+    self.prefix = later.prefix;
+    self.profile = later.profile;
+    self.since = wider_bound(self.since.take(), later.since, |current, later| current < later);
+    self.until = wider_bound(self.until.take(), later.until, |current, later| current > later);
+    self.updated_at = later.updated_at;
+
+    let mut objects: BTreeMap<String, ManifestObject> = self.objects.drain(..)
+      .map(|object| (object.key.clone(), object))
+      .collect();
+    for object in later.objects {
+      objects.insert(object.key.clone(), object);
+    }
+    self.objects = objects.into_values().collect();
+  }
+
+  pub fn size(&self) -> u64 {
+    self.objects.iter().map(|object| object.size).sum()
+  }
+
+  fn add_objects(&mut self, query: &Query) {
+    let mut objects: Vec<ManifestObject> = query.objects.values()
+      .map(|object| ManifestObject {
+        key: object.key.clone().unwrap_or_default(),
+        e_tag: object.e_tag.clone(),
+        size: object.size.and_then(|size| u64::try_from(size).ok()).unwrap_or(0),
+      })
+      .collect();
+    objects.sort_by(|a, b| a.key.cmp(&b.key));
+    self.objects = objects;
+  }
+}
+
+/// A named folder in the download directory holding the objects of one fetch and its manifest
+#[derive(Debug, Clone)]
+pub struct Workspace {
+  pub name: String,
+  pub path: PathBuf,
+}
+
+impl Workspace {
+  pub fn new(download_dir: &Path, name: &str) -> Result<Self, WorkspaceError> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+      return Err(WorkspaceError::InvalidName(name.to_string()));
+    }
+
+    Ok(Self { name: name.to_string(), path: download_dir.join(name) })
+  }
+
+  /// Name used when none is given, e.g. `my-bucket_production_api`, so the same query reuses its workspace
+  pub fn default_name(bucket: &str, prefix: &str) -> String {
+    let mut name = bucket.to_string();
+    for segment in prefix.split('/').filter(|segment| !segment.is_empty()) {
+      name.push('_');
+      name.push_str(segment);
+    }
+
+    name.chars()
+      .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '-' })
+      .collect()
+  }
+
+  /// Every workspace in the download directory, sorted by name
+  pub fn list(download_dir: &Path) -> Result<Vec<Self>, WorkspaceError> {
+    Self::folders(download_dir, true)
+  }
+
+  /// Folders in the download directory without a manifest, such as ones left by downloads made
+  /// before workspaces existed, sorted by name
+  pub fn list_unmanaged(download_dir: &Path) -> Result<Vec<Self>, WorkspaceError> {
+    Self::folders(download_dir, false)
+  }
+
+  /// Workspaces whose name matches a glob such as `prod-*`, or every workspace without one
+  pub fn select(download_dir: &Path, selector: Option<&Pattern>) -> Result<Vec<Self>, WorkspaceError> {
+    Ok(Self::matching(Self::list(download_dir)?, selector))
+  }
+
+  /// Unmanaged folders whose name matches a glob, or every one without one
+  pub fn select_unmanaged(download_dir: &Path, selector: Option<&Pattern>) -> Result<Vec<Self>, WorkspaceError> {
+    Ok(Self::matching(Self::list_unmanaged(download_dir)?, selector))
+  }
+
+  fn matching(workspaces: Vec<Self>, selector: Option<&Pattern>) -> Vec<Self> {
+    match selector {
+      Some(selector) => workspaces.into_iter().filter(|workspace| selector.matches(&workspace.name)).collect(),
+      None => workspaces,
+    }
+  }
+
+  /// Visible folders in the download directory with or without a manifest
+  fn folders(download_dir: &Path, managed: bool) -> Result<Vec<Self>, WorkspaceError> {
+    if !download_dir.exists() {
+      return Ok(Vec::new());
+    }
+
+    let mut workspaces = Vec::new();
+    for entry in fs::read_dir(download_dir).map_err(WorkspaceError::ReadError)? {
+      let entry = entry.map_err(WorkspaceError::ReadError)?;
+      let path = entry.path();
+      let name = entry.file_name().to_string_lossy().to_string();
+      if path.is_dir() && !name.starts_with('.') && path.join(MANIFEST_FILE_NAME).is_file() == managed {
+        workspaces.push(Self { name, path });
+      }
+    }
+    workspaces.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(workspaces)
+  }
+
+  /// Create the workspace folder if it doesn't exist yet
+  pub fn create(&self) -> Result<(), WorkspaceError> {
+    fs::create_dir_all(&self.path).map_err(WorkspaceError::WriteError)
+  }
+
+  pub fn manifest_path(&self) -> PathBuf {
+    self.path.join(MANIFEST_FILE_NAME)
+  }
+
+  /// The manifest, or `None` for a workspace that hasn't been fetched into yet
+  pub fn load_manifest(&self) -> Result<Option<Manifest>, WorkspaceError> {
+    let path = self.manifest_path();
+    if !path.exists() {
+      return Ok(None);
+    }
+
+    let file = File::open(path).map_err(WorkspaceError::ReadError)?;
+    let manifest = serde_json::from_reader(BufReader::new(file))?;
+
+    Ok(Some(manifest))
+  }
+
+  /// Write the manifest through a temporary file so an interrupted write can't corrupt it
+  pub fn save_manifest(&self, manifest: &Manifest) -> Result<(), WorkspaceError> {
+    fs::create_dir_all(&self.path).map_err(WorkspaceError::WriteError)?;
+
+    let path = self.manifest_path();
+    let tmp_path = path.with_extension("json.tmp");
+    let file = File::create(&tmp_path).map_err(WorkspaceError::WriteError)?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, manifest)?;
+    writer.flush().map_err(WorkspaceError::WriteError)?;
+    drop(writer);
+
+    fs::rename(&tmp_path, &path).map_err(WorkspaceError::WriteError)
+  }
+
+  /// Every log file in the workspace
+  pub fn files(&self) -> Result<Vec<String>, WorkspaceError> {
+    list_files(&self.path).map_err(|e| WorkspaceError::ReadError(e.into()))
+  }
+}
+
+/// The current bound if it's `wider` than the later one. A fetch without a bound read everything
+/// on that side, so the result has none either
+fn wider_bound<F>(current: Option<String>, later: Option<String>, wider: F) -> Option<String>
+where
+  F: Fn(DateTime<Utc>, DateTime<Utc>) -> bool,
+{
+  let (current, later) = (current?, later?);
+  match (DateTime::parse_from_rfc3339(&current), DateTime::parse_from_rfc3339(&later)) {
+    (Ok(current_time), Ok(later_time)) if wider(current_time.to_utc(), later_time.to_utc()) => Some(current),
+    _ => Some(later),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use tempfile::TempDir;
+
+  use super::*;
+
+  #[test]
+  fn lists_folders_without_a_manifest_as_unmanaged() {
+    let dir = TempDir::new().unwrap();
+    let prod = Workspace::new(dir.path(), "prod").unwrap();
+    let query = Query { objects: HashMap::new(), prefix: String::new(), bucket: "logs".to_string(), size: 0 };
+    prod.save_manifest(&Manifest::new("prod", &query, None, None)).unwrap();
+    fs::create_dir_all(dir.path().join("production/api")).unwrap();
+    fs::create_dir_all(dir.path().join(".hidden")).unwrap();
+    fs::write(dir.path().join("stray.log"), "").unwrap();
+
+    let names = |workspaces: Vec<Workspace>| workspaces.into_iter().map(|workspace| workspace.name).collect::<Vec<_>>();
+    assert_eq!(names(Workspace::list(dir.path()).unwrap()), ["prod"]);
+    assert_eq!(names(Workspace::list_unmanaged(dir.path()).unwrap()), ["production"]);
+
+    let selector = Pattern::new("prod-*").unwrap();
+    assert!(Workspace::select_unmanaged(dir.path(), Some(&selector)).unwrap().is_empty());
+  }
+
+  #[test]
+  fn widens_the_time_range_to_cover_every_fetch() {
+    let query = Query { objects: HashMap::new(), prefix: String::new(), bucket: "logs".to_string(), size: 0 };
+    let range = |since: &str, until: &str| TimeRange::parse(Some(since), Some(until), Utc::now()).unwrap();
+    let mut manifest = Manifest::new("prod", &query, None, range("2024-05-03", "2024-05-04").as_ref());
+
+    manifest.update(Manifest::new("prod", &query, None, range("2024-05-01", "2024-05-02").as_ref()));
+    let first = manifest.since.clone();
+    assert!(first.as_deref().is_some_and(|since| since.starts_with("2024-05-01")));
+    assert!(manifest.until.as_deref().is_some_and(|until| until.starts_with("2024-05-05")));
+
+    manifest.update(Manifest::new("prod", &query, None, range("2024-05-02", "2024-05-03").as_ref()));
+    assert_eq!(manifest.since, first);
+
+    manifest.update(Manifest::new("prod", &query, None, None));
+    assert_eq!((manifest.since, manifest.until), (None, None));
+  }
+}
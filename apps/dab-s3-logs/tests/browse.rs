@@ -0,0 +1,38 @@
+mod common;
+
+use dab_s3_logs::commands::browse::{list_level, navigate, BrowseAction, Navigation};
+
+use common::{log_store, BUCKET};
+
+#[tokio::test]
+async fn lists_the_folders_at_each_level() {
+  let store = log_store();
+
+  let root = list_level(&store, BUCKET, "").await.unwrap();
+  assert_eq!(root.folders, ["production", "staging"]);
+  assert_eq!(root.items(), ["[fetch this prefix]", "[preview this prefix]", "production/", "staging/"]);
+
+  let day = list_level(&store, BUCKET, "production/api/2024-05-01/").await.unwrap();
+  assert!(day.folders.is_empty());
+  assert_eq!(day.objects, 1);
+  assert_eq!(day.size, 23);
+}
+
+#[tokio::test]
+async fn navigates_down_and_back_up() {
+  let store = log_store();
+
+  let prefix = match navigate("", "production/") {
+    Navigation::Prefix(prefix) => prefix,
+    other => panic!("expected a prefix, got {:?}", other),
+  };
+  assert_eq!(prefix, "production/");
+
+  let level = list_level(&store, BUCKET, &prefix).await.unwrap();
+  assert_eq!(level.folders, ["api", "web"]);
+  assert_eq!(level.items()[0], "..");
+
+  assert_eq!(navigate("production/api/", ".."), Navigation::Prefix("production/".to_string()));
+  assert_eq!(navigate("production/", "[fetch this prefix]"), Navigation::Select(BrowseAction::Fetch));
+  assert_eq!(navigate("production/", "[preview this prefix]"), Navigation::Select(BrowseAction::Preview));
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use aws::client::ClientOptions;
+use tempfile::TempDir;
+
+use common::{log_store, profile, test_app, BUCKET};
+
+#[tokio::test]
+async fn lists_buckets_from_s3_once_then_from_the_catalog() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let store = log_store();
+  store.create_bucket("archive");
+
+  let catalog = app.bucket_catalog(&profile(Some("dev")));
+  assert!(catalog.load().unwrap().is_none());
+  assert_eq!(catalog.names(&store).await.unwrap(), ["archive", BUCKET]);
+  assert!(dir.path().join("data/buckets/dev.toml").exists());
+
+  // a new bucket only shows up once the catalog is refreshed
+  store.create_bucket("audit");
+  let reopened = test_app(&dir).bucket_catalog(&profile(Some("dev")));
+  assert_eq!(reopened.names(&store).await.unwrap(), ["archive", BUCKET]);
+
+  reopened.refresh(&store).await.unwrap();
+  assert_eq!(reopened.saved_names(), ["archive", "audit", BUCKET]);
+}
+
+#[tokio::test]
+async fn keeps_a_catalog_per_profile() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+
+  app.bucket_catalog(&profile(None)).refresh(&log_store()).await.unwrap();
+
+  assert_eq!(app.bucket_catalog(&profile(None)).profile(), "default");
+  assert_eq!(app.bucket_catalog(&profile(None)).saved_names(), [BUCKET]);
+  assert!(app.bucket_catalog(&profile(Some("prod"))).saved_names().is_empty());
+  assert_eq!(app.buckets.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn keeps_a_catalog_per_endpoint() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let local = ClientOptions { endpoint_url: Some("http://localhost:9000".to_string()), ..profile(None) };
+
+  app.bucket_catalog(&local).refresh(&log_store()).await.unwrap();
+
+  assert_eq!(app.bucket_catalog(&local).saved_names(), [BUCKET]);
+  assert!(app.bucket_catalog(&profile(None)).saved_names().is_empty());
+  assert!(dir.path().join("data/buckets/default@http-localhost-9000.toml").exists());
+}
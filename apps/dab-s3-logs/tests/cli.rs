@@ -0,0 +1,18 @@
+use std::process::Command;
+
+use tempfile::TempDir;
+
+#[test]
+fn local_commands_connect_to_nothing() {
+  let home = TempDir::new().unwrap();
+
+  // an endpoint the S3 client would refuse to be built with
+  let output = Command::new(env!("CARGO_BIN_EXE_dab-s3-logs"))
+    .args(["--endpoint-url", "localhost:9000", "workspaces", "list"])
+    .env("HOME", home.path())
+    .env("XDG_CONFIG_HOME", home.path().join(".config"))
+    .output()
+    .unwrap();
+
+  assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
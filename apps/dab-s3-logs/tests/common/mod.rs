@@ -0,0 +1,84 @@
+#![allow(dead_code)]
+
+use std::{fs, path::{Path, PathBuf}};
+
+use aws::{client::ClientOptions, s3::store::memory::MemoryStore};
+use aws_sdk_s3::primitives::DateTime;
+use chrono::{TimeZone, Utc};
+use dab_s3_logs::{app::{query_builder::QueryBuilder, App}, config::ApplicationConfig};
+use tempfile::TempDir;
+
+pub const BUCKET: &str = "logs";
+
+/// An app whose directories all live in a temporary directory
+pub fn test_app(dir: &TempDir) -> App {
+  test_app_with(dir, |_| {})
+}
+
+pub fn test_app_with<F: FnOnce(&mut ApplicationConfig)>(dir: &TempDir, configure: F) -> App {
+  let root = dir.path();
+  let mut cfg = ApplicationConfig {
+    download_directory: root.join("downloads"),
+    cache_directory: root.join("cache"),
+    data_directory: root.join("data"),
+    home_directory: root.to_path_buf(),
+    aws_config_path: root.join(".aws/config"),
+    retry_base_delay_ms: 1,
+    retry_max_delay_ms: 1,
+    ..Default::default()
+  };
+  configure(&mut cfg);
+
+  App::with_config(cfg).unwrap()
+}
+
+/// Two days of logs for an api and a web service in production, and one staging object
+pub fn log_store() -> MemoryStore {
+  let store = MemoryStore::new();
+  put_log(&store, "production/api/2024-05-01/a.log", "{\"level\":\"info\",\"n\":1}\n", (2024, 5, 1));
+  put_log(&store, "production/api/2024-05-02/b.log", "{\"level\":\"error\",\"n\":2}\n", (2024, 5, 2));
+  put_log(&store, "production/web/2024-05-01/c.log", "{\"level\":\"info\",\"n\":3}\n", (2024, 5, 1));
+  put_log(&store, "staging/api/2024-05-01/d.log", "{\"level\":\"debug\",\"n\":4}\n", (2024, 5, 1));
+  store
+}
+
+/// Store an object modified at noon on the given day
+pub fn put_log(store: &MemoryStore, key: &str, data: impl Into<Vec<u8>>, (year, month, day): (i32, u32, u32)) -> String {
+  let modified = Utc.with_ymd_and_hms(year, month, day, 12, 0, 0).unwrap();
+  store.put_object_modified(BUCKET, key, data, DateTime::from_secs(modified.timestamp()))
+}
+
+/// Client options for a profile on AWS
+pub fn profile(profile: Option<&str>) -> ClientOptions {
+  ClientOptions { profile: profile.map(str::to_string), ..Default::default() }
+}
+
+pub fn production_query() -> QueryBuilder {
+  QueryBuilder::new().bucket(BUCKET).prefix("production/")
+}
+
+/// Every file below a directory relative to it, skipping hidden files, sorted
+pub fn files_in(dir: &Path) -> Vec<String> {
+  let mut files = Vec::new();
+  collect_files(dir, dir, &mut files);
+  files.sort();
+  files
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<String>) {
+  let entries = match fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(_) => return,
+  };
+  for entry in entries.map(Result::unwrap) {
+    let path: PathBuf = entry.path();
+    if entry.file_name().to_string_lossy().starts_with('.') {
+      continue;
+    }
+    if path.is_dir() {
+      collect_files(root, &path, files);
+    } else {
+      files.push(path.strip_prefix(root).unwrap().to_string_lossy().to_string());
+    }
+  }
+}
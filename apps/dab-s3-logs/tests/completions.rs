@@ -0,0 +1,122 @@
+mod common;
+
+use std::{process::Command, time::Duration};
+
+use aws::client::ClientOptions;
+use dab_s3_logs::{commands::completions::{complete_buckets, complete_prefixes}, storage::{cache::FileCache, catalog::BucketCatalog}};
+use tempfile::TempDir;
+
+use common::{log_store, profile, test_app, BUCKET};
+
+/// Run the binary with a home directory of its own, so it reads and writes nothing else
+fn dab_s3_logs(home: &TempDir, args: &[&str]) -> String {
+  let output = Command::new(env!("CARGO_BIN_EXE_dab-s3-logs"))
+    .args(args)
+    .env("HOME", home.path())
+    .env("XDG_CONFIG_HOME", home.path().join(".config"))
+    .output()
+    .unwrap();
+  assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+  String::from_utf8(output.stdout).unwrap()
+}
+
+/// The script `completions` prints, generated by the binary so it comes from the real command line
+fn completion_script(shell: &str) -> String {
+  dab_s3_logs(&TempDir::new().unwrap(), &["completions", shell])
+}
+
+#[test]
+fn bash_completion_calls_back_for_values() {
+  let script = completion_script("bash");
+
+  assert!(script.starts_with("_dab-s3-logs() {"));
+  assert!(script.contains("complete -F _dab_s3_logs_dynamic"));
+}
+
+#[test]
+fn zsh_completion_calls_back_for_values() {
+  let script = completion_script("zsh");
+
+  assert!(script.contains(":PROFILE:__dab_s3_logs_profiles"));
+  assert!(script.contains(":BUCKET:__dab_s3_logs_buckets"));
+  assert!(script.contains(":PREFIX:__dab_s3_logs_prefixes"));
+}
+
+#[test]
+fn fish_completion_calls_back_for_values() {
+  let script = completion_script("fish");
+
+  assert!(script.contains("-l profile") && script.contains("(__dab_s3_logs_complete profiles)"));
+  assert!(script.lines().any(|line| line.contains("-l bucket") && line.ends_with("(__dab_s3_logs_complete buckets)\"")));
+  assert!(script.lines().any(|line| line.contains("-l prefix") && line.ends_with("(__dab_s3_logs_complete prefixes)\"")));
+}
+
+#[tokio::test]
+async fn completes_prefixes_one_level_at_a_time() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let cache = FileCache::new(&dir.path().join("cache/completions"));
+  let catalog = app.bucket_catalog(&profile(None));
+  let store = log_store();
+
+  assert_eq!(complete_prefixes(&store, &cache, &catalog, BUCKET, "").await.unwrap(), ["production/", "staging/"]);
+  assert_eq!(complete_prefixes(&store, &cache, &catalog, BUCKET, "prod").await.unwrap(), ["production/"]);
+  assert_eq!(complete_prefixes(&store, &cache, &catalog, BUCKET, "production/").await.unwrap(), ["production/api/", "production/web/"]);
+  assert_eq!(complete_prefixes(&store, &cache, &catalog, BUCKET, "production/w").await.unwrap(), ["production/web/"]);
+}
+
+#[tokio::test]
+async fn reuses_a_listing_until_it_expires() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let cache = FileCache::new(&dir.path().join("cache/completions"));
+  let catalog = app.bucket_catalog(&profile(None));
+  let store = log_store();
+
+  complete_prefixes(&store, &cache, &catalog, BUCKET, "").await.unwrap();
+  store.put_object(BUCKET, "archive/a.log", "a");
+
+  assert_eq!(complete_prefixes(&store, &cache, &catalog, BUCKET, "").await.unwrap(), ["production/", "staging/"]);
+}
+
+#[test]
+fn expires_cache_entries() {
+  let dir = TempDir::new().unwrap();
+  let cache = FileCache::new(dir.path());
+
+  cache.put("kept", &vec!["a"], None).unwrap();
+  cache.put("fresh", &vec!["b"], Some(Duration::from_secs(60))).unwrap();
+  cache.put("stale", &vec!["c"], Some(Duration::ZERO)).unwrap();
+
+  assert_eq!(cache.get::<Vec<String>>("kept"), Some(vec!["a".to_string()]));
+  assert_eq!(cache.get::<Vec<String>>("fresh"), Some(vec!["b".to_string()]));
+  assert_eq!(cache.get::<Vec<String>>("stale"), None);
+  assert_eq!(cache.get::<Vec<String>>("missing"), None);
+}
+
+#[tokio::test]
+async fn completes_buckets_from_the_saved_catalog_only() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let catalog = app.bucket_catalog(&profile(Some("dev")));
+  assert!(complete_buckets(&catalog, "").is_empty());
+
+  let store = log_store();
+  store.create_bucket("archive");
+  catalog.refresh(&store).await.unwrap();
+
+  assert_eq!(complete_buckets(&catalog, ""), ["archive", BUCKET]);
+  assert_eq!(complete_buckets(&catalog, "ar"), ["archive"]);
+}
+
+#[tokio::test]
+async fn completes_buckets_of_the_endpoint_passed() {
+  let home = TempDir::new().unwrap();
+  let options = ClientOptions { endpoint_url: Some("http://localhost:9000".to_string()), ..profile(None) };
+  let catalog = BucketCatalog::new(&home.path().join("dab-s3-logs/data"), &options, Default::default());
+  catalog.refresh(&log_store()).await.unwrap();
+
+  assert_eq!(dab_s3_logs(&home, &["--endpoint-url", "http://localhost:9000", "complete-value", "buckets"]), "logs\n");
+  assert_eq!(dab_s3_logs(&home, &["complete-value", "buckets"]), "");
+}
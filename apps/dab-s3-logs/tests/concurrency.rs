@@ -0,0 +1,81 @@
+mod common;
+
+use std::fs;
+
+use dab_s3_logs::{app::scheduler::{concurrency_file, is_fetch_running, write_concurrency_file, RunningFetch}, commands::{concurrency::set_fetch_concurrency, fetch::{fetch, FetchOptions}}};
+use tempfile::TempDir;
+
+use common::{log_store, production_query, test_app};
+
+#[test]
+fn fails_when_no_fetch_is_running() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+
+  assert!(set_fetch_concurrency(&app, 3, None).is_err());
+  assert!(set_fetch_concurrency(&app, 3, Some("prod".to_string())).is_err());
+}
+
+#[test]
+fn changes_every_running_fetch() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let data_dir = dir.path().join("data");
+  let _prod = RunningFetch::start(&data_dir, "prod", 8).unwrap();
+  let _stage = RunningFetch::start(&data_dir, "stage", 8).unwrap();
+
+  set_fetch_concurrency(&app, 3, None).unwrap();
+
+  assert_eq!(fs::read_to_string(concurrency_file(&data_dir, "prod")).unwrap(), "3");
+  assert_eq!(fs::read_to_string(concurrency_file(&data_dir, "stage")).unwrap(), "3");
+}
+
+#[test]
+fn ignores_and_removes_limits_left_by_killed_fetches() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let data_dir = dir.path().join("data");
+  write_concurrency_file(&concurrency_file(&data_dir, "prod"), 8).unwrap();
+
+  assert!(set_fetch_concurrency(&app, 3, None).is_err());
+  assert!(!concurrency_file(&data_dir, "prod").exists());
+}
+
+#[test]
+fn a_running_fetch_replaces_a_leftover_limit_and_removes_it_when_dropped() {
+  let dir = TempDir::new().unwrap();
+  let data_dir = dir.path().join("data");
+  write_concurrency_file(&concurrency_file(&data_dir, "prod"), 1).unwrap();
+
+  let running = RunningFetch::start(&data_dir, "prod", 4).unwrap();
+  assert_eq!(fs::read_to_string(concurrency_file(&data_dir, "prod")).unwrap(), "4");
+  assert!(is_fetch_running(&data_dir, "prod"));
+  assert!(RunningFetch::start(&data_dir, "prod", 4).is_err(), "one fetch into a workspace at a time");
+
+  drop(running);
+  assert!(!concurrency_file(&data_dir, "prod").exists());
+  assert!(!is_fetch_running(&data_dir, "prod"));
+}
+
+#[tokio::test]
+async fn a_fetch_removes_only_its_own_limit() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let data_dir = dir.path().join("data");
+  let _stage = RunningFetch::start(&data_dir, "stage", 8).unwrap();
+
+  let options = FetchOptions { workspace: Some("prod".to_string()), concurrency: Some(4), ..Default::default() };
+  fetch(&log_store(), &app, production_query(), options).await.unwrap();
+
+  assert!(!concurrency_file(&data_dir, "prod").exists());
+  assert!(!is_fetch_running(&data_dir, "prod"));
+  assert!(concurrency_file(&data_dir, "stage").exists());
+}
+
+#[test]
+fn rejects_invalid_workspace_names() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+
+  assert!(set_fetch_concurrency(&app, 3, Some("../prod".to_string())).is_err());
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use std::fs;
+
+use chrono::Utc;
+use dab_s3_logs::{app::{query_builder::QueryBuilder, time_range::TimeRange}, commands::{fetch::{errors::FetchError, fetch, preview, FetchOptions}, verify::{errors::VerifyError, verify_downloaded_logs, VerifyOptions}}, storage::{index::{DownloadIndex, IndexEntry}, integrity::{IntegrityChecker, Problem}, workspace::Workspace}};
+use tempfile::TempDir;
+
+use common::{files_in, log_store, production_query, put_log, test_app, test_app_with, BUCKET};
+
+fn into_workspace(name: &str) -> FetchOptions {
+  FetchOptions { workspace: Some(name.to_string()), profile: None, concurrency: Some(2), yes: false }
+}
+
+#[tokio::test]
+async fn fetches_every_object_into_the_workspace() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let store = log_store();
+
+  let files = fetch(&store, &app, production_query(), into_workspace("prod")).await.unwrap();
+
+  assert_eq!(files.len(), 3);
+  let workspace_dir = dir.path().join("downloads/prod");
+  assert_eq!(files_in(&workspace_dir), [
+    "production/api/2024-05-01/a.log",
+    "production/api/2024-05-02/b.log",
+    "production/web/2024-05-01/c.log",
+  ]);
+  assert_eq!(fs::read_to_string(workspace_dir.join("production/api/2024-05-02/b.log")).unwrap(), "{\"level\":\"error\",\"n\":2}\n");
+
+  let manifest = Workspace::new(&dir.path().join("downloads"), "prod").unwrap().load_manifest().unwrap().unwrap();
+  assert_eq!(manifest.bucket, BUCKET);
+  assert_eq!(manifest.objects.len(), 3);
+
+  let index = DownloadIndex::load(&dir.path().join("data")).unwrap();
+  assert_eq!(index.entries().count(), 3);
+  assert!(index.entries().all(|entry| entry.md5.is_some()));
+}
+
+#[tokio::test]
+async fn only_refetches_objects_that_changed() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let store = log_store();
+
+  fetch(&store, &app, production_query(), into_workspace("prod")).await.unwrap();
+  let unchanged = fetch(&store, &app, production_query(), into_workspace("prod")).await.unwrap();
+  assert!(unchanged.is_empty());
+
+  put_log(&store, "production/api/2024-05-01/a.log", "{\"level\":\"warn\",\"n\":5}\n", (2024, 5, 1));
+  let changed = fetch(&store, &app, production_query(), into_workspace("prod")).await.unwrap();
+
+  assert_eq!(changed.len(), 1);
+  assert!(changed[0].ends_with("production/api/2024-05-01/a.log"));
+  let content = fs::read_to_string(dir.path().join("downloads/prod/production/api/2024-05-01/a.log")).unwrap();
+  assert_eq!(content, "{\"level\":\"warn\",\"n\":5}\n");
+}
+
+#[tokio::test]
+async fn fetches_large_objects_as_byte_ranges() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app_with(&dir, |cfg| {
+    cfg.multipart_threshold = 16;
+    cfg.multipart_part_size = 5;
+    cfg.multipart_part_concurrency = 3;
+  });
+  let store = log_store();
+  let data: String = (0..40).map(|n| format!("{{\"n\":{}}}\n", n)).collect();
+  put_log(&store, "production/api/2024-05-03/large.log", data.as_str(), (2024, 5, 3));
+
+  fetch(&store, &app, production_query(), into_workspace("prod")).await.unwrap();
+
+  let content = fs::read_to_string(dir.path().join("downloads/prod/production/api/2024-05-03/large.log")).unwrap();
+  assert_eq!(content, data);
+  let report = IntegrityChecker::new(&dir.path().join("downloads"))
+    .verify(&DownloadIndex::load(&dir.path().join("data")).unwrap(), None, |_| {})
+    .unwrap();
+  assert_eq!(report.verified, 4);
+  assert!(report.findings.is_empty());
+}
+
+#[tokio::test]
+async fn only_fetches_days_in_the_time_range() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let store = log_store();
+  let time_range = TimeRange::parse(Some("2024-05-02..2024-05-03"), None, Utc::now()).unwrap();
+  let query_builder = QueryBuilder::new().bucket(BUCKET).prefix("production").service("api").time_range(time_range);
+
+  let files = fetch(&store, &app, query_builder, into_workspace("api")).await.unwrap();
+
+  assert_eq!(files.len(), 1);
+  assert_eq!(files_in(&dir.path().join("downloads/api")), ["production/api/2024-05-02/b.log"]);
+}
+
+#[tokio::test]
+async fn verify_finds_files_changed_after_fetching() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let store = log_store();
+  fetch(&store, &app, production_query(), into_workspace("prod")).await.unwrap();
+
+  let corrupted = dir.path().join("downloads/prod/production/web/2024-05-01/c.log");
+  fs::write(&corrupted, "{\"level\":\"INFO\",\"n\":3}\n").unwrap();
+  let report = IntegrityChecker::new(&dir.path().join("downloads"))
+    .verify(&DownloadIndex::load(&dir.path().join("data")).unwrap(), None, |_| {})
+    .unwrap();
+
+  assert_eq!(report.verified, 2);
+  assert_eq!(report.findings.len(), 1);
+  assert_eq!(report.findings[0].path, corrupted);
+  assert!(matches!(report.findings[0].problem, Problem::ChecksumMismatch { .. }));
+}
+
+#[tokio::test]
+async fn verify_succeeds_once_damaged_files_are_removed() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  fetch(&log_store(), &app, production_query(), into_workspace("prod")).await.unwrap();
+  let corrupted = dir.path().join("downloads/prod/production/web/2024-05-01/c.log");
+  fs::write(&corrupted, "{\"level\":\"INFO\",\"n\":3}\n").unwrap();
+
+  let result = verify_downloaded_logs(&app, VerifyOptions::default()).await;
+  assert!(matches!(result, Err(VerifyError::Failed(1))));
+
+  verify_downloaded_logs(&app, VerifyOptions { remove: true, ..Default::default() }).await.unwrap();
+  assert!(!corrupted.exists());
+  verify_downloaded_logs(&app, VerifyOptions::default()).await.unwrap();
+}
+
+#[tokio::test]
+async fn fails_to_fetch_from_a_missing_bucket() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let query_builder = QueryBuilder::new().bucket("missing").prefix("production/");
+
+  let result = fetch(&log_store(), &app, query_builder, into_workspace("prod")).await;
+
+  assert!(matches!(result, Err(FetchError::ListFailed)));
+}
+
+#[tokio::test]
+async fn previews_each_service() {
+  let store = log_store();
+  let query_builder = QueryBuilder::new().bucket(BUCKET).prefix("production").service("api").service("web");
+
+  let queries = query_builder.build(&store).await.unwrap();
+  assert_eq!(queries.iter().map(|query| query.objects.len()).collect::<Vec<_>>(), [2, 1]);
+  assert_eq!(queries[0].size, 47);
+
+  assert!(preview(&store, query_builder).await.is_ok());
+}
+
+#[tokio::test]
+async fn evicts_least_recently_used_logs_to_make_room_when_confirmed() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let store = log_store();
+  fetch(&store, &app, production_query(), into_workspace("prod")).await.unwrap();
+  let used = fs_extra::dir::get_size(dir.path().join("downloads")).unwrap();
+  // room for less than the staging object, so a production log has to go
+  app.config.lock().unwrap().as_mut().unwrap().max_storage = used + 10;
+
+  let staging = QueryBuilder::new().bucket(BUCKET).prefix("staging/");
+  let options = FetchOptions { yes: true, ..into_workspace("stage") };
+  let files = fetch(&store, &app, staging, options).await.unwrap();
+
+  assert_eq!(files.len(), 1);
+  assert_eq!(files_in(&dir.path().join("downloads/prod")).len(), 2);
+  assert_eq!(DownloadIndex::load(&dir.path().join("data")).unwrap().entries().count(), 3);
+}
+
+#[tokio::test]
+async fn adopts_logs_downloaded_before_workspaces() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let store = log_store();
+  let download_dir = dir.path().join("downloads");
+  fetch(&store, &app, production_query(), into_workspace("prod")).await.unwrap();
+  // lay the download out as it was before workspaces: files in the download directory, indexed without one
+  fs::rename(download_dir.join("prod/production"), download_dir.join("production")).unwrap();
+  fs::remove_dir_all(download_dir.join("prod")).unwrap();
+  let mut index = DownloadIndex::load(&dir.path().join("data")).unwrap();
+  for entry in index.entries().cloned().collect::<Vec<_>>() {
+    index.remove(entry.workspace.as_deref(), &entry.bucket, &entry.key);
+    index.record(IndexEntry { workspace: None, ..entry }).unwrap();
+  }
+  index.compact().unwrap();
+
+  let files = fetch(&store, &app, production_query(), into_workspace("prod")).await.unwrap();
+
+  assert!(files.is_empty(), "nothing is downloaded again");
+  assert_eq!(files_in(&download_dir.join("prod")).len(), 3);
+  assert!(!download_dir.join("production").exists());
+  let index = DownloadIndex::load(&dir.path().join("data")).unwrap();
+  assert!(index.entries().all(|entry| entry.workspace.as_deref() == Some("prod")));
+}
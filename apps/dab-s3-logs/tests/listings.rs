@@ -0,0 +1,111 @@
+mod common;
+
+use std::time::Duration;
+
+use aws::{client::ClientOptions, s3::Query};
+use aws_sdk_s3::primitives::DateTime;
+use chrono::{Datelike, TimeZone, Utc};
+use dab_s3_logs::{app::time_range::TimeRange, storage::listings::ListingCache};
+use tempfile::TempDir;
+
+use common::{log_store, production_query, put_log, BUCKET};
+
+fn listing_cache(dir: &TempDir, ttl: Duration) -> ListingCache {
+  ListingCache::new(&dir.path().join("cache/listings"), &ClientOptions::default(), ttl)
+}
+
+fn keys(queries: &[Query]) -> Vec<String> {
+  let mut keys: Vec<String> = queries.iter().flat_map(|query| query.objects.keys().cloned()).collect();
+  keys.sort();
+  keys
+}
+
+#[tokio::test]
+async fn reuses_a_listing_until_refreshed() {
+  let dir = TempDir::new().unwrap();
+  let store = log_store();
+  let cache = listing_cache(&dir, Duration::from_secs(60));
+
+  let first = production_query().listing_cache(cache.clone()).build(&store).await.unwrap();
+  put_log(&store, "production/api/2024-05-03/e.log", "{}\n", (2024, 5, 3));
+
+  let second = production_query().listing_cache(cache.clone()).build(&store).await.unwrap();
+  assert_eq!(keys(&second), keys(&first));
+  assert_eq!(second[0].size, first[0].size);
+  let key = "production/api/2024-05-01/a.log";
+  assert_eq!(second[0].objects[key].last_modified, first[0].objects[key].last_modified);
+  assert_eq!(second[0].objects[key].e_tag, first[0].objects[key].e_tag);
+
+  let refreshed = production_query().listing_cache(cache.clone().refresh(true)).build(&store).await.unwrap();
+  assert!(keys(&refreshed).contains(&"production/api/2024-05-03/e.log".to_string()));
+
+  // the refreshed listing replaces the saved one
+  let after = production_query().listing_cache(cache).build(&store).await.unwrap();
+  assert_eq!(keys(&after), keys(&refreshed));
+}
+
+#[tokio::test]
+async fn keeps_listings_of_past_days_after_the_ttl() {
+  let dir = TempDir::new().unwrap();
+  let store = log_store();
+  let cache = listing_cache(&dir, Duration::ZERO);
+  let range = TimeRange {
+    start: Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap(),
+    end: Utc.with_ymd_and_hms(2024, 5, 3, 0, 0, 0).unwrap(),
+  };
+  let query = || production_query().service("api").time_range(Some(range)).listing_cache(cache.clone());
+
+  query().build(&store).await.unwrap();
+  put_log(&store, "production/api/2024-05-02/late.log", "{}\n", (2024, 5, 2));
+
+  assert_eq!(keys(&query().build(&store).await.unwrap()), ["production/api/2024-05-01/a.log", "production/api/2024-05-02/b.log"]);
+}
+
+#[tokio::test]
+async fn lists_the_current_day_again_once_the_ttl_passes() {
+  let dir = TempDir::new().unwrap();
+  let store = log_store();
+  let cache = listing_cache(&dir, Duration::ZERO);
+  let today = Utc::now();
+  let range = TimeRange { start: today - chrono::Duration::minutes(5), end: today + chrono::Duration::minutes(5) };
+  let query = || production_query().service("api").time_range(Some(range)).listing_cache(cache.clone());
+
+  assert!(keys(&query().build(&store).await.unwrap()).is_empty());
+  let key = format!("production/api/{}/now.log", today.format("%Y-%m-%d"));
+  store.put_object_modified(BUCKET, &key, "{}\n", DateTime::from_secs(today.timestamp()));
+
+  assert_eq!(keys(&query().build(&store).await.unwrap()), [key]);
+}
+
+#[tokio::test]
+async fn lists_the_folder_of_the_current_month_again_once_the_ttl_passes() {
+  let dir = TempDir::new().unwrap();
+  let store = log_store();
+  let cache = listing_cache(&dir, Duration::ZERO);
+  let today = Utc::now();
+  let month_start = Utc.with_ymd_and_hms(today.year(), today.month(), 1, 0, 0, 0).unwrap();
+  // the first day of the month is over, but logs of later days still go to its folder
+  let range = TimeRange { start: month_start, end: month_start + chrono::Duration::days(1) };
+  let query = || production_query().service("api").date_prefix_format("%Y-%m").time_range(Some(range)).listing_cache(cache.clone());
+
+  assert!(keys(&query().build(&store).await.unwrap()).is_empty());
+  let key = format!("production/api/{}/late.log", month_start.format("%Y-%m"));
+  store.put_object_modified(BUCKET, &key, "{}\n", DateTime::from_secs(month_start.timestamp() + 60));
+
+  assert_eq!(keys(&query().build(&store).await.unwrap()), [key]);
+}
+
+#[tokio::test]
+async fn keeps_listings_of_each_endpoint_apart() {
+  let dir = TempDir::new().unwrap();
+  let store = log_store();
+  let aws = listing_cache(&dir, Duration::from_secs(60));
+  let local_options = ClientOptions { endpoint_url: Some("http://localhost:9000".to_string()), ..Default::default() };
+  let local = ListingCache::new(&dir.path().join("cache/listings"), &local_options, Duration::from_secs(60));
+
+  production_query().listing_cache(aws).build(&store).await.unwrap();
+  put_log(&store, "production/web/2024-05-02/f.log", "{}\n", (2024, 5, 2));
+
+  let listed = production_query().listing_cache(local).build(&store).await.unwrap();
+  assert!(keys(&listed).contains(&"production/web/2024-05-02/f.log".to_string()));
+}
@@ -0,0 +1,22 @@
+use std::{io, process::{Command, Stdio}};
+
+use tempfile::TempDir;
+
+#[test]
+fn stops_quietly_when_stdout_is_closed() {
+  let home = TempDir::new().unwrap();
+  let (reader, writer) = io::pipe().unwrap();
+  // like `| head` once it has read all it wants
+  drop(reader);
+
+  let output = Command::new(env!("CARGO_BIN_EXE_dab-s3-logs"))
+    .args(["output", "--format", "csv", "--fields", "level"])
+    .env("HOME", home.path())
+    .env("XDG_CONFIG_HOME", home.path().join(".config"))
+    .stdout(Stdio::from(writer))
+    .stderr(Stdio::piped())
+    .output()
+    .unwrap();
+
+  assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use std::{fs, time::Duration};
+
+use dab_s3_logs::{app::App, commands::{fetch::{fetch, FetchOptions}, reset::{delete_downloaded_logs, errors::ResetError, ResetOptions}}, storage::{index::DownloadIndex, listings::{ListingCache, LISTING_CACHE_DIRECTORY}}};
+use is_terminal::is_terminal;
+use tempfile::TempDir;
+
+use common::{files_in, log_store, production_query, profile, test_app};
+
+/// An app with the production logs fetched into the `prod` workspace
+async fn fetched(dir: &TempDir) -> App {
+  let app = test_app(dir);
+  let options = FetchOptions { workspace: Some("prod".to_string()), profile: None, concurrency: Some(2), yes: false };
+  fetch(&log_store(), &app, production_query(), options).await.unwrap();
+  app
+}
+
+#[tokio::test]
+async fn dry_run_deletes_nothing() {
+  let dir = TempDir::new().unwrap();
+  let app = fetched(&dir).await;
+
+  let options = ResetOptions { dry_run: true, ..Default::default() };
+  delete_downloaded_logs(&app, options).await.unwrap();
+
+  assert_eq!(files_in(&dir.path().join("downloads/prod")).len(), 3);
+}
+
+#[tokio::test]
+async fn deletes_matching_logs_when_confirmed() {
+  let dir = TempDir::new().unwrap();
+  let app = fetched(&dir).await;
+
+  let options = ResetOptions { prefix: Some("production/api/".to_string()), yes: true, ..Default::default() };
+  delete_downloaded_logs(&app, options).await.unwrap();
+
+  assert_eq!(files_in(&dir.path().join("downloads/prod")), ["production/web/2024-05-01/c.log"]);
+  assert_eq!(DownloadIndex::load(&dir.path().join("data")).unwrap().entries().count(), 1);
+}
+
+#[tokio::test]
+async fn needs_yes_when_not_in_a_terminal() {
+  // a terminal would be prompted instead
+  if is_terminal(std::io::stdin()) {
+    return;
+  }
+  let dir = TempDir::new().unwrap();
+  let app = fetched(&dir).await;
+
+  let result = delete_downloaded_logs(&app, ResetOptions::default()).await;
+
+  assert!(matches!(result, Err(ResetError::Declined)));
+  assert_eq!(files_in(&dir.path().join("downloads/prod")).len(), 3);
+}
+
+#[tokio::test]
+async fn clears_saved_listings_unless_scoped() {
+  let dir = TempDir::new().unwrap();
+  let app = fetched(&dir).await;
+  let listings_dir = dir.path().join("cache").join(LISTING_CACHE_DIRECTORY);
+  let listings = ListingCache::new(&listings_dir, &profile(None), Duration::from_secs(60));
+  production_query().listing_cache(listings).build(&log_store()).await.unwrap();
+
+  let scoped = ResetOptions { prefix: Some("production/api/".to_string()), yes: true, ..Default::default() };
+  delete_downloaded_logs(&app, scoped).await.unwrap();
+  assert!(!files_in(&listings_dir).is_empty());
+
+  delete_downloaded_logs(&app, ResetOptions { yes: true, ..Default::default() }).await.unwrap();
+  assert!(!listings_dir.exists());
+  assert!(files_in(&dir.path().join("downloads/prod")).is_empty());
+}
+
+#[tokio::test]
+async fn asks_before_clearing_saved_listings_with_no_logs_to_delete() {
+  // a terminal would be prompted instead
+  if is_terminal(std::io::stdin()) {
+    return;
+  }
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let listings_dir = dir.path().join("cache").join(LISTING_CACHE_DIRECTORY);
+  let listings = ListingCache::new(&listings_dir, &profile(None), Duration::from_secs(60));
+  production_query().listing_cache(listings).build(&log_store()).await.unwrap();
+
+  let result = delete_downloaded_logs(&app, ResetOptions::default()).await;
+
+  assert!(matches!(result, Err(ResetError::Declined)));
+  assert!(!files_in(&listings_dir).is_empty());
+}
+
+#[tokio::test]
+async fn deletes_interrupted_downloads_unless_scoped() {
+  let dir = TempDir::new().unwrap();
+  let app = fetched(&dir).await;
+  let part = dir.path().join("downloads/prod/production/web/2024-05-02/.d.log.part");
+  fs::create_dir_all(part.parent().unwrap()).unwrap();
+  fs::write(&part, "partial").unwrap();
+
+  let scoped = ResetOptions { workspace: Some("prod".to_string()), yes: true, ..Default::default() };
+  delete_downloaded_logs(&app, scoped).await.unwrap();
+  assert!(part.exists());
+
+  delete_downloaded_logs(&app, ResetOptions { yes: true, ..Default::default() }).await.unwrap();
+  assert!(!part.exists());
+  assert!(!dir.path().join("downloads/prod").exists());
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use dab_s3_logs::{app::{query_builder::QueryBuilder, stream::stream_query_results}, commands::stream::{errors::StreamError, stream}, output::OutputOptions};
+use tempfile::TempDir;
+
+use common::{files_in, log_store, production_query, put_log, test_app};
+
+#[tokio::test]
+async fn streams_every_object() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let store = log_store();
+  let query = production_query().build_merged(&store).await.unwrap();
+
+  let report = stream_query_results(&query, &app, &store, OutputOptions::default(), 2).await.unwrap();
+
+  assert_eq!(report.objects, 3);
+  assert!(report.failures.is_empty());
+  assert!(files_in(&dir.path().join("downloads")).is_empty(), "nothing is stored");
+}
+
+#[tokio::test]
+async fn reports_objects_that_fail_to_decode() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let store = log_store();
+  // a gzip header followed by garbage
+  put_log(&store, "production/api/2024-05-01/broken.log.gz", [0x1f, 0x8b, 0x08, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00, 0x03, 0xde, 0xad], (2024, 5, 1));
+
+  let result = stream(&store, &app, production_query(), OutputOptions::default(), Some(2)).await;
+
+  assert!(matches!(result, Err(StreamError::PartialStream { failed: 1, total: 4 })), "{:?}", result);
+  assert_eq!(result.unwrap_err().exit_code(), 2);
+}
+
+#[tokio::test]
+async fn fails_to_stream_from_a_missing_bucket() {
+  let dir = TempDir::new().unwrap();
+  let app = test_app(&dir);
+  let query_builder = QueryBuilder::new().bucket("missing").prefix("production/");
+
+  let result = stream(&log_store(), &app, query_builder, OutputOptions::default(), None).await;
+
+  assert!(matches!(result, Err(StreamError::ListFailed)));
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use std::fs;
+
+use dab_s3_logs::{app::{scheduler::RunningFetch, App}, commands::{fetch::{fetch, FetchOptions}, verify::{errors::VerifyError, verify_downloaded_logs, VerifyOptions}}};
+use tempfile::TempDir;
+
+use common::{log_store, production_query, test_app};
+
+/// An app with the production logs fetched into the `prod` workspace
+async fn fetched(dir: &TempDir) -> App {
+  let app = test_app(dir);
+  let options = FetchOptions { workspace: Some("prod".to_string()), profile: None, concurrency: Some(2), yes: false };
+  fetch(&log_store(), &app, production_query(), options).await.unwrap();
+  app
+}
+
+#[tokio::test]
+async fn removes_damaged_and_incomplete_downloads() {
+  let dir = TempDir::new().unwrap();
+  let app = fetched(&dir).await;
+  let damaged = dir.path().join("downloads/prod/production/web/2024-05-01/c.log");
+  fs::write(&damaged, "changed").unwrap();
+  let part = dir.path().join("downloads/prod/production/web/2024-05-02/.d.log.part");
+  fs::create_dir_all(part.parent().unwrap()).unwrap();
+  fs::write(&part, "partial").unwrap();
+
+  let result = verify_downloaded_logs(&app, VerifyOptions::default()).await;
+  assert!(matches!(result, Err(VerifyError::Failed(2))));
+
+  verify_downloaded_logs(&app, VerifyOptions { remove: true, ..Default::default() }).await.unwrap();
+  assert!(!damaged.exists());
+  assert!(!part.exists());
+}
+
+#[tokio::test]
+async fn leaves_the_downloads_of_a_running_fetch_alone() {
+  let dir = TempDir::new().unwrap();
+  let app = fetched(&dir).await;
+  let part = dir.path().join("downloads/prod/production/web/2024-05-02/.d.log.part");
+  fs::create_dir_all(part.parent().unwrap()).unwrap();
+  fs::write(&part, "partial").unwrap();
+
+  let running = RunningFetch::start(&dir.path().join("data"), "prod", 2).unwrap();
+  verify_downloaded_logs(&app, VerifyOptions { remove: true, ..Default::default() }).await.unwrap();
+  assert!(part.exists());
+
+  drop(running);
+  verify_downloaded_logs(&app, VerifyOptions { remove: true, ..Default::default() }).await.unwrap();
+  assert!(!part.exists());
+}
@@ -4,4 +4,6 @@ use thiserror::Error;
 pub enum ClientError {
   #[error("Failed to get client")]
   GetClientError,
-}
\ No newline at end of file
+  #[error("Endpoint URL {0} must start with http:// or https://")]
+  InvalidEndpointUrl(String),
+}
@@ -1,21 +1,102 @@
-use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_s3::Client;
+use aws_config::{environment::region::EnvironmentVariableRegionProvider, imds::region::ImdsRegionProvider, meta::region::RegionProviderChain, profile::{profile_file::ProfileFiles, ProfileFileRegionProvider}, Region, SdkConfig};
+use aws_sdk_s3::{config::Builder, Client};
 
 pub mod errors;
 
-// TODO: move to application config
-const REGION: &str = "ap-southeast-2";
+/// Region used when neither the options, the environment, the profile nor the instance set one
+pub const DEFAULT_REGION: &str = "ap-southeast-2";
 
-pub async fn get_aws_client(profile: Option<String>) -> Result<Client, errors::ClientError> {
-  let region_provider = RegionProviderChain::default_provider().or_else(REGION);
-  let mut config_builder = aws_config::from_env().region(region_provider);
+/// How the S3 client is set up. Anything left unset comes from the environment and profile, the
+/// region falling back to the instance's and then [`DEFAULT_REGION`]
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+  pub profile: Option<String>,
+  /// Takes precedence over the region of the environment and profile
+  pub region: Option<String>,
+  /// Endpoint of an S3 compatible store such as MinIO or LocalStack, e.g. `http://localhost:9000`
+  pub endpoint_url: Option<String>,
+  /// Address buckets as `<endpoint>/<bucket>` rather than `<bucket>.<endpoint>`, which most
+  /// S3 compatible stores need
+  pub force_path_style: bool,
+}
+
+pub async fn get_aws_client(options: ClientOptions) -> Result<Client, errors::ClientError> {
+  let config = load_sdk_config(&options).await;
+  let mut s3_config = Builder::from(&config).force_path_style(options.force_path_style);
 
-  if profile.is_some() {
-    config_builder = config_builder.profile_name(profile.unwrap());
+  // only S3 goes to the custom endpoint, other services like STS stay on AWS
+  if let Some(endpoint_url) = options.endpoint_url {
+    if !endpoint_url.starts_with("http://") && !endpoint_url.starts_with("https://") {
+      return Err(errors::ClientError::InvalidEndpointUrl(endpoint_url));
+    }
+    s3_config = s3_config.endpoint_url(endpoint_url);
   }
 
-  let config = config_builder.load().await;
-  let client = Client::new(&config);
+  let client = Client::from_conf(s3_config.build());
 
   Ok(client)
 }
+
+/// Shared SDK config for the options' profile and region, which every service client is built from
+pub async fn load_sdk_config(options: &ClientOptions) -> SdkConfig {
+  let mut config_builder = aws_config::from_env().region(region_provider(options, ProfileFiles::default()));
+
+  if let Some(profile) = &options.profile {
+    config_builder = config_builder.profile_name(profile);
+  }
+
+  config_builder.load().await
+}
+
+/// The region of the options, the environment, the options' profile or the instance, in that order.
+/// The SDK's default chain would read the region of the default or `AWS_PROFILE` profile instead
+fn region_provider(options: &ClientOptions, profile_files: ProfileFiles) -> RegionProviderChain {
+  let mut profile_provider = ProfileFileRegionProvider::builder().profile_files(profile_files);
+  if let Some(profile) = &options.profile {
+    profile_provider = profile_provider.profile_name(profile);
+  }
+
+  RegionProviderChain::first_try(options.region.clone().map(Region::new))
+    .or_else(EnvironmentVariableRegionProvider::new())
+    .or_else(profile_provider.build())
+    .or_else(ImdsRegionProvider::builder().build())
+    .or_else(DEFAULT_REGION)
+}
+
+#[cfg(test)]
+mod tests {
+  use aws_config::profile::profile_file::ProfileFileKind;
+
+  use super::*;
+
+  const CONFIG: &str = "[default]\nregion = us-east-1\n\n[profile logs]\nregion = eu-west-1\n";
+
+  fn profile_files() -> ProfileFiles {
+    ProfileFiles::builder().with_contents(ProfileFileKind::Config, CONFIG).build()
+  }
+
+  fn region_set_by_environment() -> bool {
+    std::env::var_os("AWS_REGION").is_some() || std::env::var_os("AWS_DEFAULT_REGION").is_some()
+  }
+
+  #[tokio::test]
+  async fn uses_the_region_of_the_selected_profile() {
+    if region_set_by_environment() {
+      return;
+    }
+    let options = ClientOptions { profile: Some("logs".to_string()), ..Default::default() };
+
+    let region = region_provider(&options, profile_files()).region().await;
+
+    assert_eq!(region, Some(Region::new("eu-west-1")));
+  }
+
+  #[tokio::test]
+  async fn prefers_the_region_of_the_options() {
+    let options = ClientOptions { profile: Some("logs".to_string()), region: Some("ap-south-1".to_string()), ..Default::default() };
+
+    let region = region_provider(&options, profile_files()).region().await;
+
+    assert_eq!(region, Some(Region::new("ap-south-1")));
+  }
+}
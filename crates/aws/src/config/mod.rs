@@ -1,10 +1,23 @@
-use std::path::PathBuf;
+use std::{env, path::PathBuf};
 
 use thiserror::Error;
 
 const AWS_CONFIG_FILE: &str = ".aws/config";
+const AWS_CREDENTIALS_FILE: &str = ".aws/credentials";
+const AWS_CONFIG_FILE_ENV: &str = "AWS_CONFIG_FILE";
+const AWS_CREDENTIALS_FILE_ENV: &str = "AWS_SHARED_CREDENTIALS_FILE";
 
+/// Path of the shared config file, `AWS_CONFIG_FILE` or `~/.aws/config`
 pub fn get_config_file_path () -> Result<PathBuf, GetAwsConfigFilePathError> {
+  file_path(AWS_CONFIG_FILE_ENV, AWS_CONFIG_FILE)
+}
+
+/// Path of the shared credentials file, `AWS_SHARED_CREDENTIALS_FILE` or `~/.aws/credentials`
+pub fn get_credentials_file_path () -> Result<PathBuf, GetAwsConfigFilePathError> {
+  file_path(AWS_CREDENTIALS_FILE_ENV, AWS_CREDENTIALS_FILE)
+}
+
+fn file_path (env_var: &str, default_suffix: &str) -> Result<PathBuf, GetAwsConfigFilePathError> {
   let home_dir = {
     let option = dirs::home_dir();
     match option {
@@ -12,7 +25,15 @@ pub fn get_config_file_path () -> Result<PathBuf, GetAwsConfigFilePathError> {
       None => return Err(GetAwsConfigFilePathError::HomeDirNotFound)
     }
   };
-  let path = home_dir.join(AWS_CONFIG_FILE);
+
+  let path = match env::var(env_var) {
+    Ok(path) if path == "~" => home_dir,
+    Ok(path) => match path.strip_prefix("~/") {
+      Some(relative) => home_dir.join(relative),
+      None => PathBuf::from(path),
+    },
+    Err(_) => home_dir.join(default_suffix),
+  };
 
   Ok(path)
 }
@@ -21,4 +42,4 @@ pub fn get_config_file_path () -> Result<PathBuf, GetAwsConfigFilePathError> {
 pub enum GetAwsConfigFilePathError {
   #[error("Home directory not found")]
   HomeDirNotFound,
-}
\ No newline at end of file
+}
@@ -0,0 +1,49 @@
+use std::error::Error as StdError;
+
+use aws_sdk_sts::{error::{ProvideErrorMetadata, SdkError}, operation::get_caller_identity::GetCallerIdentityError};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum IdentityError {
+  #[error("No credentials found: {0}")]
+  NoCredentials(String),
+  #[error("Failed to resolve credentials: {0}")]
+  CredentialsError(String),
+  #[error("Credentials expired")]
+  Expired,
+  #[error("GetCallerIdentity failed: {}", error_chain(.0.as_ref()))]
+  CallerIdentity(Box<SdkError<GetCallerIdentityError>>),
+  #[error("Timed out resolving credentials")]
+  TimedOut,
+}
+
+impl IdentityError {
+  /// Error code STS answered with, e.g. `ExpiredToken` or `InvalidClientTokenId`
+  pub fn code(&self) -> Option<&str> {
+    match self {
+      IdentityError::CallerIdentity(e) => e.code(),
+      _ => None,
+    }
+  }
+
+  /// True when STS was never reached, so the credentials may be fine
+  pub fn is_network_error(&self) -> bool {
+    matches!(self, IdentityError::CallerIdentity(e) if matches!(**e, SdkError::DispatchFailure(_) | SdkError::TimeoutError(_)))
+  }
+}
+
+/// An error's message followed by those of its sources, skipping repeats, as the SDK nests
+/// providers that each report the same failure
+pub fn error_chain(error: &dyn StdError) -> String {
+  let mut messages: Vec<String> = Vec::new();
+  let mut next = Some(error);
+  while let Some(error) = next {
+    let message = error.to_string();
+    if messages.last() != Some(&message) {
+      messages.push(message);
+    }
+    next = error.source();
+  }
+
+  messages.join(": ")
+}
@@ -0,0 +1,186 @@
+use std::time::{Duration, SystemTime};
+
+use aws_sdk_sts::{config::{Builder, ProvideCredentials}, Client};
+use aws_credential_types::provider::error::CredentialsError;
+use tokio::time::timeout;
+
+use crate::{client::{load_sdk_config, ClientOptions}, profiles::profile::{AwsProfiles, CredentialKind}};
+
+pub mod errors;
+
+use errors::{error_chain, IdentityError};
+
+/// Longest a check waits on a credential provider and STS together
+const CHECK_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Who a set of credentials belongs to
+#[derive(Debug, Clone)]
+pub struct CallerIdentity {
+  pub account: Option<String>,
+  pub arn: Option<String>,
+  pub user_id: Option<String>,
+  /// When temporary credentials stop working, `None` for long-term access keys
+  pub expires_at: Option<SystemTime>,
+}
+
+/// Resolve credentials the way the S3 client would, then ask STS who they belong to
+pub async fn check_identity(options: &ClientOptions) -> Result<CallerIdentity, IdentityError> {
+  timeout(CHECK_TIMEOUT, get_caller_identity(options)).await.map_err(|_| IdentityError::TimedOut)?
+}
+
+async fn get_caller_identity(options: &ClientOptions) -> Result<CallerIdentity, IdentityError> {
+  let config = load_sdk_config(options).await;
+  let provider = config.credentials_provider()
+    .ok_or_else(|| IdentityError::NoCredentials("no credentials provider configured".to_string()))?;
+
+  let credentials = provider.provide_credentials().await.map_err(|e| match e {
+    CredentialsError::CredentialsNotLoaded(_) => IdentityError::NoCredentials(error_chain(&e)),
+    CredentialsError::ProviderTimedOut(_) => IdentityError::TimedOut,
+    _ => IdentityError::CredentialsError(error_chain(&e)),
+  })?;
+  let expires_at = credentials.expiry();
+  if expires_at.is_some_and(|expiry| expiry <= SystemTime::now()) {
+    return Err(IdentityError::Expired);
+  }
+
+  // sign with the credentials already resolved rather than resolving them a second time
+  let sts_config = Builder::from(&config).credentials_provider(credentials).build();
+  let res = Client::from_conf(sts_config)
+    .get_caller_identity()
+    .send()
+    .await
+    .map_err(|e| IdentityError::CallerIdentity(Box::new(e)))?;
+
+  Ok(CallerIdentity { account: res.account, arn: res.arn, user_id: res.user_id, expires_at })
+}
+
+/// What to do about a failed check of a profile, or of the default credential chain without one
+pub fn remediation(error: &IdentityError, profile: Option<&str>, profiles: &AwsProfiles) -> String {
+  if error.is_network_error() {
+    return "Check your network connection and proxy settings, STS couldn't be reached".to_string();
+  }
+
+  let name = profile.unwrap_or("default");
+  let chain = match profiles.source_chain(name) {
+    Ok(chain) => chain,
+    Err(_) if profile.is_some() => return format!("Add [profile {}] to the AWS config file, e.g. with `aws configure sso --profile {}`", name, name),
+    Err(_) => return "Set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, or choose a profile with `dab-s3-logs config select-aws-profile`".to_string(),
+  };
+  let target = chain[0];
+  // the end of the chain holds the credentials every role in it is assumed with
+  let base = chain[chain.len() - 1];
+
+  if matches!(error, IdentityError::CallerIdentity(_)) && error.code() == Some("AccessDenied") && chain.len() > 1 {
+    return format!("Allow {} to assume {}", base.name, target.role_arn().unwrap_or("the role"));
+  }
+
+  match base.credential_kind() {
+    CredentialKind::Sso => format!("Run `aws sso login --profile {}`", base.name),
+    CredentialKind::Static if error.code() == Some("ExpiredToken") || matches!(error, IdentityError::Expired) =>
+      format!("Replace the expired session credentials of {} in the AWS credentials file", base.name),
+    CredentialKind::Static => format!("Replace the access keys of {}, e.g. with `aws configure --profile {}`", base.name, base.name),
+    CredentialKind::Process => format!("Check the credential_process of {} runs: {}", base.name, base.get("credential_process").unwrap_or_default()),
+    CredentialKind::WebIdentity => format!("Check the web_identity_token_file of {} exists and hasn't expired", base.name),
+    CredentialKind::AssumeRole => format!("Check the {} credentials of {} are available", base.credential_source().unwrap_or("source"), base.name),
+    CredentialKind::None => format!("Set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, or run `aws configure --profile {}`", base.name),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use aws_sdk_sts::{error::{ErrorMetadata, SdkError}, operation::get_caller_identity::GetCallerIdentityError};
+  use aws_smithy_runtime_api::http::{Response, StatusCode};
+  use aws_smithy_types::body::SdkBody;
+
+  use super::*;
+  use crate::profiles::parser::parse;
+
+  const CONFIG: &str = "
+[profile reader]
+role_arn = arn:aws:iam::123456789012:role/reader
+source_profile = keys
+
+[profile sso-dev]
+sso_session = corp
+sso_account_id = 123456789012
+sso_role_name = Developer
+
+[sso-session corp]
+sso_start_url = https://corp.awsapps.com/start
+sso_region = us-east-1
+
+[profile empty]
+region = us-east-1
+";
+
+  const CREDENTIALS: &str = "
+[keys]
+aws_access_key_id = AKIDEXAMPLE
+aws_secret_access_key = secret
+";
+
+  fn profiles() -> AwsProfiles {
+    let config = parse(CONFIG, Path::new("config")).unwrap();
+    let credentials = parse(CREDENTIALS, Path::new("credentials")).unwrap();
+
+    AwsProfiles::from_sections(config, credentials)
+  }
+
+  fn sts_error(code: &str) -> IdentityError {
+    let error = GetCallerIdentityError::generic(ErrorMetadata::builder().code(code).build());
+    let response = Response::new(StatusCode::try_from(403).unwrap(), SdkBody::empty());
+    IdentityError::CallerIdentity(Box::new(SdkError::service_error(error, response)))
+  }
+
+  #[test]
+  fn blames_the_network_when_sts_is_unreachable() {
+    let error = IdentityError::CallerIdentity(Box::new(SdkError::timeout_error("timed out")));
+
+    assert!(remediation(&error, Some("keys"), &profiles()).starts_with("Check your network connection"));
+  }
+
+  #[test]
+  fn suggests_adding_a_missing_profile() {
+    let fix = remediation(&IdentityError::Expired, Some("missing"), &profiles());
+
+    assert_eq!(fix, "Add [profile missing] to the AWS config file, e.g. with `aws configure sso --profile missing`");
+  }
+
+  #[test]
+  fn suggests_environment_credentials_without_any_profile() {
+    let fix = remediation(&IdentityError::NoCredentials("none".to_string()), None, &AwsProfiles::default());
+
+    assert!(fix.starts_with("Set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"));
+  }
+
+  #[test]
+  fn suggests_sso_login_for_sso_profiles() {
+    assert_eq!(remediation(&IdentityError::Expired, Some("sso-dev"), &profiles()), "Run `aws sso login --profile sso-dev`");
+  }
+
+  #[test]
+  fn fixes_the_source_of_an_assumed_role() {
+    let profiles = profiles();
+
+    assert_eq!(remediation(&sts_error("AccessDenied"), Some("reader"), &profiles), "Allow keys to assume arn:aws:iam::123456789012:role/reader");
+    assert_eq!(remediation(&sts_error("InvalidClientTokenId"), Some("reader"), &profiles), "Replace the access keys of keys, e.g. with `aws configure --profile keys`");
+  }
+
+  #[test]
+  fn tells_expired_session_keys_from_bad_keys() {
+    let profiles = profiles();
+
+    assert_eq!(remediation(&sts_error("ExpiredToken"), Some("keys"), &profiles), "Replace the expired session credentials of keys in the AWS credentials file");
+    assert_eq!(remediation(&IdentityError::Expired, Some("keys"), &profiles), "Replace the expired session credentials of keys in the AWS credentials file");
+    assert_eq!(remediation(&sts_error("InvalidClientTokenId"), Some("keys"), &profiles), "Replace the access keys of keys, e.g. with `aws configure --profile keys`");
+  }
+
+  #[test]
+  fn suggests_configuring_a_profile_without_credentials() {
+    let fix = remediation(&IdentityError::NoCredentials("none".to_string()), Some("empty"), &profiles());
+
+    assert_eq!(fix, "Set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, or run `aws configure --profile empty`");
+  }
+}
@@ -1,4 +1,5 @@
 pub mod client;
+pub mod identity;
 pub mod profiles;
 pub mod s3;
 pub mod config;
@@ -1,10 +1,18 @@
+use std::{io::Error as IoError, path::PathBuf};
+
 use thiserror::Error;
 use crate::config;
 
 #[derive(Error, Debug)]
 pub enum GetProfilesError {
-  #[error("Config file parse error: {0}")]
-  FileError(#[from] ini::Error),
+  #[error("Failed to read {0}: {1}")]
+  ReadError(PathBuf, IoError),
+  #[error("{path}:{line}: {message}")]
+  ParseError { path: PathBuf, line: usize, message: String },
   #[error("Home directory not found")]
   HomeDirNotFound(#[from] config::GetAwsConfigFilePathError),
-}
\ No newline at end of file
+  #[error("Profile {0} not found")]
+  UnknownProfile(String),
+  #[error("Profile {0} is its own source profile")]
+  SourceProfileLoop(String),
+}
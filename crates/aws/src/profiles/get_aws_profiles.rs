@@ -1,27 +1,28 @@
-use std::path::PathBuf;
+use std::{fs, io::ErrorKind, path::Path};
 
-use ini::Ini;
+use crate::profiles::{errors, parser::{self, Section}, profile::AwsProfiles};
 
-use crate::profiles::errors;
-
-use crate::config::get_config_file_path;
+use crate::config::{get_config_file_path, get_credentials_file_path};
 
+/// Names of every profile in the shared config and credentials files
 pub fn get_aws_profiles()  -> Result<Vec<String>, errors::GetProfilesError> {
-  let aws_config_path = get_config_file_path()?;
-  let conf = Ini::load_from_file::<PathBuf>(aws_config_path)?;
-  let profiles = parse_profiles_from_ini(conf);
+  let profiles = load_aws_profiles()?;
+
+  Ok(profiles.names())
+}
+
+/// Read the shared config and credentials files, either of which may be missing
+pub fn load_aws_profiles() -> Result<AwsProfiles, errors::GetProfilesError> {
+  let config = read_sections(&get_config_file_path()?)?;
+  let credentials = read_sections(&get_credentials_file_path()?)?;
 
-  Ok(profiles)
+  Ok(AwsProfiles::from_sections(config, credentials))
 }
 
-fn parse_profiles_from_ini(ini: Ini) -> Vec<String> {
-  ini.iter()
-    .filter_map(
-      |(sec, _props)| {
-        sec.map(|section| section.to_string()
-            .replace("profile", "")
-            .trim()
-            .to_string())        
-      }
-    ).collect()
+fn read_sections(path: &Path) -> Result<Vec<Section>, errors::GetProfilesError> {
+  match fs::read_to_string(path) {
+    Ok(source) => parser::parse(&source, path),
+    Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+    Err(e) => Err(errors::GetProfilesError::ReadError(path.to_path_buf(), e)),
+  }
 }
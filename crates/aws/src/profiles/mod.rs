@@ -1,2 +1,4 @@
 pub mod errors;
-pub mod get_aws_profiles;
\ No newline at end of file
+pub mod get_aws_profiles;
+pub mod parser;
+pub mod profile;
@@ -0,0 +1,139 @@
+use std::{collections::BTreeMap, path::Path};
+
+use super::errors::GetProfilesError;
+
+/// A value in a shared config or credentials file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Property {
+  Value(String),
+  /// Indented `key = value` lines beneath a property with no value, as in `services` sections
+  SubProperties(BTreeMap<String, String>),
+}
+
+/// A `[header]` and the properties beneath it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+  pub header: String,
+  pub properties: BTreeMap<String, Property>,
+}
+
+/// Parse the INI dialect of `~/.aws/config` and `~/.aws/credentials`.
+///
+/// Lines starting with `#` or `;` are comments, as is anything after ` #` or ` ;` in a value.
+/// An indented line continues the property above it: a `key = value` pair when that property has
+/// no value of its own, or another line of its value otherwise.
+pub fn parse(source: &str, path: &Path) -> Result<Vec<Section>, GetProfilesError> {
+  let error = |line: usize, message: &str| GetProfilesError::ParseError { path: path.to_path_buf(), line: line + 1, message: message.to_string() };
+
+  let mut sections: Vec<Section> = Vec::new();
+  let mut last_key: Option<String> = None;
+  for (number, line) in source.lines().enumerate() {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+      continue;
+    }
+
+    if trimmed.starts_with('[') {
+      let end = trimmed.find(']').ok_or_else(|| error(number, "section header is missing `]`"))?;
+      sections.push(Section { header: trimmed[1..end].trim().to_string(), properties: BTreeMap::new() });
+      last_key = None;
+      continue;
+    }
+
+    let section = sections.last_mut().ok_or_else(|| error(number, "property outside of a section"))?;
+
+    if line.starts_with(char::is_whitespace) {
+      let key = last_key.as_ref().ok_or_else(|| error(number, "indented line without a property above it"))?;
+      match section.properties.get_mut(key) {
+        Some(Property::SubProperties(properties)) => {
+          let (key, value) = split_property(trimmed).ok_or_else(|| error(number, "expected `key = value`"))?;
+          properties.insert(key, value);
+        }
+        Some(Property::Value(value)) => {
+          value.push('\n');
+          value.push_str(strip_comment(trimmed));
+        }
+        None => return Err(error(number, "indented line without a property above it")),
+      }
+      continue;
+    }
+
+    let (key, value) = split_property(trimmed).ok_or_else(|| error(number, "expected `key = value`"))?;
+    let property = if value.is_empty() { Property::SubProperties(BTreeMap::new()) } else { Property::Value(value) };
+    section.properties.insert(key.clone(), property);
+    last_key = Some(key);
+  }
+
+  Ok(sections)
+}
+
+fn split_property(line: &str) -> Option<(String, String)> {
+  let (key, value) = line.split_once('=')?;
+  let key = key.trim();
+  if key.is_empty() {
+    return None;
+  }
+
+  Some((key.to_lowercase(), strip_comment(value.trim()).to_string()))
+}
+
+fn strip_comment(value: &str) -> &str {
+  let end = [" #", " ;", "\t#", "\t;"].iter()
+    .filter_map(|marker| value.find(marker))
+    .min()
+    .unwrap_or(value.len());
+
+  value[..end].trim_end()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse_str(source: &str) -> Vec<Section> {
+    parse(source, Path::new("config")).unwrap()
+  }
+
+  fn value(section: &Section, key: &str) -> Option<String> {
+    match section.properties.get(key) {
+      Some(Property::Value(value)) => Some(value.clone()),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn parses_sections_and_properties() {
+    let sections = parse_str("# comment\n[default]\nregion = ap-southeast-2 # sydney\n\n[profile dev]\n; comment\nRole_Arn=arn:aws:iam::123:role/dev\n");
+
+    assert_eq!(sections.len(), 2);
+    assert_eq!(sections[0].header, "default");
+    assert_eq!(value(&sections[0], "region").as_deref(), Some("ap-southeast-2"));
+    assert_eq!(sections[1].header, "profile dev");
+    assert_eq!(value(&sections[1], "role_arn").as_deref(), Some("arn:aws:iam::123:role/dev"));
+  }
+
+  #[test]
+  fn nests_indented_properties_under_an_empty_value() {
+    let sections = parse_str("[services local]\ns3 =\n  endpoint_url = http://localhost:9000\n  addressing_style = path\n");
+
+    let expected = BTreeMap::from([
+      ("addressing_style".to_string(), "path".to_string()),
+      ("endpoint_url".to_string(), "http://localhost:9000".to_string()),
+    ]);
+    assert_eq!(sections[0].properties.get("s3"), Some(&Property::SubProperties(expected)));
+  }
+
+  #[test]
+  fn continues_a_value_on_indented_lines() {
+    let sections = parse_str("[default]\nsso_registration_scopes = a,\n  b\n");
+
+    assert_eq!(value(&sections[0], "sso_registration_scopes").as_deref(), Some("a,\nb"));
+  }
+
+  #[test]
+  fn rejects_a_property_outside_of_a_section() {
+    let result = parse("region = ap-southeast-2\n", Path::new("config"));
+
+    assert!(matches!(result, Err(GetProfilesError::ParseError { line: 1, .. })));
+  }
+}
@@ -0,0 +1,345 @@
+use std::{collections::BTreeMap, fmt};
+
+use log::warn;
+
+use super::{errors::GetProfilesError, parser::{Property, Section}};
+
+/// A named profile, merged from the config and credentials files
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+  pub name: String,
+  properties: BTreeMap<String, String>,
+}
+
+/// Where a profile gets its credentials from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CredentialKind {
+  /// Access keys in the profile itself
+  Static,
+  /// A role assumed with the credentials of a source profile or `credential_source`
+  AssumeRole,
+  /// IAM Identity Center, through an `sso-session` or the legacy `sso_start_url`
+  Sso,
+  /// An external `credential_process`
+  Process,
+  /// A role assumed with a web identity token file
+  WebIdentity,
+  /// Nothing in the profile, so the rest of the default chain supplies them
+  None,
+}
+
+impl fmt::Display for CredentialKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CredentialKind::Static => write!(f, "access keys"),
+      CredentialKind::AssumeRole => write!(f, "assume role"),
+      CredentialKind::Sso => write!(f, "sso"),
+      CredentialKind::Process => write!(f, "credential process"),
+      CredentialKind::WebIdentity => write!(f, "web identity"),
+      CredentialKind::None => write!(f, "default chain"),
+    }
+  }
+}
+
+impl Profile {
+  pub fn get(&self, key: &str) -> Option<&str> {
+    self.properties.get(key).map(String::as_str)
+  }
+
+  pub fn region(&self) -> Option<&str> {
+    self.get("region")
+  }
+
+  pub fn role_arn(&self) -> Option<&str> {
+    self.get("role_arn")
+  }
+
+  pub fn source_profile(&self) -> Option<&str> {
+    self.get("source_profile")
+  }
+
+  pub fn credential_source(&self) -> Option<&str> {
+    self.get("credential_source")
+  }
+
+  /// Name of the `sso-session` section holding the SSO start URL and region
+  pub fn sso_session(&self) -> Option<&str> {
+    self.get("sso_session")
+  }
+
+  /// Start URL of a legacy SSO profile, which doesn't use an `sso-session`
+  pub fn sso_start_url(&self) -> Option<&str> {
+    self.get("sso_start_url")
+  }
+
+  pub fn sso_region(&self) -> Option<&str> {
+    self.get("sso_region")
+  }
+
+  pub fn sso_account_id(&self) -> Option<&str> {
+    self.get("sso_account_id")
+  }
+
+  pub fn sso_role_name(&self) -> Option<&str> {
+    self.get("sso_role_name")
+  }
+
+  /// Name of the `services` section with endpoint overrides for this profile
+  pub fn services(&self) -> Option<&str> {
+    self.get("services")
+  }
+
+  pub fn credential_kind(&self) -> CredentialKind {
+    if self.role_arn().is_some() && self.get("web_identity_token_file").is_some() {
+      CredentialKind::WebIdentity
+    } else if self.role_arn().is_some() {
+      CredentialKind::AssumeRole
+    } else if self.sso_session().is_some() || self.sso_start_url().is_some() {
+      CredentialKind::Sso
+    } else if self.get("credential_process").is_some() {
+      CredentialKind::Process
+    } else if self.get("aws_access_key_id").is_some() {
+      CredentialKind::Static
+    } else {
+      CredentialKind::None
+    }
+  }
+}
+
+/// An `[sso-session name]` section, shared by the SSO profiles that name it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SsoSession {
+  pub name: String,
+  properties: BTreeMap<String, String>,
+}
+
+impl SsoSession {
+  pub fn start_url(&self) -> Option<&str> {
+    self.properties.get("sso_start_url").map(String::as_str)
+  }
+
+  pub fn region(&self) -> Option<&str> {
+    self.properties.get("sso_region").map(String::as_str)
+  }
+
+  pub fn registration_scopes(&self) -> Vec<&str> {
+    self.properties.get("sso_registration_scopes")
+      .map(|scopes| scopes.split([',', '\n']).map(str::trim).filter(|scope| !scope.is_empty()).collect())
+      .unwrap_or_default()
+  }
+}
+
+/// A `[services name]` section, overriding settings such as `endpoint_url` per service
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Services {
+  pub name: String,
+  /// Settings keyed by service, e.g. `s3`
+  pub services: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Services {
+  pub fn endpoint_url(&self, service: &str) -> Option<&str> {
+    self.services.get(service)?.get("endpoint_url").map(String::as_str)
+  }
+}
+
+/// Everything in the shared config and credentials files
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AwsProfiles {
+  pub profiles: BTreeMap<String, Profile>,
+  pub sso_sessions: BTreeMap<String, SsoSession>,
+  pub services: BTreeMap<String, Services>,
+}
+
+impl AwsProfiles {
+  /// Merge the sections of both files. Profiles in the config file are headed `[profile name]`,
+  /// apart from `[default]`, while the credentials file uses bare names. Where both files set a
+  /// property, the credentials file wins
+  pub fn from_sections(config: Vec<Section>, credentials: Vec<Section>) -> Self {
+    let mut profiles = Self::default();
+
+    for section in config {
+      let (kind, name) = match section.header.split_once(char::is_whitespace) {
+        Some((kind, name)) => (kind, name.trim()),
+        None => (section.header.as_str(), ""),
+      };
+      match (kind, name) {
+        ("default", "") => merge(&mut profiles.profile("default").properties, section.properties),
+        ("profile", name) if !name.is_empty() => merge(&mut profiles.profile(name).properties, section.properties),
+        ("sso-session", name) if !name.is_empty() => {
+          let session = profiles.sso_sessions.entry(name.to_string()).or_insert_with(|| SsoSession { name: name.to_string(), ..Default::default() });
+          merge(&mut session.properties, section.properties);
+        }
+        ("services", name) if !name.is_empty() => {
+          let services = profiles.services.entry(name.to_string()).or_insert_with(|| Services { name: name.to_string(), ..Default::default() });
+          for (service, property) in section.properties {
+            if let Property::SubProperties(settings) = property {
+              services.services.entry(service).or_default().extend(settings);
+            }
+          }
+        }
+        _ => warn!("Ignoring [{}] in the AWS config file, profiles there are headed [profile <name>]", section.header),
+      }
+    }
+
+    for section in credentials {
+      let name = section.header.clone();
+      merge(&mut profiles.profile(&name).properties, section.properties);
+    }
+
+    profiles
+  }
+
+  /// Profile names in alphabetical order
+  pub fn names(&self) -> Vec<String> {
+    self.profiles.keys().cloned().collect()
+  }
+
+  pub fn get(&self, name: &str) -> Result<&Profile, GetProfilesError> {
+    self.profiles.get(name).ok_or_else(|| GetProfilesError::UnknownProfile(name.to_string()))
+  }
+
+  /// The `sso-session` a profile refers to
+  pub fn sso_session(&self, profile: &Profile) -> Option<&SsoSession> {
+    self.sso_sessions.get(profile.sso_session()?)
+  }
+
+  /// The `services` section a profile refers to
+  pub fn services_for(&self, profile: &Profile) -> Option<&Services> {
+    self.services.get(profile.services()?)
+  }
+
+  /// The profile followed by each `source_profile` it assumes a role from, in order
+  pub fn source_chain(&self, name: &str) -> Result<Vec<&Profile>, GetProfilesError> {
+    let mut chain = vec![self.get(name)?];
+    while let Some(source) = chain.last().and_then(|profile| profile.source_profile()) {
+      let profile = self.get(source)?;
+      // a profile may be its own source when it holds the keys used to assume its role
+      if profile.name == chain.last().unwrap().name && profile.get("aws_access_key_id").is_some() {
+        break;
+      }
+      if chain.iter().any(|seen| seen.name == profile.name) {
+        return Err(GetProfilesError::SourceProfileLoop(name.to_string()));
+      }
+      chain.push(profile);
+    }
+
+    Ok(chain)
+  }
+
+  fn profile(&mut self, name: &str) -> &mut Profile {
+    self.profiles.entry(name.to_string()).or_insert_with(|| Profile { name: name.to_string(), ..Default::default() })
+  }
+}
+
+/// Copy values over, flattening sub-properties into `parent.key`
+fn merge(into: &mut BTreeMap<String, String>, properties: BTreeMap<String, Property>) {
+  for (key, property) in properties {
+    match property {
+      Property::Value(value) => {
+        into.insert(key, value);
+      }
+      Property::SubProperties(settings) => {
+        for (sub_key, value) in settings {
+          into.insert(format!("{}.{}", key, sub_key), value);
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use super::*;
+  use crate::profiles::parser::parse;
+
+  const CONFIG: &str = "
+[default]
+region = ap-southeast-2
+
+[profile profile-reader]
+role_arn = arn:aws:iam::123456789012:role/reader
+source_profile = default
+
+[profile sso-dev]
+sso_session = corp
+sso_account_id = 123456789012
+sso_role_name = Developer
+services = local
+
+[sso-session corp]
+sso_start_url = https://corp.awsapps.com/start
+sso_region = us-east-1
+
+[services local]
+s3 =
+  endpoint_url = http://localhost:9000
+
+[stray]
+region = us-west-2
+";
+
+  const CREDENTIALS: &str = "
+[default]
+aws_access_key_id = AKIDEXAMPLE
+aws_secret_access_key = secret
+region = us-east-1
+
+[ci]
+aws_access_key_id = AKIDCI
+aws_secret_access_key = secret
+";
+
+  fn profiles() -> AwsProfiles {
+    let config = parse(CONFIG, Path::new("config")).unwrap();
+    let credentials = parse(CREDENTIALS, Path::new("credentials")).unwrap();
+
+    AwsProfiles::from_sections(config, credentials)
+  }
+
+  #[test]
+  fn tells_profiles_apart_from_other_sections() {
+    let profiles = profiles();
+
+    assert_eq!(profiles.names(), ["ci", "default", "profile-reader", "sso-dev"]);
+    assert_eq!(profiles.sso_sessions.keys().collect::<Vec<_>>(), ["corp"]);
+    assert_eq!(profiles.services.keys().collect::<Vec<_>>(), ["local"]);
+  }
+
+  #[test]
+  fn prefers_the_credentials_file_over_the_config_file() {
+    let default = profiles().get("default").unwrap().clone();
+
+    assert_eq!(default.region(), Some("us-east-1"));
+    assert_eq!(default.credential_kind(), CredentialKind::Static);
+  }
+
+  #[test]
+  fn resolves_sso_sessions_and_services() {
+    let profiles = profiles();
+    let profile = profiles.get("sso-dev").unwrap();
+
+    assert_eq!(profile.credential_kind(), CredentialKind::Sso);
+    assert_eq!(profiles.sso_session(profile).and_then(SsoSession::start_url), Some("https://corp.awsapps.com/start"));
+    assert_eq!(profiles.services_for(profile).and_then(|services| services.endpoint_url("s3")), Some("http://localhost:9000"));
+  }
+
+  #[test]
+  fn follows_source_profiles() {
+    let profiles = profiles();
+
+    let chain: Vec<_> = profiles.source_chain("profile-reader").unwrap().iter().map(|profile| profile.name.as_str()).collect();
+    assert_eq!(chain, ["profile-reader", "default"]);
+    assert_eq!(profiles.get("profile-reader").unwrap().role_arn(), Some("arn:aws:iam::123456789012:role/reader"));
+  }
+
+  #[test]
+  fn rejects_a_source_profile_loop() {
+    let config = parse("[profile a]\nrole_arn = x\nsource_profile = b\n[profile b]\nrole_arn = y\nsource_profile = a\n", Path::new("config")).unwrap();
+    let profiles = AwsProfiles::from_sections(config, Vec::new());
+
+    assert!(matches!(profiles.source_chain("a"), Err(GetProfilesError::SourceProfileLoop(_))));
+  }
+}
@@ -1,44 +1,56 @@
-use aws_sdk_s3::{types::Bucket, Client};
+use aws_sdk_s3::{primitives::{DateTime, DateTimeFormat}, types::Bucket};
 use serde::{Deserialize, Serialize};
-use std::{fs::{create_dir_all, File}, io::Write, path::PathBuf};
+use std::{fs::{create_dir_all, File}, io::Write, path::{Path, PathBuf}, time::SystemTime};
 use toml;
 
-use super::errors::BucketsError;
+use super::{errors::BucketsError, store::ObjectStore};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct BucketDto {
   pub name: String,
-  pub creation_date: String,
+  /// Missing for S3 compatible stores that don't report it
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub creation_date: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct BucketsContainer {
   pub profile_name: String,
+  /// Unix time the buckets were listed
+  #[serde(default)]
+  pub refreshed_at: i64,
   pub buckets: Vec<BucketDto>,
 }
 
-fn bucket_to_dto(bucket: &Bucket) -> BucketDto {
-  BucketDto {
-    name: bucket.name.clone().unwrap(),
-    creation_date: bucket.creation_date.unwrap().to_string(),
-  }
+/// `None` for a bucket without a name, which there's no way to use
+fn bucket_to_dto(bucket: &Bucket) -> Option<BucketDto> {
+  Some(BucketDto {
+    name: bucket.name.clone()?,
+    creation_date: bucket.creation_date.map(|date| date.to_string()),
+  })
+}
+
+pub fn dto_to_bucket(dto: &BucketDto) -> Bucket {
+  Bucket::builder()
+    .name(&dto.name)
+    .set_creation_date(dto.creation_date.as_deref().and_then(|date| DateTime::from_str(date, DateTimeFormat::DateTime).ok()))
+    .build()
 }
 
 const BUCKETS_DIRECTORY: &str = "buckets";
 
 pub fn save_buckets_to_file(buckets: &[Bucket], profile: &str, data_directory: PathBuf) -> Result<(), BucketsError> {
-  let bucket_dtos = buckets.iter().map(|bucket| {
-    bucket_to_dto(bucket)
-  }).collect::<Vec<BucketDto>>();
+  let bucket_dtos = buckets.iter().filter_map(bucket_to_dto).collect::<Vec<BucketDto>>();
 
+  let refreshed_at = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|since| since.as_secs() as i64).unwrap_or(0);
   let profile_with_buckets = BucketsContainer {
     profile_name: profile.to_string(),
+    refreshed_at,
     buckets: bucket_dtos,
   };
 
-  let toml = toml::to_string(&profile_with_buckets).unwrap();
+  let toml = toml::to_string(&profile_with_buckets).map_err(BucketsError::SerializeBucketsError)?;
 
-  let filename = format!("{}.toml", profile);
   let buckets_data_dir = data_directory.join(BUCKETS_DIRECTORY);
 
   if !buckets_data_dir.exists() {
@@ -51,7 +63,7 @@ pub fn save_buckets_to_file(buckets: &[Bucket], profile: &str, data_directory: P
     }
   }
 
-  let file_path = buckets_data_dir.join(filename);
+  let file_path = buckets_file_path(profile, &data_directory);
   let result = File::create(file_path);
   match result {
     Ok(mut file) => {
@@ -71,37 +83,55 @@ pub fn save_buckets_to_file(buckets: &[Bucket], profile: &str, data_directory: P
   Ok(())
 }
 
-pub async fn get_buckets(client: &Client) -> Result<Vec<Bucket>, BucketsError> {
-  let req = client.list_buckets();
-  let response_result = req.send().await;
-  match response_result {
-    Ok(response) => {
-      match response.buckets {
-        Some(buckets) => {
-          Ok(buckets)
-        },
-        None => Ok(Vec::new())
-      }
-    },
-    Err(e) => {
-      Err(BucketsError::ListBucketsError(e))
-    }
-  }
+pub async fn get_buckets<S: ObjectStore>(store: &S) -> Result<Vec<Bucket>, BucketsError> {
+  store.list_buckets().await.map_err(BucketsError::ListBucketsError)
 }
 
 pub fn get_buckets_from_file(profile: &str, data_directory: PathBuf) -> Result<Vec<BucketDto>, BucketsError> {
-  let filename = format!("{}.toml", profile);
-  let buckets_data_dir = data_directory.join(BUCKETS_DIRECTORY);
-  let file_path = buckets_data_dir.join(filename);
+  load_buckets_file(profile, &data_directory).map(|container| container.buckets)
+}
+
+/// The saved buckets of a profile along with when they were listed
+pub fn load_buckets_file(profile: &str, data_directory: &Path) -> Result<BucketsContainer, BucketsError> {
+  let file_path = buckets_file_path(profile, data_directory);
 
   let result = std::fs::read_to_string(file_path);
   match result {
     Ok(contents) => {
-      let buckets_container: BucketsContainer = toml::from_str(&contents).unwrap();
-      Ok(buckets_container.buckets)
+      let buckets_container: BucketsContainer = toml::from_str(&contents).map_err(BucketsError::ParseBucketsError)?;
+      Ok(buckets_container)
     },
     Err(e) => {
-      Err(BucketsError::SaveBucketsError(e))
+      Err(BucketsError::LoadBucketsError(e))
     }
   }
 }
+
+/// Where the buckets of a profile are saved, `<data_directory>/buckets/<profile>.toml`
+pub fn buckets_file_path(profile: &str, data_directory: &Path) -> PathBuf {
+  data_directory.join(BUCKETS_DIRECTORY).join(format!("{}.toml", profile))
+}
+
+#[cfg(test)]
+mod tests {
+  use tempfile::TempDir;
+
+  use super::*;
+
+  #[test]
+  fn saves_buckets_without_a_creation_date_and_skips_those_without_a_name() {
+    let dir = TempDir::new().unwrap();
+    let buckets = [
+      Bucket::builder().name("logs").creation_date(DateTime::from_secs(1_714_564_800)).build(),
+      Bucket::builder().name("minio").build(),
+      Bucket::builder().build(),
+    ];
+
+    save_buckets_to_file(&buckets, "default", dir.path().to_path_buf()).unwrap();
+    let saved = load_buckets_file("default", dir.path()).unwrap().buckets;
+
+    assert_eq!(saved.iter().map(|bucket| bucket.name.as_str()).collect::<Vec<_>>(), ["logs", "minio"]);
+    assert_eq!(dto_to_bucket(&saved[0]).creation_date, Some(DateTime::from_secs(1_714_564_800)));
+    assert_eq!(dto_to_bucket(&saved[1]).creation_date, None);
+  }
+}
@@ -0,0 +1,36 @@
+use std::{fs::File, io::{self, Read}, path::Path};
+
+use md5::{Digest, Md5};
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The MD5 digest an ETag stands for, if it is one. Objects uploaded in parts have ETags like
+/// `"<hex>-<parts>"` that aren't a digest of their content
+pub fn etag_md5(e_tag: &str) -> Option<String> {
+  let e_tag = e_tag.trim_matches('"');
+  if e_tag.len() == 32 && e_tag.chars().all(|c| c.is_ascii_hexdigit()) {
+    Some(e_tag.to_ascii_lowercase())
+  } else {
+    None
+  }
+}
+
+/// Hex encoded MD5 of a file's content
+pub fn file_md5(path: &Path) -> io::Result<String> {
+  let mut file = File::open(path)?;
+  let mut hasher = Md5::new();
+  let mut buffer = vec![0; READ_BUFFER_SIZE];
+  loop {
+    let read = file.read(&mut buffer)?;
+    if read == 0 {
+      break;
+    }
+    hasher.update(&buffer[..read]);
+  }
+
+  Ok(to_hex(&hasher.finalize()))
+}
+
+pub fn to_hex(digest: &[u8]) -> String {
+  digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,323 @@
+use std::{io::SeekFrom, path::{Path, PathBuf}, sync::Arc};
+
+use aws_sdk_s3::{primitives::ByteStream, types::Object};
+use log::debug;
+use md5::{Digest, Md5};
+use tokio::{fs::{self, OpenOptions}, io::{AsyncSeekExt, AsyncWriteExt}, sync::Semaphore, task::{self, JoinSet}};
+
+use super::{checksum::{etag_md5, file_md5, to_hex}, errors::DownloadError, retry::RetryPolicy, store::{ByteRange, GetObject, ObjectBody, ObjectStore}};
+
+/// Downloads are written next to their final path under this suffix, hidden by a leading `.`
+pub const TEMP_FILE_SUFFIX: &str = ".part";
+
+/// How objects are downloaded. The application config holds the defaults
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadOptions {
+  pub retry: RetryPolicy,
+  /// Objects at least this big are downloaded as concurrent byte ranges
+  pub multipart_threshold: u64,
+  /// Size of each byte range
+  pub part_size: u64,
+  /// Byte ranges fetched at once for a single object
+  pub part_concurrency: usize,
+}
+
+/// Download an object into `dir`, split into concurrent byte ranges when it's large enough.
+///
+/// The object is written to a temporary file that's only renamed into place once it's complete and
+/// verified, so an interrupted download never leaves a truncated file behind. Every request is
+/// conditional on the listed ETag, so an object replaced mid download fails rather than mixing old
+/// and new bytes. The file is checked against the listed size, against S3's additional checksums
+/// when the object has them, and against the ETag when it's an MD5 of the content.
+///
+/// Additional checksums are only checked for objects downloaded whole. S3 doesn't return them for
+/// byte ranges, and those of multipart uploads cover each uploaded part rather than the object, so
+/// a ranged download relies on the size and, when the ETag is an MD5, the checksum of the whole file.
+/// Objects large enough to be ranged were usually uploaded in parts too, leaving only the size check.
+///
+/// Returns the hex MD5 of the file, or the error of the last attempt along with the number of attempts made.
+pub async fn download_object<S: ObjectStore>(store: &S, bucket: &str, object: &Object, dir: &Path, options: &DownloadOptions) -> Result<String, (DownloadError, u32)> {
+  let key = object.key.clone().unwrap_or_default();
+  let size = object.size.and_then(|size| u64::try_from(size).ok()).unwrap_or(0);
+  let e_tag = object.e_tag.clone();
+
+  let file_path = prepare_path(dir, &key).await.map_err(|e| (e, 1))?;
+  let temp_path = temp_path(&file_path);
+
+  let result = if size >= options.multipart_threshold && options.part_size > 0 {
+    // parts are retried on their own, so only a complete file that fails verification is fetched again
+    options.retry.run(|| download_ranged(store, bucket, &key, e_tag.clone(), size, &temp_path, options)).await
+      .map_err(|(e, attempts)| match e {
+        DownloadError::PartFailed { source, attempts } => (*source, attempts),
+        e => (e, attempts),
+      })
+  } else {
+    options.retry.run(|| download_single(store, bucket, &key, e_tag.clone(), size, &temp_path)).await
+  };
+
+  match result {
+    Ok(md5) => {
+      fs::rename(&temp_path, &file_path).await.map_err(|e| (e.into(), 1))?;
+      debug!("Downloaded file: {}", key);
+      Ok(md5)
+    }
+    Err(e) => {
+      let _ = fs::remove_file(&temp_path).await;
+      Err(e)
+    }
+  }
+}
+
+/// Start reading an object's body, retrying the request but not the body, which the caller consumes.
+/// The SDK validates the body against any additional checksum stored with the object as it's read
+pub async fn open_object<S: ObjectStore>(store: &S, bucket: &str, object: &Object, retry: &RetryPolicy) -> Result<ByteStream, (DownloadError, u32)> {
+  let key = object.key.clone().unwrap_or_default();
+
+  retry.run(|| async {
+    let request = GetObject::new(bucket, &key).if_match(object.e_tag.clone());
+    let res = store.get_object(request).await?;
+
+    Ok(res.body)
+  }).await
+}
+
+/// Where a download into `file_path` is written until it's complete
+pub fn temp_path(file_path: &Path) -> PathBuf {
+  let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
+  file_path.with_file_name(format!(".{}{}", file_name, TEMP_FILE_SUFFIX))
+}
+
+/// True for a download left incomplete by an interrupted fetch
+pub fn is_temp_file(path: &Path) -> bool {
+  path.file_name()
+    .and_then(|name| name.to_str())
+    .is_some_and(|name| name.starts_with('.') && name.ends_with(TEMP_FILE_SUFFIX))
+}
+
+async fn download_single<S: ObjectStore>(store: &S, bucket: &str, key: &str, e_tag: Option<String>, size: u64, temp_path: &Path) -> Result<String, DownloadError> {
+  let res = store.get_object(GetObject::new(bucket, key).if_match(e_tag)).await?;
+
+  let expected_md5 = expected_md5(&res);
+  let mut hasher = Md5::new();
+  let mut written = 0;
+
+  let mut body = res.body;
+  let mut file = fs::File::create(temp_path).await?;
+  while let Some(bytes) = body.try_next().await? {
+    file.write_all(&bytes).await?;
+    hasher.update(&bytes);
+    written += bytes.len() as u64;
+  }
+  file.sync_all().await?;
+
+  let md5 = to_hex(&hasher.finalize());
+  verify(key, size, written, expected_md5, &md5)?;
+
+  Ok(md5)
+}
+
+/// Fetch every part concurrently into a file preallocated to the full size, retrying parts on their own,
+/// then verify the complete file. A part that runs out of attempts fails with `PartFailed`
+async fn download_ranged<S: ObjectStore>(store: &S, bucket: &str, key: &str, e_tag: Option<String>, size: u64, temp_path: &Path, options: &DownloadOptions) -> Result<String, DownloadError> {
+  let file = fs::File::create(temp_path).await?;
+  file.set_len(size).await?;
+  drop(file);
+
+  let parts = size.div_ceil(options.part_size);
+  debug!("Downloading {} in {} parts of {} bytes", key, parts, options.part_size);
+
+  let semaphore = Arc::new(Semaphore::new(options.part_concurrency.max(1)));
+  let mut running = JoinSet::new();
+  let mut expected_md5 = None;
+  for part in 0..parts {
+    let start = part * options.part_size;
+    let end = (start + options.part_size).min(size) - 1;
+
+    let permit = semaphore.clone().acquire_owned().await.unwrap();
+    let store = store.clone();
+    let bucket = bucket.to_string();
+    let key = key.to_string();
+    let e_tag = e_tag.clone();
+    let temp_path = temp_path.to_path_buf();
+    let retry = options.retry;
+    running.spawn(async move {
+      let result = retry.run(|| download_part(&store, &bucket, &key, e_tag.clone(), &temp_path, ByteRange { start, end })).await;
+      drop(permit);
+      result
+    });
+
+    // stop handing out parts as soon as one has given up
+    while let Some(finished) = running.try_join_next() {
+      expected_md5 = part_result(finished)?.or(expected_md5);
+    }
+  }
+
+  while let Some(finished) = running.join_next().await {
+    expected_md5 = part_result(finished)?.or(expected_md5);
+  }
+
+  // parts arrive out of order, so the file is hashed once it's complete
+  let hashed_path = temp_path.to_path_buf();
+  let md5 = task::spawn_blocking(move || file_md5(&hashed_path))
+    .await
+    .map_err(|e| DownloadError::WriteError(e.into()))??;
+  let written = fs::metadata(temp_path).await?.len();
+  verify(key, size, written, expected_md5, &md5)?;
+
+  Ok(md5)
+}
+
+fn part_result(finished: Result<Result<Option<String>, (DownloadError, u32)>, task::JoinError>) -> Result<Option<String>, DownloadError> {
+  match finished {
+    Ok(Ok(expected_md5)) => Ok(expected_md5),
+    Ok(Err((e, attempts))) => Err(DownloadError::PartFailed { source: Box::new(e), attempts }),
+    Err(e) => Err(DownloadError::WriteError(e.into())),
+  }
+}
+
+/// Write one byte range, returning the MD5 the object's ETag stands for, if any
+async fn download_part<S: ObjectStore>(store: &S, bucket: &str, key: &str, e_tag: Option<String>, file_path: &Path, range: ByteRange) -> Result<Option<String>, DownloadError> {
+  let request = GetObject::new(bucket, key).range(Some(range)).if_match(e_tag.clone());
+  let res = store.get_object(request).await?;
+
+  if let (Some(expected), Some(actual)) = (&e_tag, &res.e_tag) {
+    if expected != actual {
+      return Err(DownloadError::ETagMismatch { key: key.to_string(), expected: expected.clone(), actual: actual.to_string() });
+    }
+  }
+  let expected_md5 = expected_md5(&res);
+
+  let mut file = OpenOptions::new().write(true).open(file_path).await?;
+  file.seek(SeekFrom::Start(range.start)).await?;
+
+  let mut body = res.body;
+  let mut written = 0;
+  while let Some(bytes) = body.try_next().await? {
+    file.write_all(&bytes).await?;
+    written += bytes.len() as u64;
+  }
+  file.sync_all().await?;
+
+  let expected = range.end - range.start + 1;
+  if written != expected {
+    return Err(DownloadError::SizeMismatch { key: format!("{} bytes {}-{}", key, range.start, range.end), expected, actual: written });
+  }
+
+  Ok(expected_md5)
+}
+
+/// The MD5 the response's ETag stands for. ETags of objects encrypted with KMS or a customer key aren't digests
+fn expected_md5(res: &ObjectBody) -> Option<String> {
+  if res.key_encrypted {
+    return None;
+  }
+
+  res.e_tag.as_deref().and_then(etag_md5)
+}
+
+fn verify(key: &str, size: u64, written: u64, expected_md5: Option<String>, md5: &str) -> Result<(), DownloadError> {
+  if written != size {
+    return Err(DownloadError::SizeMismatch { key: key.to_string(), expected: size, actual: written });
+  }
+  if let Some(expected) = expected_md5 {
+    if expected != md5 {
+      return Err(DownloadError::ChecksumMismatch { key: key.to_string(), expected, actual: md5.to_string() });
+    }
+  }
+
+  Ok(())
+}
+
+async fn prepare_path(dir: &Path, key: &str) -> Result<PathBuf, DownloadError> {
+  if !dir.is_dir() {
+    return Err(DownloadError::InvalidDirectory(dir.to_path_buf()));
+  }
+
+  let file_path = dir.join(key);
+  let parent_dir = file_path.parent().ok_or_else(|| DownloadError::InvalidDirectory(file_path.clone()))?;
+  fs::create_dir_all(parent_dir).await?;
+
+  Ok(file_path)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::atomic::{AtomicU32, Ordering}, time::Duration};
+
+  use aws_sdk_s3::types::Bucket;
+  use tempfile::TempDir;
+
+  use super::*;
+  use crate::s3::{errors::RequestError, store::{memory::MemoryStore, ObjectListing}};
+
+  /// Flips a byte of the first `corrupt` responses while keeping their length and ETag
+  #[derive(Clone)]
+  struct CorruptingStore {
+    inner: MemoryStore,
+    corrupt: Arc<AtomicU32>,
+  }
+
+  impl ObjectStore for CorruptingStore {
+    async fn list_buckets(&self) -> Result<Vec<Bucket>, RequestError> {
+      self.inner.list_buckets().await
+    }
+
+    async fn list_objects(&self, bucket: &str, prefix: &str, delimiter: Option<&str>) -> Result<ObjectListing, RequestError> {
+      self.inner.list_objects(bucket, prefix, delimiter).await
+    }
+
+    async fn get_object(&self, request: GetObject) -> Result<ObjectBody, RequestError> {
+      let res = self.inner.get_object(request).await?;
+      if self.corrupt.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1)).is_err() {
+        return Ok(res);
+      }
+
+      let mut data = res.body.collect().await.unwrap().into_bytes().to_vec();
+      data[0] ^= 1;
+      Ok(ObjectBody { body: ByteStream::from(data), ..res })
+    }
+
+    async fn head_object(&self, bucket: &str, key: &str) -> Result<Object, RequestError> {
+      self.inner.head_object(bucket, key).await
+    }
+  }
+
+  fn ranged_options(max_attempts: u32) -> DownloadOptions {
+    DownloadOptions {
+      retry: RetryPolicy { max_attempts, base_delay: Duration::ZERO, max_delay: Duration::ZERO },
+      multipart_threshold: 4,
+      part_size: 4,
+      part_concurrency: 2,
+    }
+  }
+
+  async fn corrupted_download(corrupt: u32, max_attempts: u32) -> (TempDir, Result<String, (DownloadError, u32)>) {
+    let inner = MemoryStore::new();
+    inner.put_object("logs", "a.log", "0123456789");
+    let store = CorruptingStore { inner, corrupt: Arc::new(AtomicU32::new(corrupt)) };
+    let object = store.head_object("logs", "a.log").await.unwrap();
+    let dir = TempDir::new().unwrap();
+
+    let result = download_object(&store, "logs", &object, dir.path(), &ranged_options(max_attempts)).await;
+    (dir, result)
+  }
+
+  #[tokio::test]
+  async fn downloads_a_ranged_object_again_when_the_complete_file_is_corrupt() {
+    let (dir, result) = corrupted_download(1, 3).await;
+
+    assert!(result.is_ok());
+    assert_eq!(std::fs::read_to_string(dir.path().join("a.log")).unwrap(), "0123456789");
+  }
+
+  #[tokio::test]
+  async fn reports_every_attempt_at_a_ranged_object_that_stays_corrupt() {
+    let (dir, result) = corrupted_download(u32::MAX, 3).await;
+
+    let (e, attempts) = result.unwrap_err();
+    assert!(matches!(e, DownloadError::ChecksumMismatch { .. }), "{e}");
+    assert_eq!(attempts, 3);
+    assert!(!dir.path().join("a.log").exists());
+    assert!(!temp_path(&dir.path().join("a.log")).exists());
+  }
+}
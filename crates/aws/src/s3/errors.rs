@@ -1,6 +1,6 @@
 use thiserror::Error;
-use aws_sdk_s3::{self, error::SdkError, operation::list_buckets::ListBucketsError};
-use std::io::Error as IoError;
+use aws_sdk_s3::{self, error::{ProvideErrorMetadata, SdkError}, operation::{get_object::GetObjectError, head_object::HeadObjectError, list_buckets::ListBucketsError, list_objects_v2::ListObjectsV2Error}, primitives::ByteStreamError};
+use std::{io::Error as IoError, path::PathBuf};
 
 #[derive(Error, Debug)]
 pub enum BucketsError {
@@ -8,6 +8,139 @@ pub enum BucketsError {
   SaveBucketsError(IoError),
   #[error("Failed to create buckets data directory")]
   DirectoryCreationError(IoError),
-  #[error("Failed to get buckets")]
-  ListBucketsError(SdkError<ListBucketsError>)
+  #[error("Failed to get buckets: {0}")]
+  ListBucketsError(RequestError),
+  #[error("Failed to load buckets from file: {0}")]
+  LoadBucketsError(IoError),
+  #[error("Failed to parse buckets file: {0}")]
+  ParseBucketsError(toml::de::Error),
+  #[error("Failed to serialize buckets: {0}")]
+  SerializeBucketsError(toml::ser::Error),
+}
+
+/// A request to an object store that failed
+#[derive(Error, Debug)]
+pub enum RequestError {
+  #[error("ListBuckets failed: {0}")]
+  ListBuckets(Box<SdkError<ListBucketsError>>),
+  #[error("ListObjectsV2 failed: {0}")]
+  ListObjects(Box<SdkError<ListObjectsV2Error>>),
+  #[error("GetObject failed: {0}")]
+  GetObject(Box<SdkError<GetObjectError>>),
+  #[error("HeadObject failed: {0}")]
+  HeadObject(Box<SdkError<HeadObjectError>>),
+  #[error("No such bucket: {0}")]
+  NoSuchBucket(String),
+  #[error("No such key: {0}")]
+  NoSuchKey(String),
+  #[error("ETag of {0} doesn't match")]
+  PreconditionFailed(String),
+  #[error("Range not satisfiable for {0}")]
+  InvalidRange(String),
+}
+
+impl RequestError {
+  /// True for throttling, server errors, timeouts and dropped connections, which are worth retrying
+  pub fn is_retryable(&self) -> bool {
+    match self {
+      RequestError::ListBuckets(e) => is_retryable_sdk_error(e),
+      RequestError::ListObjects(e) => is_retryable_sdk_error(e),
+      RequestError::GetObject(e) => is_retryable_sdk_error(e),
+      RequestError::HeadObject(e) => is_retryable_sdk_error(e),
+      RequestError::NoSuchBucket(_) | RequestError::NoSuchKey(_) | RequestError::PreconditionFailed(_) | RequestError::InvalidRange(_) => false,
+    }
+  }
+}
+
+fn is_retryable_sdk_error<E: ProvideErrorMetadata>(e: &SdkError<E>) -> bool {
+  match e {
+    SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => true,
+    SdkError::ServiceError(service_error) => {
+      let status = service_error.raw().status().as_u16();
+      let code = service_error.err().code().unwrap_or_default();
+      status == 429 || status >= 500 || RETRYABLE_CODES.contains(&code)
+    }
+    _ => false,
+  }
+}
+
+#[derive(Error, Debug)]
+pub enum DownloadError {
+  #[error("Path {0} is not a directory")]
+  InvalidDirectory(PathBuf),
+  #[error(transparent)]
+  RequestError(#[from] RequestError),
+  #[error("Download interrupted: {0}")]
+  BodyError(#[from] ByteStreamError),
+  #[error("Failed to write file: {0}")]
+  WriteError(#[from] IoError),
+  #[error("{key} is {actual} bytes, expected {expected}")]
+  SizeMismatch { key: String, expected: u64, actual: u64 },
+  #[error("{key} has MD5 {actual}, expected {expected}")]
+  ChecksumMismatch { key: String, expected: String, actual: String },
+  #[error("{key} changed during download, ETag {actual} doesn't match {expected}")]
+  ETagMismatch { key: String, expected: String, actual: String },
+  /// A byte range that failed after being retried on its own
+  #[error("{source}")]
+  PartFailed { source: Box<DownloadError>, attempts: u32 },
+}
+
+impl DownloadError {
+  /// True for requests that are worth retrying, see [`RequestError::is_retryable`], and for a body
+  /// that was cut short, came out the wrong size or failed its MD5, since fetching it again
+  /// usually gets it right. An object whose ETag changed mid download isn't retried, as it's no
+  /// longer the object that was listed, and nor are local write errors or a part already retried
+  pub fn is_retryable(&self) -> bool {
+    match self {
+      DownloadError::RequestError(e) => e.is_retryable(),
+      DownloadError::BodyError(_) | DownloadError::SizeMismatch { .. } | DownloadError::ChecksumMismatch { .. } => true,
+      DownloadError::InvalidDirectory(_) | DownloadError::WriteError(_) | DownloadError::ETagMismatch { .. } | DownloadError::PartFailed { .. } => false,
+    }
+  }
+}
+
+const RETRYABLE_CODES: [&str; 5] = ["SlowDown", "Throttling", "ThrottlingException", "RequestTimeout", "InternalError"];
+
+#[cfg(test)]
+mod tests {
+  use std::io;
+
+  use aws_sdk_s3::error::ErrorMetadata;
+  use aws_smithy_runtime_api::http::{Response, StatusCode};
+  use aws_smithy_types::body::SdkBody;
+
+  use super::*;
+
+  fn service_error(status: u16, code: &str) -> DownloadError {
+    let error = GetObjectError::generic(ErrorMetadata::builder().code(code).build());
+    let response = Response::new(StatusCode::try_from(status).unwrap(), SdkBody::empty());
+    RequestError::GetObject(Box::new(SdkError::service_error(error, response))).into()
+  }
+
+  #[test]
+  fn retries_throttling_and_server_errors() {
+    assert!(service_error(503, "ServiceUnavailable").is_retryable());
+    assert!(service_error(429, "TooManyRequests").is_retryable());
+    assert!(service_error(400, "RequestTimeout").is_retryable());
+    assert!(!service_error(403, "AccessDenied").is_retryable());
+  }
+
+  #[test]
+  fn retries_timeouts_but_not_requests_that_were_never_sent() {
+    let timeout = RequestError::GetObject(Box::new(SdkError::timeout_error("timed out")));
+    let unbuildable = RequestError::GetObject(Box::new(SdkError::construction_failure("bad request")));
+
+    assert!(DownloadError::from(timeout).is_retryable());
+    assert!(!DownloadError::from(unbuildable).is_retryable());
+  }
+
+  #[test]
+  fn retries_corrupt_downloads_but_not_changed_objects_or_local_failures() {
+    assert!(DownloadError::SizeMismatch { key: "a".to_string(), expected: 2, actual: 1 }.is_retryable());
+    assert!(DownloadError::ChecksumMismatch { key: "a".to_string(), expected: "x".to_string(), actual: "y".to_string() }.is_retryable());
+    assert!(!DownloadError::ETagMismatch { key: "a".to_string(), expected: "x".to_string(), actual: "y".to_string() }.is_retryable());
+    assert!(!DownloadError::from(RequestError::NoSuchKey("a".to_string())).is_retryable());
+    assert!(!DownloadError::from(RequestError::PreconditionFailed("a".to_string())).is_retryable());
+    assert!(!DownloadError::WriteError(io::Error::other("disk full")).is_retryable());
+  }
 }
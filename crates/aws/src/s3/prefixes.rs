@@ -0,0 +1,53 @@
+use anyhow::Result;
+use aws_sdk_s3::types::Object;
+use log::debug;
+
+use super::{store::ObjectStore, DELIMITER};
+
+/// Everything directly beneath a prefix: its sub folders and the objects stored at that level
+#[derive(Debug, Default)]
+pub struct PrefixListing {
+  pub prefix: String,
+  pub folders: Vec<String>,
+  pub objects: Vec<Object>,
+}
+
+impl PrefixListing {
+  /// Folder names relative to the listed prefix, without the trailing delimiter
+  pub fn folder_names(&self) -> Vec<String> {
+    self.folders.iter()
+      .map(|folder| folder.strip_prefix(self.prefix.as_str()).unwrap_or(folder).trim_end_matches(DELIMITER).to_string())
+      .collect()
+  }
+}
+
+/// List one level of a bucket using `/` as the delimiter
+pub async fn list_prefix<S: ObjectStore>(store: &S, bucket: &str, prefix: &str) -> Result<PrefixListing> {
+  let output = store.list_objects(bucket, prefix, Some(DELIMITER)).await?;
+
+  let listing = PrefixListing {
+    prefix: prefix.to_string(),
+    folders: output.folders,
+    objects: output.objects,
+  };
+
+  debug!("Listed {} folders and {} objects under {}", listing.folders.len(), listing.objects.len(), prefix);
+
+  Ok(listing)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn strips_the_listed_prefix_once() {
+    let listing = PrefixListing {
+      prefix: "logs/".to_string(),
+      folders: vec!["logs/logs/".to_string(), "logs/api/".to_string()],
+      objects: Vec::new(),
+    };
+
+    assert_eq!(listing.folder_names(), ["logs", "api"]);
+  }
+}
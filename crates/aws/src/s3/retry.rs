@@ -0,0 +1,111 @@
+use std::{future::Future, time::Duration};
+
+use log::warn;
+
+use super::errors::DownloadError;
+
+/// How often and how patiently to retry a failed request. The application config holds the defaults
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+  /// Attempts in total, including the first
+  pub max_attempts: u32,
+  pub base_delay: Duration,
+  pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+  /// Exponential backoff with full jitter: a random delay up to `base_delay * 2^attempt`, capped at `max_delay`
+  pub fn delay(&self, attempt: u32) -> Duration {
+    let ceiling = self.base_delay
+      .saturating_mul(2u32.saturating_pow(attempt))
+      .min(self.max_delay);
+
+    ceiling.mul_f64(fastrand::f64())
+  }
+
+  /// Run `operation` until it succeeds, fails with an error that isn't retryable or runs out of attempts.
+  /// Returns the error of the last attempt along with the number of attempts made
+  pub async fn run<T, F, Fut>(&self, mut operation: F) -> Result<T, (DownloadError, u32)>
+  where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, DownloadError>>,
+  {
+    let mut attempt = 0;
+    loop {
+      attempt += 1;
+      match operation().await {
+        Ok(value) => return Ok(value),
+        Err(e) if e.is_retryable() && attempt < self.max_attempts => {
+          let delay = self.delay(attempt - 1);
+          warn!("Attempt {} of {} failed, retrying in {:?}: {}", attempt, self.max_attempts, delay, e);
+          tokio::time::sleep(delay).await;
+        }
+        Err(e) => return Err((e, attempt)),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicU32, Ordering};
+
+  use super::*;
+
+  fn policy(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy { max_attempts, base_delay: Duration::ZERO, max_delay: Duration::ZERO }
+  }
+
+  fn size_mismatch() -> DownloadError {
+    DownloadError::SizeMismatch { key: "a.log".to_string(), expected: 2, actual: 1 }
+  }
+
+  #[test]
+  fn delay_is_capped_by_the_backoff_and_the_maximum() {
+    let policy = RetryPolicy { max_attempts: 5, base_delay: Duration::from_millis(100), max_delay: Duration::from_secs(1) };
+
+    for _ in 0..100 {
+      assert!(policy.delay(0) <= Duration::from_millis(100));
+      assert!(policy.delay(2) <= Duration::from_millis(400));
+      assert!(policy.delay(10) <= Duration::from_secs(1));
+      assert!(policy.delay(u32::MAX) <= Duration::from_secs(1), "huge attempts saturate");
+    }
+  }
+
+  #[tokio::test]
+  async fn run_retries_until_it_succeeds() {
+    let calls = AtomicU32::new(0);
+
+    let result = policy(5).run(|| async {
+      match calls.fetch_add(1, Ordering::SeqCst) {
+        0 | 1 => Err(size_mismatch()),
+        _ => Ok("done"),
+      }
+    }).await;
+
+    assert_eq!(result.unwrap(), "done");
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+  }
+
+  #[tokio::test]
+  async fn run_reports_the_attempts_made_when_it_gives_up() {
+    let result = policy(3).run(|| async { Err::<(), _>(size_mismatch()) }).await;
+
+    let (e, attempts) = result.unwrap_err();
+    assert!(matches!(e, DownloadError::SizeMismatch { .. }));
+    assert_eq!(attempts, 3);
+  }
+
+  #[tokio::test]
+  async fn run_stops_at_an_error_that_isnt_retryable() {
+    let calls = AtomicU32::new(0);
+
+    let result = policy(5).run(|| async {
+      calls.fetch_add(1, Ordering::SeqCst);
+      Err::<(), _>(DownloadError::InvalidDirectory("logs".into()))
+    }).await;
+
+    assert_eq!(result.unwrap_err().1, 1);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+  }
+}
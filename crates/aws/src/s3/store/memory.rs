@@ -0,0 +1,216 @@
+use std::{collections::{BTreeMap, BTreeSet}, sync::{Arc, RwLock}};
+
+use aws_sdk_s3::{primitives::{ByteStream, DateTime}, types::{Bucket, Object}};
+use md5::{Digest, Md5};
+
+use super::{GetObject, ObjectBody, ObjectListing, ObjectStore};
+use crate::s3::{checksum::to_hex, errors::RequestError};
+
+/// Buckets and objects held in memory, for running against a fake S3 in tests
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+  buckets: Arc<RwLock<BTreeMap<String, BTreeMap<String, StoredObject>>>>,
+}
+
+#[derive(Debug, Clone)]
+struct StoredObject {
+  data: Arc<Vec<u8>>,
+  e_tag: String,
+  last_modified: DateTime,
+}
+
+impl MemoryStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Add an empty bucket, leaving it untouched if it already exists
+  pub fn create_bucket(&self, bucket: &str) {
+    self.buckets.write().unwrap().entry(bucket.to_string()).or_default();
+  }
+
+  /// Store an object modified now, creating its bucket if needed. Returns its ETag
+  pub fn put_object(&self, bucket: &str, key: &str, data: impl Into<Vec<u8>>) -> String {
+    self.put_object_modified(bucket, key, data, DateTime::from(std::time::SystemTime::now()))
+  }
+
+  /// Store an object with the given modified time, creating its bucket if needed. Returns its
+  /// ETag, the quoted MD5 of the data as S3 gives for single part uploads
+  pub fn put_object_modified(&self, bucket: &str, key: &str, data: impl Into<Vec<u8>>, last_modified: DateTime) -> String {
+    let data = data.into();
+    let e_tag = format!("\"{}\"", to_hex(&Md5::digest(&data)));
+
+    let object = StoredObject { data: Arc::new(data), e_tag: e_tag.clone(), last_modified };
+    self.buckets.write().unwrap()
+      .entry(bucket.to_string())
+      .or_default()
+      .insert(key.to_string(), object);
+
+    e_tag
+  }
+
+  pub fn delete_object(&self, bucket: &str, key: &str) {
+    if let Some(objects) = self.buckets.write().unwrap().get_mut(bucket) {
+      objects.remove(key);
+    }
+  }
+
+  fn stored(&self, bucket: &str, key: &str) -> Result<StoredObject, RequestError> {
+    let buckets = self.buckets.read().unwrap();
+    let objects = buckets.get(bucket).ok_or_else(|| RequestError::NoSuchBucket(bucket.to_string()))?;
+
+    objects.get(key).cloned().ok_or_else(|| RequestError::NoSuchKey(key.to_string()))
+  }
+}
+
+impl ObjectStore for MemoryStore {
+  async fn list_buckets(&self) -> Result<Vec<Bucket>, RequestError> {
+    let buckets = self.buckets.read().unwrap();
+
+    Ok(buckets.keys().map(|name| Bucket::builder().name(name).creation_date(DateTime::from_secs(0)).build()).collect())
+  }
+
+  async fn list_objects(&self, bucket: &str, prefix: &str, delimiter: Option<&str>) -> Result<ObjectListing, RequestError> {
+    let buckets = self.buckets.read().unwrap();
+    let objects = buckets.get(bucket).ok_or_else(|| RequestError::NoSuchBucket(bucket.to_string()))?;
+
+    let mut listing = ObjectListing::default();
+    let mut folders = BTreeSet::new();
+    for (key, object) in objects.range(prefix.to_string()..).take_while(|(key, _)| key.starts_with(prefix)) {
+      let rest = &key[prefix.len()..];
+      match delimiter.filter(|delimiter| !delimiter.is_empty()).and_then(|delimiter| rest.find(delimiter).map(|index| index + delimiter.len())) {
+        Some(end) => {
+          folders.insert(format!("{}{}", prefix, &rest[..end]));
+        }
+        None => listing.objects.push(to_object(key, object)),
+      }
+    }
+    listing.folders = folders.into_iter().collect();
+
+    Ok(listing)
+  }
+
+  async fn get_object(&self, request: GetObject) -> Result<ObjectBody, RequestError> {
+    let object = self.stored(&request.bucket, &request.key)?;
+    if request.if_match.as_ref().is_some_and(|e_tag| *e_tag != object.e_tag) {
+      return Err(RequestError::PreconditionFailed(request.key));
+    }
+
+    let data = match request.range {
+      Some(range) => {
+        let len = object.data.len() as u64;
+        if range.start >= len || range.start > range.end {
+          return Err(RequestError::InvalidRange(request.key));
+        }
+        let end = range.end.min(len - 1);
+        object.data[range.start as usize..=end as usize].to_vec()
+      }
+      None => object.data.to_vec(),
+    };
+
+    Ok(ObjectBody { body: ByteStream::from(data), e_tag: Some(object.e_tag), key_encrypted: false })
+  }
+
+  async fn head_object(&self, bucket: &str, key: &str) -> Result<Object, RequestError> {
+    let object = self.stored(bucket, key)?;
+
+    Ok(to_object(key, &object))
+  }
+}
+
+fn to_object(key: &str, object: &StoredObject) -> Object {
+  Object::builder()
+    .key(key)
+    .size(object.data.len() as i64)
+    .e_tag(&object.e_tag)
+    .last_modified(object.last_modified)
+    .build()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::s3::store::ByteRange;
+
+  fn store() -> MemoryStore {
+    let store = MemoryStore::new();
+    store.put_object("logs", "production/api/2024-05-01/a.log", "a");
+    store.put_object("logs", "production/api/2024-05-02/b.log", "bb");
+    store.put_object("logs", "production/web/2024-05-01/c.log", "ccc");
+    store.put_object("logs", "staging/api/d.log", "dddd");
+    store
+  }
+
+  #[tokio::test]
+  async fn lists_objects_under_a_prefix() {
+    let listing = store().list_objects("logs", "production/", None).await.unwrap();
+
+    let keys: Vec<_> = listing.objects.iter().filter_map(|object| object.key.as_deref()).collect();
+    assert_eq!(keys, ["production/api/2024-05-01/a.log", "production/api/2024-05-02/b.log", "production/web/2024-05-01/c.log"]);
+    assert!(listing.folders.is_empty());
+  }
+
+  #[tokio::test]
+  async fn rolls_keys_up_into_folders_with_a_delimiter() {
+    let store = store();
+    store.put_object("logs", "production/top.log", "top");
+
+    let listing = store.list_objects("logs", "production/", Some("/")).await.unwrap();
+
+    assert_eq!(listing.folders, ["production/api/", "production/web/"]);
+    assert_eq!(listing.objects.len(), 1);
+    assert_eq!(listing.objects[0].key.as_deref(), Some("production/top.log"));
+  }
+
+  #[tokio::test]
+  async fn fails_to_list_a_missing_bucket() {
+    let result = store().list_objects("missing", "", None).await;
+
+    assert!(matches!(result, Err(RequestError::NoSuchBucket(_))));
+  }
+
+  #[tokio::test]
+  async fn reads_a_byte_range() {
+    let store = MemoryStore::new();
+    store.put_object("logs", "a.log", "0123456789");
+
+    let body = store.get_object(GetObject::new("logs", "a.log").range(Some(ByteRange { start: 2, end: 5 }))).await.unwrap();
+    let data = body.body.collect().await.unwrap().into_bytes();
+
+    assert_eq!(&data[..], b"2345");
+  }
+
+  #[tokio::test]
+  async fn clamps_a_range_past_the_end() {
+    let store = MemoryStore::new();
+    store.put_object("logs", "a.log", "0123456789");
+
+    let body = store.get_object(GetObject::new("logs", "a.log").range(Some(ByteRange { start: 8, end: 100 }))).await.unwrap();
+    let data = body.body.collect().await.unwrap().into_bytes();
+
+    assert_eq!(&data[..], b"89");
+  }
+
+  #[tokio::test]
+  async fn fails_a_get_when_the_etag_changed() {
+    let store = MemoryStore::new();
+    let e_tag = store.put_object("logs", "a.log", "old");
+    store.put_object("logs", "a.log", "new");
+
+    let result = store.get_object(GetObject::new("logs", "a.log").if_match(Some(e_tag))).await;
+
+    assert!(matches!(result, Err(RequestError::PreconditionFailed(_))));
+  }
+
+  #[tokio::test]
+  async fn heads_an_object() {
+    let store = MemoryStore::new();
+    let e_tag = store.put_object("logs", "a.log", "hello");
+
+    let object = store.head_object("logs", "a.log").await.unwrap();
+
+    assert_eq!(object.size, Some(5));
+    assert_eq!(object.e_tag, Some(e_tag));
+    assert_eq!(object.e_tag.as_deref(), Some("\"5d41402abc4b2a76b9719d911017c592\""));
+  }
+}
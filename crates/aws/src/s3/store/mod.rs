@@ -0,0 +1,78 @@
+use std::future::Future;
+
+use aws_sdk_s3::{primitives::ByteStream, types::{Bucket, Object}};
+
+use super::errors::RequestError;
+
+pub mod memory;
+pub mod sdk;
+
+/// Objects beneath a prefix, and the folders one level down when listed with a delimiter
+#[derive(Debug, Default)]
+pub struct ObjectListing {
+  pub objects: Vec<Object>,
+  /// Common prefixes, each ending in the delimiter
+  pub folders: Vec<String>,
+}
+
+/// Inclusive byte range of an object, as in an HTTP `Range` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+  pub start: u64,
+  pub end: u64,
+}
+
+/// What to read from an object
+#[derive(Debug, Clone, Default)]
+pub struct GetObject {
+  pub bucket: String,
+  pub key: String,
+  /// Only part of the object, the whole object when not set
+  pub range: Option<ByteRange>,
+  /// Fail with `PreconditionFailed` unless the object still has this ETag
+  pub if_match: Option<String>,
+}
+
+impl GetObject {
+  pub fn new(bucket: &str, key: &str) -> Self {
+    Self { bucket: bucket.to_string(), key: key.to_string(), ..Default::default() }
+  }
+
+  pub fn range(mut self, range: Option<ByteRange>) -> Self {
+    self.range = range;
+    self
+  }
+
+  pub fn if_match(mut self, e_tag: Option<String>) -> Self {
+    self.if_match = e_tag;
+    self
+  }
+}
+
+/// The body of an object and what's needed to check it
+#[derive(Debug)]
+pub struct ObjectBody {
+  pub body: ByteStream,
+  pub e_tag: Option<String>,
+  /// Encrypted with KMS or a customer provided key, whose ETags aren't an MD5 of the content
+  pub key_encrypted: bool,
+}
+
+/// Where log objects are listed and read from. Implemented for the S3 SDK client, and by
+/// [`memory::MemoryStore`] so everything above it can run without a network.
+///
+/// Cloning is expected to be cheap, as a clone is handed to every concurrent download.
+pub trait ObjectStore: Clone + Send + Sync + 'static {
+  /// Every bucket visible to the store
+  fn list_buckets(&self) -> impl Future<Output = Result<Vec<Bucket>, RequestError>> + Send;
+
+  /// Every object whose key starts with `prefix`. With a delimiter, keys continuing past the next
+  /// delimiter are rolled up into folders instead
+  fn list_objects(&self, bucket: &str, prefix: &str, delimiter: Option<&str>) -> impl Future<Output = Result<ObjectListing, RequestError>> + Send;
+
+  /// Read an object, or a byte range of it
+  fn get_object(&self, request: GetObject) -> impl Future<Output = Result<ObjectBody, RequestError>> + Send;
+
+  /// An object's size, ETag and modified time without reading it
+  fn head_object(&self, bucket: &str, key: &str) -> impl Future<Output = Result<Object, RequestError>> + Send;
+}
@@ -0,0 +1,67 @@
+use aws_sdk_s3::{types::{Bucket, ChecksumMode, Object, ServerSideEncryption}, Client};
+
+use super::{GetObject, ObjectBody, ObjectListing, ObjectStore};
+use crate::s3::errors::RequestError;
+
+impl ObjectStore for Client {
+  async fn list_buckets(&self) -> Result<Vec<Bucket>, RequestError> {
+    let output = self.list_buckets()
+      .send()
+      .await
+      .map_err(|e| RequestError::ListBuckets(Box::new(e)))?;
+
+    Ok(output.buckets.unwrap_or_default())
+  }
+
+  async fn list_objects(&self, bucket: &str, prefix: &str, delimiter: Option<&str>) -> Result<ObjectListing, RequestError> {
+    let req = self.list_objects_v2()
+      .bucket(bucket)
+      .prefix(prefix)
+      .set_delimiter(delimiter.map(str::to_string))
+      .max_keys(1000);
+    let mut pages = req.into_paginator().send();
+
+    let mut listing = ObjectListing::default();
+    while let Some(page) = pages.next().await {
+      let output = page.map_err(|e| RequestError::ListObjects(Box::new(e)))?;
+      listing.folders.extend(output.common_prefixes().iter().filter_map(|folder| folder.prefix.clone()));
+      listing.objects.extend(output.contents().iter().cloned());
+    }
+
+    Ok(listing)
+  }
+
+  async fn get_object(&self, request: GetObject) -> Result<ObjectBody, RequestError> {
+    let res = self.get_object()
+      .bucket(request.bucket)
+      .key(request.key)
+      .set_range(request.range.map(|range| format!("bytes={}-{}", range.start, range.end)))
+      .set_if_match(request.if_match)
+      // the SDK validates whole object bodies against any additional checksum stored with the object
+      .checksum_mode(ChecksumMode::Enabled)
+      .send()
+      .await
+      .map_err(|e| RequestError::GetObject(Box::new(e)))?;
+
+    let kms_encrypted = matches!(res.server_side_encryption(), Some(ServerSideEncryption::AwsKms | ServerSideEncryption::AwsKmsDsse));
+    let key_encrypted = kms_encrypted || res.sse_customer_algorithm().is_some();
+
+    Ok(ObjectBody { e_tag: res.e_tag, key_encrypted, body: res.body })
+  }
+
+  async fn head_object(&self, bucket: &str, key: &str) -> Result<Object, RequestError> {
+    let res = self.head_object()
+      .bucket(bucket)
+      .key(key)
+      .send()
+      .await
+      .map_err(|e| RequestError::HeadObject(Box::new(e)))?;
+
+    Ok(Object::builder()
+      .key(key)
+      .set_size(res.content_length)
+      .set_e_tag(res.e_tag)
+      .set_last_modified(res.last_modified)
+      .build())
+  }
+}